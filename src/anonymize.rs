@@ -0,0 +1,83 @@
+//! Pseudonymization of `Person` trees, for sharing tree shapes without names
+//! or exact ages.
+//!
+//! Callers are responsible for calling `Person::mark_changed` on the root
+//! after a successful edit.
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::Person;
+
+/// Number of digest bytes kept in a pseudonym.
+const PSEUDONYM_BYTES: usize = 6;
+
+/// Derives the pseudonym of `name` under `seed`.
+///
+/// The pseudonym is `anon-` followed by the first bytes of
+/// `SHA-256(seed as 8 little-endian bytes || name)` in hex. It depends only on
+/// the seed and the name, so equal names always get equal pseudonyms, while
+/// the same name gets unrelated pseudonyms under different seeds.
+pub fn pseudonym(name: &str, seed: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.to_le_bytes());
+    hasher.update(name.as_bytes());
+    let digest = hasher.finalize();
+    let hex: String = digest[..PSEUDONYM_BYTES]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("anon-{}", hex)
+}
+
+/// Returns a function rounding an age down to a multiple of `size`, or a
+/// `ValueError` naming `argument` if `size` is 0.
+fn age_rounder(size: u32, argument: &str) -> PyResult<impl Fn(u32) -> u32> {
+    if size == 0 {
+        return Err(PyValueError::new_err(format!(
+            "{} must be positive",
+            argument
+        )));
+    }
+    Ok(move |age: u32| age - age % size)
+}
+
+/// Replaces every name in the tree with its pseudonym and, if `age_bucket` is
+/// given, rounds every age down to a multiple of it.
+///
+/// The bucket is validated before anything is changed, so on error the tree
+/// is left untouched.
+pub fn anonymize(root: &mut Person, seed: u64, age_bucket: Option<u32>) -> PyResult<()> {
+    let round = age_bucket
+        .map(|size| age_rounder(size, "age_bucket"))
+        .transpose()?;
+
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        node.name = pseudonym(&node.name, seed);
+        if let Some(round) = &round {
+            node.age = round(node.age);
+        }
+        stack.extend(node.children.iter_mut());
+    }
+    Ok(())
+}
+
+/// Rounds every age in the tree down to a multiple of `bucket_size`.
+///
+/// Each age becomes the lower bound of its bucket, so with a size of 10 the
+/// ages 30 through 39 all become 30 and 9 becomes 0. Ages are never rounded
+/// up. A size of 1 leaves the tree unchanged; a size of 0 is rejected before
+/// anything is changed.
+pub fn bucketize_ages(root: &mut Person, bucket_size: u32) -> PyResult<()> {
+    let round = age_rounder(bucket_size, "bucket_size")?;
+
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        node.age = round(node.age);
+        stack.extend(node.children.iter_mut());
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Per-node bookkeeping for values derived from a `Person` tree.
+//!
+//! Every `Person` carries a [`NodeState`] holding a mutation version, caches
+//! that are only valid for the version they were computed at, and an optional
+//! change observer. The state is not part of a person's value: it is ignored by
+//! equality and `Debug`, and a clone starts out with empty caches and no
+//! observer.
+//!
+//! Python can only reach a nested node through a copy (the `children` getter
+//! clones), so every in-place change to a tree goes through a method on the
+//! root, which bumps the root's version.
+
+use std::sync::Mutex;
+
+use pyo3::Py;
+use pyo3::PyAny;
+use pyo3::Python;
+use pyo3::types::PyDict;
+
+use crate::hash::ContentDigest;
+use crate::stats::Shape;
+
+#[derive(Debug, Default)]
+pub struct NodeState {
+    /// Incremented on every mutation made through the Python API.
+    version: u64,
+    /// The last memoized `to_dict` result, with the version it was built at.
+    dict: Mutex<Option<(u64, Py<PyDict>)>>,
+    /// The last computed content hash, with the version it was computed at.
+    hash: Mutex<Option<(u64, ContentDigest)>>,
+    /// The last computed depth and descendant count, with the version they
+    /// were computed at.
+    shape: Mutex<Option<(u64, Shape)>>,
+    /// The callable set with `Person.set_on_change`, if any.
+    observer: Option<Py<PyAny>>,
+}
+
+impl NodeState {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Records a mutation, invalidating every cached value.
+    pub fn bump(&mut self) {
+        self.version = self.version.wrapping_add(1);
+    }
+
+    /// Returns the memoized dict if it was built at the current version.
+    pub fn cached_dict(&self, py: Python<'_>) -> Option<Py<PyDict>> {
+        match &*self.dict.lock().unwrap() {
+            Some((version, dict)) if *version == self.version => Some(dict.clone_ref(py)),
+            _ => None,
+        }
+    }
+
+    /// Memoizes `dict` as the dict form at the current version.
+    pub fn store_dict(&self, py: Python<'_>, dict: &Py<PyDict>) {
+        *self.dict.lock().unwrap() = Some((self.version, dict.clone_ref(py)));
+    }
+
+    /// Returns the content hash if it was computed at the current version.
+    pub fn cached_hash(&self) -> Option<ContentDigest> {
+        match *self.hash.lock().unwrap() {
+            Some((version, digest)) if version == self.version => Some(digest),
+            _ => None,
+        }
+    }
+
+    /// Memoizes `digest` as the content hash at the current version.
+    pub fn store_hash(&self, digest: ContentDigest) {
+        *self.hash.lock().unwrap() = Some((self.version, digest));
+    }
+
+    /// Returns the shape if it was computed at the current version.
+    pub fn cached_shape(&self) -> Option<Shape> {
+        match *self.shape.lock().unwrap() {
+            Some((version, shape)) if version == self.version => Some(shape),
+            _ => None,
+        }
+    }
+
+    /// Memoizes `shape` as the shape at the current version.
+    pub fn store_shape(&self, shape: Shape) {
+        *self.shape.lock().unwrap() = Some((self.version, shape));
+    }
+
+    /// Returns a new reference to the change observer, if one is set.
+    pub fn observer(&self, py: Python<'_>) -> Option<Py<PyAny>> {
+        self.observer
+            .as_ref()
+            .map(|observer| observer.clone_ref(py))
+    }
+
+    /// The change observer, for garbage collector traversal.
+    pub fn observer_ref(&self) -> Option<&Py<PyAny>> {
+        self.observer.as_ref()
+    }
+
+    /// Sets or, with `None`, removes the change observer.
+    pub fn set_observer(&mut self, observer: Option<Py<PyAny>>) {
+        self.observer = observer;
+    }
+}
+
+impl Clone for NodeState {
+    fn clone(&self) -> Self {
+        NodeState {
+            version: self.version,
+            dict: Mutex::default(),
+            hash: Mutex::default(),
+            shape: Mutex::default(),
+            observer: None,
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! A compact binary encoding for `Person` trees.
+//!
+//! The format is meant for constrained links where every byte counts. It has no
+//! field names and no type tags, so it is considerably smaller than msgpack for
+//! typical trees.
+//!
+//! # Byte layout
+//!
+//! A tree is encoded as its nodes in pre-order (a node, then each of its children
+//! in order). Every node is laid out as:
+//!
+//! | Field         | Encoding                                  |
+//! |---------------|-------------------------------------------|
+//! | name length   | unsigned LEB128 varint, in bytes          |
+//! | name          | that many bytes of UTF-8, no terminator   |
+//! | age           | unsigned LEB128 varint, at most `u32::MAX` |
+//! | child count   | unsigned LEB128 varint                    |
+//!
+//! The node's children follow immediately after its child count. There is no
+//! header or trailer, and a payload holds exactly one root: trailing bytes after
+//! the root is complete are rejected.
+//!
+//! Unsigned LEB128 stores seven bits per byte, least significant group first, and
+//! sets the high bit of every byte except the last. For example `30` is `0x1e` and
+//! `300` is `0xac 0x02`.
+
+use pyo3::PyResult;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+
+/// Encodes a person tree into the compact binary format.
+pub fn encode(person: &Person) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut stack = vec![person];
+
+    while let Some(node) = stack.pop() {
+        write_varint(&mut out, node.name.len() as u64);
+        out.extend_from_slice(node.name.as_bytes());
+        write_varint(&mut out, u64::from(node.age));
+        write_varint(&mut out, node.children.len() as u64);
+
+        // Push in reverse so the first child is encoded first.
+        stack.extend(node.children.iter().rev());
+    }
+
+    out
+}
+
+/// Decodes a person tree from the compact binary format.
+///
+/// Returns a `PersonError` if the payload is truncated, malformed, or has
+/// trailing bytes after the root node.
+pub fn decode(bytes: &[u8]) -> PyResult<Person> {
+    let mut reader = Reader::new(bytes);
+    // Nodes whose children are still being read, with the number still expected.
+    let mut open: Vec<(Person, u64)> = Vec::new();
+
+    loop {
+        let (mut node, mut remaining) = reader.read_node()?;
+
+        // Attach every node that is now complete to its parent.
+        while remaining == 0 {
+            match open.pop() {
+                Some((mut parent, parent_remaining)) => {
+                    parent.children.push(node);
+                    node = parent;
+                    remaining = parent_remaining - 1;
+                }
+                None => {
+                    if reader.pos != bytes.len() {
+                        return Err(ErrorCode::Malformed.err(format!(
+                            "Unexpected trailing data at byte {} of compact payload",
+                            reader.pos
+                        )));
+                    }
+                    return Ok(node);
+                }
+            }
+        }
+
+        open.push((node, remaining));
+    }
+}
+
+/// Appends `value` as an unsigned LEB128 varint.
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// A cursor over LEB128-based binary data, shared with the patch format.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    /// The offset of the next unread byte.
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Whether every byte has been read.
+    pub(crate) fn is_at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    /// Reads the next `len` bytes.
+    pub(crate) fn read_bytes(&mut self, len: u64) -> PyResult<&'a [u8]> {
+        let start = self.pos;
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| ErrorCode::Malformed.err(format!("Truncated data at byte {}", start)))?;
+        self.pos = end;
+        Ok(&self.bytes[start..end])
+    }
+
+    /// Reads one node header, returning the childless node and its child count.
+    fn read_node(&mut self) -> PyResult<(Person, u64)> {
+        let name_len = self.read_varint()?;
+        let name = self.read_str(name_len)?;
+        let age = u32::try_from(self.read_varint()?).map_err(|_| {
+            ErrorCode::InvalidAge.err(format!("Age out of range at byte {}", self.pos))
+        })?;
+        let child_count = self.read_varint()?;
+
+        Ok((Person::new(name, age, vec![]), child_count))
+    }
+
+    pub(crate) fn read_varint(&mut self) -> PyResult<u64> {
+        let start = self.pos;
+        let mut value = 0u64;
+        let mut shift = 0;
+
+        loop {
+            let byte = *self.bytes.get(self.pos).ok_or_else(|| {
+                ErrorCode::Malformed.err(format!("Truncated varint at byte {}", start))
+            })?;
+            self.pos += 1;
+
+            if shift == 63 && byte > 1 {
+                return Err(
+                    ErrorCode::Malformed.err(format!("Varint at byte {} overflows 64 bits", start))
+                );
+            }
+            value |= u64::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    pub(crate) fn read_str(&mut self, len: u64) -> PyResult<String> {
+        let start = self.pos;
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| ErrorCode::Malformed.err(format!("Truncated name at byte {}", start)))?;
+        self.pos = end;
+
+        String::from_utf8(self.bytes[start..end].to_vec()).map_err(|_| {
+            ErrorCode::InvalidName.err(format!("Name at byte {} is not valid UTF-8", start))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::PyErr;
+    use pyo3::Python;
+
+    use super::*;
+    use crate::create_balanced_person;
+    use crate::create_chain;
+    use crate::errors::code_of;
+
+    fn message(err: &PyErr) -> String {
+        Python::with_gil(|py| err.value(py).to_string())
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut wide = create_balanced_person(3, 4);
+        wide.name = "ünïcode ".repeat(40);
+        wide.age = u32::MAX;
+        wide.children[2].name = String::new();
+        let chain = create_chain(
+            (0..100_000).map(|i| format!("n{}", i)).collect(),
+            (0..100_000).collect(),
+        )
+        .unwrap();
+        for tree in [Person::new("Ann".to_string(), 30, vec![]), wide, chain] {
+            assert!(decode(&encode(&tree)).unwrap() == tree);
+        }
+
+        let leaf = Person::new("a".to_string(), 300, vec![]);
+        assert_eq!(encode(&leaf), [0x01, b'a', 0xac, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn rejects_truncated_varints() {
+        let leaf = Person::new("a".to_string(), 300, vec![]);
+        let bytes = encode(&leaf);
+        // Cut inside the two-byte age.
+        let err = decode(&bytes[..3]).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+        assert_eq!(message(&err), "Truncated varint at byte 2");
+
+        let err = decode(&[0x80]).unwrap_err();
+        assert_eq!(message(&err), "Truncated varint at byte 0");
+    }
+
+    #[test]
+    fn rejects_varints_over_64_bits() {
+        let mut max = vec![0xff; 9];
+        max.push(0x01);
+        assert_eq!(Reader::new(&max).read_varint().unwrap(), u64::MAX);
+
+        for last in [0x02, 0x81] {
+            let mut bytes = vec![0x00, 0x00];
+            bytes.extend([0xff; 9]);
+            bytes.push(last);
+            let err = decode(&bytes).unwrap_err();
+            assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+            assert_eq!(message(&err), "Varint at byte 2 overflows 64 bits");
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = encode(&create_balanced_person(1, 2));
+        let len = bytes.len();
+        bytes.push(0x00);
+        let err = decode(&bytes).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+        assert_eq!(
+            message(&err),
+            format!(
+                "Unexpected trailing data at byte {} of compact payload",
+                len
+            )
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Copy-on-write `Person` trees for cheap versioned snapshots.
+//!
+//! A `CowPerson` holds its tree as reference-counted nodes whose children are
+//! shared through `Arc`. `snapshot()` copies only the root handle, so keeping
+//! many historical versions, as an editor's undo stack does, costs memory
+//! only for what changed between them.
+//!
+//! # Sharing and aliasing
+//!
+//! Nodes are never modified while shared. An edit through one handle copies
+//! the nodes on the path from the root to the edited node that are shared
+//! with any other handle, and modifies the copies; every subtree off that
+//! path stays shared. So:
+//!
+//! - No edit through a handle is ever visible through another handle, whether
+//!   a snapshot or the handle it was taken from. Handles behave exactly as if
+//!   each held its own deep copy.
+//! - An edit at depth `d` copies at most `d + 1` nodes, each holding only
+//!   its fields and `Arc`s to its children, never a whole subtree. Nodes no
+//!   longer shared are edited in place.
+//! - Converting to and from `Person` copies the whole tree, since `Person`
+//!   owns its children outright. Edits should be made through the handle.
+//!
+//! A failed edit, such as one at a path that leaves the tree, copies nothing
+//! and changes nothing. `shared_nodes` reports how many nodes two handles
+//! physically share.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyResult;
+use pyo3::exceptions::PyIndexError;
+use pyo3::pyclass;
+use pyo3::pymethods;
+
+use crate::Person;
+use crate::names;
+use crate::paths::out_of_range;
+
+/// One node of a copy-on-write tree.
+#[derive(Clone)]
+struct Node {
+    name: String,
+    age: u32,
+    children: Vec<Arc<Node>>,
+}
+
+impl Drop for Node {
+    /// Drops unshared descendants iteratively, so that very deep trees cannot
+    /// overflow the stack; shared ones only lose a reference.
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.children);
+        while let Some(child) = pending.pop() {
+            if let Ok(mut node) = Arc::try_unwrap(child) {
+                pending.append(&mut node.children);
+            }
+        }
+    }
+}
+
+impl Node {
+    /// Copies a `Person` tree into fresh, unshared nodes.
+    fn from_person(root: &Person) -> Arc<Node> {
+        // Build bottom-up: each frame is a person with its children built so far.
+        let mut stack: Vec<(&Person, Vec<Arc<Node>>)> = vec![(root, vec![])];
+        loop {
+            let (person, built) = stack.last_mut().unwrap();
+            if let Some(child) = person.children.get(built.len()) {
+                stack.push((child, vec![]));
+                continue;
+            }
+            let (person, children) = stack.pop().unwrap();
+            let node = Arc::new(Node {
+                name: person.name.clone(),
+                age: person.age,
+                children,
+            });
+            match stack.last_mut() {
+                Some((_, siblings)) => siblings.push(node),
+                None => return node,
+            }
+        }
+    }
+
+    /// Copies the subtree at this node out as a `Person` tree.
+    fn to_person(&self) -> Person {
+        let mut stack: Vec<(&Node, Vec<Person>)> = vec![(self, vec![])];
+        loop {
+            let (node, built) = stack.last_mut().unwrap();
+            if let Some(child) = node.children.get(built.len()) {
+                stack.push((child, vec![]));
+                continue;
+            }
+            let (node, children) = stack.pop().unwrap();
+            let person = Person::new(node.name.clone(), node.age, children);
+            match stack.last_mut() {
+                Some((_, siblings)) => siblings.push(person),
+                None => return person,
+            }
+        }
+    }
+
+    /// Returns the node at `path` below this one.
+    fn at(&self, path: &[usize]) -> PyResult<&Node> {
+        let mut node = self;
+        for (depth, &index) in path.iter().enumerate() {
+            node = node
+                .children
+                .get(index)
+                .ok_or_else(|| out_of_range(path, depth, node.children.len()))?;
+        }
+        Ok(node)
+    }
+}
+
+/// Returns the node at `path` below `root` for editing, first copying every
+/// shared node on the way. The path must already be known to be in range.
+fn make_mut<'a>(root: &'a mut Arc<Node>, path: &[usize]) -> &'a mut Node {
+    let mut node = Arc::make_mut(root);
+    for &index in path {
+        node = Arc::make_mut(&mut node.children[index]);
+    }
+    node
+}
+
+/// A `Person` tree with cheap snapshots, sharing unchanged subtrees between
+/// versions as described in the `cow` module.
+///
+/// Nodes are addressed by path, the list of child indices from the root.
+#[pyclass]
+pub struct CowPerson {
+    root: Arc<Node>,
+}
+
+#[pymethods]
+impl CowPerson {
+    /// Creates a copy-on-write tree holding a copy of `person`.
+    #[new]
+    fn new(person: &Person) -> Self {
+        CowPerson {
+            root: Node::from_person(person),
+        }
+    }
+
+    /// Returns a handle to the current version, sharing every node with this
+    /// one, in constant time.
+    fn snapshot(&self) -> CowPerson {
+        CowPerson {
+            root: Arc::clone(&self.root),
+        }
+    }
+
+    /// Copies the subtree at `path` out as a Person.
+    ///
+    /// # Arguments
+    /// * `path` - The child indices from the root; the default is the root
+    #[pyo3(signature = (path = vec![]))]
+    fn to_person(&self, path: Vec<usize>) -> PyResult<Person> {
+        Ok(self.root.at(&path)?.to_person())
+    }
+
+    /// Sets the name of the node at `path`.
+    fn set_name(&mut self, path: Vec<usize>, name: &Bound<'_, PyAny>) -> PyResult<()> {
+        let name = names::ingest_name(name)?;
+        self.root.at(&path)?;
+        make_mut(&mut self.root, &path).name = name;
+        Ok(())
+    }
+
+    /// Sets the age of the node at `path`.
+    fn set_age(&mut self, path: Vec<usize>, age: u32) -> PyResult<()> {
+        self.root.at(&path)?;
+        make_mut(&mut self.root, &path).age = age;
+        Ok(())
+    }
+
+    /// Appends a copy of `child` to the children of the node at `path`.
+    fn add_child(&mut self, path: Vec<usize>, child: &Person) -> PyResult<()> {
+        self.root.at(&path)?;
+        make_mut(&mut self.root, &path)
+            .children
+            .push(Node::from_person(child));
+        Ok(())
+    }
+
+    /// Removes the child at `index` of the node at `path`.
+    ///
+    /// # Returns
+    /// The removed subtree as a Person
+    fn remove_child(&mut self, path: Vec<usize>, index: usize) -> PyResult<Person> {
+        let count = self.root.at(&path)?.children.len();
+        if index >= count {
+            return Err(PyIndexError::new_err(format!(
+                "Child index {} is out of range for {} children",
+                index, count
+            )));
+        }
+        let removed = make_mut(&mut self.root, &path).children.remove(index);
+        Ok(removed.to_person())
+    }
+
+    /// Counts the nodes of this tree that are physically shared with `other`,
+    /// rather than merely equal.
+    fn shared_nodes(&self, other: &CowPerson) -> usize {
+        let mut theirs: HashSet<*const Node> = HashSet::new();
+        let mut stack = vec![&other.root];
+        while let Some(node) = stack.pop() {
+            theirs.insert(Arc::as_ptr(node));
+            stack.extend(node.children.iter());
+        }
+
+        let mut shared = 0;
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            if theirs.contains(&Arc::as_ptr(node)) {
+                shared += 1;
+            }
+            stack.extend(node.children.iter());
+        }
+        shared
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "CowPerson(name={:?}, children={})",
+            self.root.name,
+            self.root.children.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_balanced_person;
+
+    #[test]
+    fn snapshots_do_not_see_later_writes() {
+        let person = create_balanced_person(3, 2);
+        let mut tree = CowPerson::new(&person);
+        let mut snapshot = tree.snapshot();
+
+        tree.set_age(vec![0, 1, 0], 99).unwrap();
+        tree.add_child(vec![1], &person).unwrap();
+        tree.remove_child(vec![0], 0).unwrap();
+        assert_eq!(snapshot.to_person(vec![]).unwrap(), person);
+        assert_eq!(tree.to_person(vec![0, 0, 0]).unwrap().age, 99);
+
+        snapshot.set_age(vec![], 1).unwrap();
+        assert_eq!(tree.to_person(vec![]).unwrap().age, person.age);
+    }
+
+    #[test]
+    fn writes_copy_only_the_shared_nodes_on_their_path() {
+        // 15 nodes, 3 levels below the root.
+        let mut tree = CowPerson::new(&create_balanced_person(3, 2));
+        let snapshot = tree.snapshot();
+        assert_eq!(tree.shared_nodes(&snapshot), 15);
+
+        tree.set_age(vec![0, 1, 0], 99).unwrap();
+        assert_eq!(tree.shared_nodes(&snapshot), 11);
+        // Those four nodes are no longer shared, so they are edited in place.
+        tree.set_age(vec![0, 1, 0], 98).unwrap();
+        tree.set_age(vec![0], 97).unwrap();
+        assert_eq!(tree.shared_nodes(&snapshot), 11);
+        tree.set_age(vec![1, 0], 96).unwrap();
+        assert_eq!(tree.shared_nodes(&snapshot), 9);
+
+        assert!(tree.set_age(vec![1, 5], 1).is_err());
+        assert_eq!(tree.shared_nodes(&snapshot), 9);
+        assert_eq!(snapshot.shared_nodes(&tree), 9);
+    }
+}
@@ -0,0 +1,129 @@
+//! Reading `Person` forests from CSV files with parent references.
+//!
+//! The file needs a header row with the columns `name`, `age` and
+//! `parent_name`, in any order and possibly among other columns, which are
+//! ignored. Each following row is one person:
+//!
+//! ```text
+//! name,age,parent_name
+//! Ann,60,
+//! Bob,35,Ann
+//! Cid,8,Bob
+//! ```
+//!
+//! Names identify people, so they must be unique. A row with an empty
+//! `parent_name` is a root; any other parent must be the name of some row,
+//! which may come before or after it. Roots are returned in row order, and
+//! children are ordered by row as well. Parent references that form a cycle
+//! are an error.
+
+use std::collections::HashMap;
+use std::fs::File;
+
+use pyo3::PyResult;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+
+/// Reads the forest in the CSV file at `path`.
+pub fn from_csv(path: &str) -> PyResult<Vec<Person>> {
+    let mut reader = csv::Reader::from_reader(File::open(path)?);
+    let csv_error =
+        |err: csv::Error| ErrorCode::Malformed.err(format!("Invalid CSV in {}: {}", path, err));
+
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| {
+                ErrorCode::MissingField.err(format!("{} has no `{}` column", path, name))
+            })
+    };
+    let (name_col, age_col, parent_col) = (column("name")?, column("age")?, column("parent_name")?);
+
+    let mut names: Vec<String> = Vec::new();
+    let mut ages: Vec<u32> = Vec::new();
+    let mut parent_names: Vec<(u64, String)> = Vec::new();
+    let mut index_of: HashMap<String, usize> = HashMap::new();
+
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let line = record.position().map_or(0, |position| position.line());
+        let field = |col: usize| record.get(col).unwrap_or("");
+
+        let name = field(name_col).to_string();
+        let age = field(age_col).trim().parse::<u32>().map_err(|_| {
+            ErrorCode::InvalidAge.err(format!(
+                "Line {}: invalid age {:?} for {:?}",
+                line,
+                field(age_col),
+                name
+            ))
+        })?;
+        if index_of.insert(name.clone(), names.len()).is_some() {
+            return Err(
+                ErrorCode::DuplicateName.err(format!("Line {}: duplicate name {:?}", line, name))
+            );
+        }
+        names.push(name);
+        ages.push(age);
+        parent_names.push((line, field(parent_col).to_string()));
+    }
+
+    let mut roots = Vec::new();
+    let mut children: Vec<Vec<usize>> = vec![vec![]; names.len()];
+    for (index, (line, parent)) in parent_names.iter().enumerate() {
+        if parent.is_empty() {
+            roots.push(index);
+            continue;
+        }
+        let parent = *index_of.get(parent).ok_or_else(|| {
+            ErrorCode::Malformed.err(format!(
+                "Line {}: parent {:?} of {:?} is not in the file",
+                line, parent, names[index]
+            ))
+        })?;
+        children[parent].push(index);
+    }
+
+    // Visit everything reachable from the roots; anything left over is on, or
+    // below, a cycle of parent references.
+    let mut order = Vec::with_capacity(names.len());
+    let mut stack: Vec<usize> = roots.iter().rev().copied().collect();
+    while let Some(index) = stack.pop() {
+        order.push(index);
+        stack.extend(children[index].iter().rev());
+    }
+    if order.len() != names.len() {
+        let mut reached = vec![false; names.len()];
+        for &index in &order {
+            reached[index] = true;
+        }
+        let stranded = reached.iter().position(|&reached| !reached).unwrap();
+        return Err(ErrorCode::Malformed.err(format!(
+            "Line {}: {:?} is part of, or below, a cycle of parent references",
+            parent_names[stranded].0, names[stranded]
+        )));
+    }
+
+    // Build bottom-up: in reverse pre-order, every node's children are
+    // complete before the node itself.
+    let mut built: Vec<Option<Person>> = (0..names.len()).map(|_| None).collect();
+    for &index in order.iter().rev() {
+        let kids = children[index]
+            .iter()
+            .map(|&child| built[child].take().unwrap())
+            .collect();
+        built[index] = Some(Person::new(
+            std::mem::take(&mut names[index]),
+            ages[index],
+            kids,
+        ));
+    }
+
+    Ok(roots
+        .into_iter()
+        .map(|root| built[root].take().unwrap())
+        .collect())
+}
@@ -0,0 +1,109 @@
+//! An opt-in, process-wide LRU cache of `from_msgpack` results.
+//!
+//! Hot paths that decode the same payload again and again can skip the work by
+//! enabling the cache with `set_decode_cache_size`. Entries are keyed by the
+//! SHA-256 digest of the payload bytes and hold a decoded tree; a hit returns a
+//! copy, so callers can never mutate a cached tree. The cache starts with
+//! capacity 0, which disables it entirely.
+//!
+//! # Thread safety
+//!
+//! The cache is guarded by a mutex, which is only held to look up, insert or
+//! evict entries, never while decoding. Concurrent misses for the same payload
+//! may each decode it; the result is the same either way.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+use pyo3::PyResult;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::Person;
+use crate::msgpack;
+
+type PayloadDigest = [u8; 32];
+
+#[derive(Default)]
+struct DecodeCache {
+    capacity: usize,
+    /// Cached trees with the tick of their last use.
+    entries: HashMap<PayloadDigest, (u64, Person)>,
+    /// Digests by the tick of their last use, least recent first.
+    recency: BTreeMap<u64, PayloadDigest>,
+    next_tick: u64,
+}
+
+impl DecodeCache {
+    fn get(&mut self, key: &PayloadDigest) -> Option<Person> {
+        let tick = self.next_tick;
+        let (last_used, person) = self.entries.get_mut(key)?;
+        self.recency.remove(last_used);
+        self.recency.insert(tick, *key);
+        *last_used = tick;
+        self.next_tick += 1;
+        Some(person.clone())
+    }
+
+    fn insert(&mut self, key: PayloadDigest, person: Person) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        if let Some((last_used, _)) = self.entries.insert(key, (tick, person)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(tick, key);
+        self.evict();
+    }
+
+    /// Drops least recently used entries until the cache fits its capacity.
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let (_, key) = self.recency.pop_first().unwrap();
+            self.entries.remove(&key);
+        }
+    }
+}
+
+static CACHE: LazyLock<Mutex<DecodeCache>> = LazyLock::new(Default::default);
+
+fn cache() -> std::sync::MutexGuard<'static, DecodeCache> {
+    // The cache holds no invariants a panic could break, so recover from poisoning.
+    CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Sets the maximum number of cached trees, evicting the least recently used
+/// ones if there are more. A capacity of 0 disables the cache and empties it.
+pub fn set_capacity(capacity: usize) {
+    let mut cache = cache();
+    cache.capacity = capacity;
+    cache.evict();
+}
+
+/// Removes every cached tree, keeping the capacity.
+pub fn clear() {
+    let mut cache = cache();
+    cache.entries.clear();
+    cache.recency.clear();
+}
+
+/// Decodes a msgpack payload, going through the cache when it is enabled.
+pub fn decode(bytes: &[u8]) -> PyResult<Person> {
+    if cache().capacity == 0 {
+        return msgpack::decode(bytes);
+    }
+
+    let key: PayloadDigest = Sha256::digest(bytes).into();
+    if let Some(person) = cache().get(&key) {
+        return Ok(person);
+    }
+    let person = msgpack::decode(bytes)?;
+    cache().insert(key, person.clone());
+    Ok(person)
+}
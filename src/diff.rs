@@ -0,0 +1,326 @@
+//! Structural comparison of two `Person` trees.
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+
+use crate::Person;
+
+/// Returns the path of the first difference between two trees, or `None` if
+/// they are equal.
+///
+/// Nodes are compared in pre-order: a node's `name`, then its `age`, then its
+/// children in order. Paths use attribute and index syntax relative to the
+/// root, such as `age` or `children[1].children[0].name`. When one node has
+/// more children than the other, the path names the first unmatched child,
+/// e.g. `children[2]`, after all shared children have been compared.
+pub fn first_mismatch(left: &Person, right: &Person) -> Option<String> {
+    enum Step<'a> {
+        Compare(&'a Person, &'a Person, String),
+        Unmatched(String),
+    }
+
+    let field = |path: &str, name: &str| {
+        if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", path, name)
+        }
+    };
+
+    let mut stack = vec![Step::Compare(left, right, String::new())];
+    while let Some(step) = stack.pop() {
+        let (a, b, path) = match step {
+            Step::Compare(a, b, path) => (a, b, path),
+            Step::Unmatched(path) => return Some(path),
+        };
+
+        if a.name != b.name {
+            return Some(field(&path, "name"));
+        }
+        if a.age != b.age {
+            return Some(field(&path, "age"));
+        }
+
+        let shared = a.children.len().min(b.children.len());
+        if a.children.len() != b.children.len() {
+            stack.push(Step::Unmatched(field(
+                &path,
+                &format!("children[{}]", shared),
+            )));
+        }
+        for i in (0..shared).rev() {
+            stack.push(Step::Compare(
+                &a.children[i],
+                &b.children[i],
+                field(&path, &format!("children[{}]", i)),
+            ));
+        }
+    }
+
+    None
+}
+
+/// Whether two trees have the same names and shape, ignoring ages.
+///
+/// Children are compared in order, so the same children in another order do
+/// not match. The comparison is iterative and stops at the first difference.
+pub fn equals_structure(left: &Person, right: &Person) -> bool {
+    let mut stack = vec![(left, right)];
+    while let Some((a, b)) = stack.pop() {
+        if a.name != b.name || a.children.len() != b.children.len() {
+            return false;
+        }
+        stack.extend(a.children.iter().zip(&b.children));
+    }
+    true
+}
+
+/// The most table cells `edit_distance` fills before giving up. Its tables
+/// have fewer cells than it fills, so this bounds both its time, to well under
+/// a second, and its memory, to about 80 MB.
+pub const MAX_EDIT_DISTANCE_WORK: u64 = 10_000_000;
+
+/// A tree laid out in post-order for `edit_distance`.
+struct Postorder<'a> {
+    /// Every node, in post-order.
+    nodes: Vec<&'a Person>,
+    /// The post-order index of the leftmost leaf below every node.
+    leftmost: Vec<usize>,
+    /// The nodes that are not the leftmost child of their parent, and the
+    /// root, in increasing order.
+    keyroots: Vec<usize>,
+}
+
+impl<'a> Postorder<'a> {
+    fn new(root: &'a Person) -> Self {
+        let mut nodes = Vec::new();
+        let mut leftmost = Vec::new();
+        // Each frame is a node, the number of its children visited so far and
+        // the leftmost leaf of its first child, once known.
+        let mut stack: Vec<(&Person, usize, Option<usize>)> = vec![(root, 0, None)];
+        while let Some((node, visited, first_leaf)) = stack.last_mut() {
+            if let Some(child) = node.children.get(*visited) {
+                *visited += 1;
+                stack.push((child, 0, None));
+                continue;
+            }
+            let index = nodes.len();
+            let leaf = first_leaf.unwrap_or(index);
+            nodes.push(*node);
+            leftmost.push(leaf);
+            stack.pop();
+            if let Some((_, _, parent_leaf)) = stack.last_mut() {
+                parent_leaf.get_or_insert(leaf);
+            }
+        }
+
+        // A keyroot is the last node in post-order with its leftmost leaf.
+        let mut last_with_leaf = vec![0; nodes.len()];
+        for (index, &leaf) in leftmost.iter().enumerate() {
+            last_with_leaf[leaf] = index;
+        }
+        let mut keyroots: Vec<usize> = (0..nodes.len())
+            .filter(|&index| leftmost[index] == index)
+            .map(|leaf| last_with_leaf[leaf])
+            .collect();
+        keyroots.sort_unstable();
+
+        Postorder {
+            nodes,
+            leftmost,
+            keyroots,
+        }
+    }
+
+    /// The number of table cells filled for this tree's side of the
+    /// comparison: the total size of the subtrees at its keyroots.
+    fn work(&self) -> u64 {
+        self.keyroots
+            .iter()
+            .map(|&root| (root - self.leftmost[root] + 1) as u64)
+            .sum()
+    }
+}
+
+/// Returns the ordered tree edit distance between two trees, or an error if
+/// computing it would take more than `MAX_EDIT_DISTANCE_WORK` steps.
+///
+/// # Cost model
+///
+/// The distance is the fewest operations turning `left` into `right`, where
+/// each of these costs 1:
+///
+/// - deleting a node, which makes its children children of its parent, in
+///   its place;
+/// - inserting a node, the reverse of deleting one;
+/// - relabelling a node, changing its name, its age or both.
+///
+/// Children are ordered, so swapping two children costs more than 0. Equal
+/// trees are at distance 0, and the distance is at most the sum of the sizes.
+///
+/// # Complexity
+///
+/// This is the Zhang-Shasha algorithm. For trees of `n` and `m` nodes it takes
+/// `O(n m)` memory and `O(n m min(d1, l1) min(d2, l2))` time, where `d` is a
+/// tree's depth and `l` its number of leaves: near `O(n m log n log m)` for
+/// balanced trees, but up to `O(n^2 m^2)` for long zigzag chains. The exact
+/// number of steps is known before starting, and `PyValueError` is returned
+/// instead of starting when it exceeds the cap. Even the cheapest pairs take
+/// `n m` steps, so two trees of more than about 3,000 nodes each are always
+/// rejected.
+pub fn edit_distance(left: &Person, right: &Person) -> PyResult<usize> {
+    let a = Postorder::new(left);
+    let b = Postorder::new(right);
+    let work = a.work().saturating_mul(b.work());
+    if work > MAX_EDIT_DISTANCE_WORK {
+        return Err(PyValueError::new_err(format!(
+            "Trees of {} and {} nodes are too large to compare: {} steps, more than the maximum of {}",
+            a.nodes.len(),
+            b.nodes.len(),
+            work,
+            MAX_EDIT_DISTANCE_WORK
+        )));
+    }
+
+    let (n, m) = (a.nodes.len(), b.nodes.len());
+    // The distance between every pair of subtrees, row-major. Distances are
+    // at most `n + m`, which the cap keeps far below `u32::MAX`.
+    let mut tree = vec![0u32; n * m];
+    // The distance between forests of the current pair of keyroot subtrees,
+    // with one extra row and column for the empty forest.
+    let mut forest = vec![0u32; (n + 1) * (m + 1)];
+    let width = m + 1;
+
+    for &i in &a.keyroots {
+        for &j in &b.keyroots {
+            let (li, lj) = (a.leftmost[i], b.leftmost[j]);
+            let (rows, cols) = (i - li + 2, j - lj + 2);
+            forest[0] = 0;
+            for x in 1..rows {
+                forest[x * width] = x as u32;
+            }
+            for (y, cell) in forest[..cols].iter_mut().enumerate().skip(1) {
+                *cell = y as u32;
+            }
+
+            for x in 1..rows {
+                let node_a = li + x - 1;
+                for y in 1..cols {
+                    let node_b = lj + y - 1;
+                    let delete = forest[(x - 1) * width + y] + 1;
+                    let insert = forest[x * width + y - 1] + 1;
+                    let cell = if a.leftmost[node_a] == li && b.leftmost[node_b] == lj {
+                        // Both forests are whole trees.
+                        let (p, q) = (a.nodes[node_a], b.nodes[node_b]);
+                        let relabel = u32::from(p.name != q.name || p.age != q.age);
+                        let distance = delete
+                            .min(insert)
+                            .min(forest[(x - 1) * width + y - 1] + relabel);
+                        tree[node_a * m + node_b] = distance;
+                        distance
+                    } else {
+                        let x0 = a.leftmost[node_a] - li;
+                        let y0 = b.leftmost[node_b] - lj;
+                        delete
+                            .min(insert)
+                            .min(forest[x0 * width + y0] + tree[node_a * m + node_b])
+                    };
+                    forest[x * width + y] = cell;
+                }
+            }
+        }
+    }
+
+    Ok(tree[n * m - 1] as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::Python;
+
+    use super::*;
+
+    fn person(name: &str, children: Vec<Person>) -> Person {
+        Person::new(name.to_string(), 1, children)
+    }
+
+    fn leaf(name: &str) -> Person {
+        person(name, vec![])
+    }
+
+    fn family() -> Person {
+        person(
+            "a",
+            vec![person("b", vec![leaf("c"), leaf("d")]), leaf("e")],
+        )
+    }
+
+    fn distance(left: &Person, right: &Person) -> usize {
+        let distance = edit_distance(left, right).unwrap();
+        assert_eq!(edit_distance(right, left).unwrap(), distance);
+        distance
+    }
+
+    #[test]
+    fn known_distances() {
+        let old = family();
+        assert_eq!(distance(&old, &family()), 0);
+
+        let mut renamed = family();
+        renamed.children[0].children[1].name = "z".to_string();
+        assert_eq!(distance(&old, &renamed), 1);
+        let mut aged = family();
+        aged.children[1].age = 2;
+        assert_eq!(distance(&old, &aged), 1);
+
+        let mut inserted = family();
+        inserted.children[0].children.insert(1, leaf("x"));
+        assert_eq!(distance(&old, &inserted), 1);
+        let mut deleted = family();
+        deleted.children.pop();
+        assert_eq!(distance(&old, &deleted), 1);
+
+        // Deleting "b" makes "c" and "d" children of the root, in its place.
+        let reparented = person("a", vec![leaf("c"), leaf("d"), leaf("e")]);
+        assert_eq!(distance(&old, &reparented), 1);
+
+        let mut swapped = family();
+        swapped.children.swap(0, 1);
+        // Deleting "e" and inserting it again before "b".
+        assert_eq!(distance(&old, &swapped), 2);
+
+        // The example from Zhang and Shasha's paper.
+        let left = person(
+            "f",
+            vec![
+                person("d", vec![leaf("a"), person("c", vec![leaf("b")])]),
+                leaf("e"),
+            ],
+        );
+        let right = person(
+            "f",
+            vec![
+                person("c", vec![person("d", vec![leaf("a"), leaf("b")])]),
+                leaf("e"),
+            ],
+        );
+        assert_eq!(distance(&left, &right), 2);
+    }
+
+    #[test]
+    fn rejects_comparisons_over_the_work_limit() {
+        let wide = |children: usize| person("root", (0..children).map(|_| leaf("x")).collect());
+        // A root with `c` leaf children fills `2c` cells per side.
+        assert!(edit_distance(&wide(1_500), &wide(1_500)).is_ok());
+
+        let err = edit_distance(&wide(1_600), &wide(1_600)).unwrap_err();
+        Python::with_gil(|py| {
+            assert!(err.is_instance_of::<PyValueError>(py));
+            assert!(
+                err.value(py)
+                    .to_string()
+                    .contains("more than the maximum of 10000000")
+            );
+        });
+    }
+}
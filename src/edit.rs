@@ -0,0 +1,119 @@
+//! In-place structural edits of `Person` trees.
+//!
+//! Callers are responsible for calling `Person::mark_changed` on the root
+//! after a successful edit.
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyResult;
+use pyo3::exceptions::PyIndexError;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::IntoPyDict;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyList;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use crate::Person;
+use crate::paths::node_at;
+use crate::paths::node_at_mut;
+
+/// Detaches the subtree at `from_path` and appends it to the children of the
+/// node at `to_path`.
+///
+/// Both paths refer to positions before the move. The root cannot be moved,
+/// and a node cannot be moved into itself or one of its descendants. Paths are
+/// checked before anything is modified, so a failed move leaves the tree intact.
+pub fn move_child(root: &mut Person, from_path: &[usize], to_path: &[usize]) -> PyResult<()> {
+    let Some((&from_index, from_parent)) = from_path.split_last() else {
+        return Err(PyValueError::new_err("Cannot move the root of the tree"));
+    };
+    node_at(root, from_path)?;
+    node_at(root, to_path)?;
+    if to_path.starts_with(from_path) {
+        return Err(PyValueError::new_err(format!(
+            "Cannot move {:?} into its own subtree at {:?}",
+            from_path, to_path
+        )));
+    }
+
+    // Removing the subtree shifts later siblings down by one, which affects
+    // the destination if it lies below one of them.
+    let mut to_path = to_path.to_vec();
+    if to_path.starts_with(from_parent)
+        && let Some(index) = to_path.get_mut(from_parent.len())
+        && *index > from_index
+    {
+        *index -= 1;
+    }
+
+    let subtree = node_at_mut(root, from_parent)?.children.remove(from_index);
+    node_at_mut(root, &to_path)
+        .map_err(|_| PyIndexError::new_err("Destination vanished during move"))?
+        .children
+        .push(subtree);
+    Ok(())
+}
+
+/// Swaps the immediate children at indices `i` and `j` of `node`.
+pub fn swap_children(node: &mut Person, i: usize, j: usize) -> PyResult<()> {
+    let len = node.children.len();
+    if let Some(index) = [i, j].into_iter().find(|&index| index >= len) {
+        return Err(PyIndexError::new_err(format!(
+            "Child index {} is out of range for {} children",
+            index, len
+        )));
+    }
+    node.children.swap(i, j);
+    Ok(())
+}
+
+/// Sorts the immediate children of `node` by the results of the Python
+/// callable `key`, called once with a copy of each child.
+///
+/// The keys are ordered by Python's own `sorted`, so the sort is stable and
+/// compares keys exactly as Python would. If `key` raises or two keys cannot
+/// be compared, the error propagates and the children keep their order.
+pub fn sort_children_by(node: &mut Person, key: &Bound<'_, PyAny>) -> PyResult<()> {
+    let py = key.py();
+    let keys = node
+        .children
+        .iter()
+        .map(|child| key.call1((child.clone(),)))
+        .collect::<PyResult<Vec<_>>>()?;
+    let keys = PyList::new(py, keys)?;
+    let order: Vec<usize> = py
+        .import("builtins")?
+        .getattr("sorted")?
+        .call(
+            ((0..node.children.len()).collect::<Vec<_>>(),),
+            Some(&[("key", keys.getattr("__getitem__")?)].into_py_dict(py)?),
+        )?
+        .extract()?;
+
+    let mut children: Vec<Option<Person>> = std::mem::take(&mut node.children)
+        .into_iter()
+        .map(Some)
+        .collect();
+    node.children = order
+        .into_iter()
+        .map(|index| children[index].take().unwrap())
+        .collect();
+    Ok(())
+}
+
+/// Shuffles the children of every node in the tree, deterministically for a
+/// given `seed`.
+///
+/// Nodes are shuffled in pre-order, each after its parent, from one generator
+/// seeded with `seed`. As for `PersonRng`, the order a seed gives is only
+/// stable for a given version of this crate and of `rand`.
+pub fn shuffle_children(root: &mut Person, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        node.children.shuffle(&mut rng);
+        stack.extend(node.children.iter_mut().rev());
+    }
+}
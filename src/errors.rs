@@ -0,0 +1,123 @@
+//! Machine-readable error codes for rejected person data.
+//!
+//! Ingestion errors (from `from_dict`, `from_msgpack`, `from_json` and the
+//! formats built on them) are raised as `PersonError`, a `ValueError` subclass
+//! registered in the module, so existing `except ValueError` handlers keep
+//! working. Each instance carries a stable numeric `code` from [`ErrorCode`],
+//! and `PersonError` has a class attribute per code, so clients can branch on
+//! the code rather than the message:
+//!
+//! ```python
+//! try:
+//!     Person.from_dict(payload, globally_unique_names=True)
+//! except PersonError as err:
+//!     if err.code == PersonError.DUPLICATE_NAME:
+//!         ...
+//! ```
+//!
+//! Codes are never renumbered or reused; messages may change between versions.
+//! A missing dict key still raises `KeyError`, and a value of the wrong Python
+//! type still raises `TypeError`, as before.
+
+use pyo3::Bound;
+use pyo3::PyErr;
+use pyo3::PyResult;
+use pyo3::Python;
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyModule;
+use pyo3::types::PyModuleMethods;
+
+create_exception!(
+    py03_pydantic_ormsgpack_experiment,
+    PersonError,
+    PyValueError,
+    "Invalid person data. `code` holds one of the class's integer code attributes."
+);
+
+/// Why person data was rejected. The discriminants are the stable codes seen
+/// from Python.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The payload is not well-formed: truncated bytes, invalid JSON, a value
+    /// of the wrong shape, and so on.
+    Malformed = 1,
+    /// A person is missing a required field.
+    MissingField = 2,
+    /// A name is not valid Unicode.
+    InvalidName = 3,
+    /// An age is not a valid `u32` or ISO 8601 duration.
+    InvalidAge = 4,
+    /// A name does not match the required pattern.
+    NamePattern = 5,
+    /// A name is used by more than one person.
+    DuplicateName = 6,
+    /// A person has more children than allowed.
+    TooManyChildren = 7,
+    /// The tree has more nodes than allowed.
+    TooManyNodes = 8,
+    /// The payload is longer than allowed.
+    PayloadTooLarge = 9,
+}
+
+impl ErrorCode {
+    /// Every code, in numeric order.
+    pub const ALL: [ErrorCode; 9] = [
+        ErrorCode::Malformed,
+        ErrorCode::MissingField,
+        ErrorCode::InvalidName,
+        ErrorCode::InvalidAge,
+        ErrorCode::NamePattern,
+        ErrorCode::DuplicateName,
+        ErrorCode::TooManyChildren,
+        ErrorCode::TooManyNodes,
+        ErrorCode::PayloadTooLarge,
+    ];
+
+    /// The name of the code's class attribute on `PersonError`.
+    pub fn attribute(self) -> &'static str {
+        match self {
+            ErrorCode::Malformed => "MALFORMED",
+            ErrorCode::MissingField => "MISSING_FIELD",
+            ErrorCode::InvalidName => "INVALID_NAME",
+            ErrorCode::InvalidAge => "INVALID_AGE",
+            ErrorCode::NamePattern => "NAME_PATTERN",
+            ErrorCode::DuplicateName => "DUPLICATE_NAME",
+            ErrorCode::TooManyChildren => "TOO_MANY_CHILDREN",
+            ErrorCode::TooManyNodes => "TOO_MANY_NODES",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+        }
+    }
+
+    /// Creates a `PersonError` with this code and `message`.
+    pub fn err(self, message: impl Into<String>) -> PyErr {
+        let err = PersonError::new_err(message.into());
+        Python::with_gil(|py| {
+            // Setting an attribute on a fresh exception instance cannot fail.
+            let _ = err.value(py).setattr("code", self as u32);
+        });
+        err
+    }
+}
+
+/// Adds `PersonError`, with its code attributes, to `module`.
+pub fn register(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    let class = module.py().get_type::<PersonError>();
+    for code in ErrorCode::ALL {
+        class.setattr(code.attribute(), code as u32)?;
+    }
+    module.add("PersonError", class)
+}
+
+/// Returns the `code` of a `PersonError`, or `None` for any other error.
+#[cfg(test)]
+pub fn code_of(err: &PyErr) -> Option<u32> {
+    Python::with_gil(|py| {
+        let value = err.value(py);
+        if !value.is_instance_of::<PersonError>() {
+            return None;
+        }
+        value.getattr("code").ok()?.extract().ok()
+    })
+}
@@ -0,0 +1,516 @@
+//! Conversion of `Person` trees to Python dicts, with output options.
+//!
+//! With the default options the result is the plain `{"name", "age",
+//! "children"}` form produced by `Person.__dict__`.
+//!
+//! # Frozen exports
+//!
+//! `freeze` turns such a result into a read-only view for handing to untrusted
+//! code: every node becomes a `types.MappingProxyType` over its dict and every
+//! `children` list becomes a tuple, so no part of the export can be modified
+//! in place. The values themselves (strings and integers) are immutable
+//! already. Copying a view with `dict(view)` gives a mutable shallow copy, as
+//! for any mapping proxy. `from_dict` reads a frozen export like any other.
+
+use std::collections::BTreeMap;
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyResult;
+use pyo3::Python;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyDict;
+use pyo3::types::PyDictMethods;
+use pyo3::types::PyList;
+use pyo3::types::PyListMethods;
+use pyo3::types::PyTuple;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::Person;
+
+/// How ages are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AgeFormat {
+    /// A plain integer number of years, e.g. `30`.
+    #[default]
+    Int,
+    /// An ISO 8601 duration in whole years, e.g. `"P30Y"`.
+    Iso8601,
+}
+
+impl AgeFormat {
+    /// Parses the Python-facing name of a format: `"int"` or `"iso8601"`.
+    pub fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "int" => Ok(AgeFormat::Int),
+            "iso8601" => Ok(AgeFormat::Iso8601),
+            _ => Err(PyValueError::new_err(format!(
+                "age_format must be 'int' or 'iso8601', got {:?}",
+                name
+            ))),
+        }
+    }
+}
+
+/// How multi-word keys are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyCase {
+    /// Words joined by `_`, e.g. `parent_id`.
+    #[default]
+    Snake,
+    /// Words after the first capitalized, e.g. `parentId`.
+    Camel,
+    /// Words joined by `-`, e.g. `parent-id`.
+    Kebab,
+}
+
+impl KeyCase {
+    /// Parses the Python-facing name of a case: `"snake"`, `"camel"` or
+    /// `"kebab"`.
+    pub fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "snake" => Ok(KeyCase::Snake),
+            "camel" => Ok(KeyCase::Camel),
+            "kebab" => Ok(KeyCase::Kebab),
+            _ => Err(PyValueError::new_err(format!(
+                "key_case must be 'snake', 'camel' or 'kebab', got {:?}",
+                name
+            ))),
+        }
+    }
+
+    /// Writes the snake_case key `key` in this case.
+    pub fn apply(self, key: &str) -> String {
+        match self {
+            KeyCase::Snake => key.to_string(),
+            KeyCase::Kebab => key.replace('_', "-"),
+            KeyCase::Camel => {
+                let mut words = key.split('_');
+                let mut out = words.next().unwrap_or_default().to_string();
+                for word in words {
+                    let mut chars = word.chars();
+                    if let Some(first) = chars.next() {
+                        out.extend(first.to_uppercase());
+                        out.push_str(chars.as_str());
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// The fields written for every node. Without `children`, only the root is
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fields {
+    pub name: bool,
+    pub age: bool,
+    pub children: bool,
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Fields {
+            name: true,
+            age: true,
+            children: true,
+        }
+    }
+}
+
+impl Fields {
+    /// Selects exactly the named fields, each of which must be `"name"`,
+    /// `"age"` or `"children"`, plus `children`, which is always selected, so
+    /// naming it changes nothing.
+    pub fn parse(names: &[String]) -> PyResult<Self> {
+        let mut fields = Fields {
+            name: false,
+            age: false,
+            children: true,
+        };
+        for name in names {
+            match name.as_str() {
+                "name" => fields.name = true,
+                "age" => fields.age = true,
+                "children" => {}
+                _ => {
+                    return Err(PyValueError::new_err(format!(
+                        "Unknown field {:?}: fields must be 'name', 'age' or 'children'",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Deselects the named fields, each of which must be `"name"`, `"age"` or
+    /// `"children"`.
+    pub fn exclude(&mut self, names: &[String]) -> PyResult<()> {
+        for name in names {
+            match name.as_str() {
+                "name" => self.name = false,
+                "age" => self.age = false,
+                "children" => self.children = false,
+                _ => {
+                    return Err(PyValueError::new_err(format!(
+                        "Unknown field {:?}: excluded fields must be 'name', 'age' or 'children'",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options controlling the dict form of a tree.
+///
+/// # Node ids
+///
+/// With `with_ids` set, every node dict gets an `id` and a `parent_id` (`None`
+/// for the root). Ids depend only on the node's path, so they are stable
+/// across runs and processes for trees of the same shape. An id is 16 hex
+/// digits: the first 8 bytes of SHA-256 over nothing for the root, and over
+/// the parent's 8 id bytes followed by the child index as 8 little-endian
+/// bytes for any other node.
+///
+/// # Sibling indices
+///
+/// With `with_index` set, every node dict except the root's gets a
+/// `sibling_index` holding its position in its parent's `children`, so
+/// consumers can restore the order after a trip through a container that
+/// loses it. `from_dict` ignores the key and relies on list order alone.
+///
+/// # Type tags
+///
+/// With `with_type` set, every node dict starts with a `"__type__": "Person"`
+/// entry, for consumers that dispatch on a tag as in tagged-union JSON.
+/// `from_dict` ignores the tag, like any other unknown key.
+///
+/// # Field selection
+///
+/// `fields` projects every node onto the selected fields, as in GraphQL-style
+/// selection, to save bandwidth. `children` is always selected, so the result
+/// still has the shape of the tree. Fields can also be deselected, to keep
+/// data from a consumer; only deselecting `children` stops the recursion,
+/// leaving just the root. A projection without `name`, `age` or `children`
+/// cannot be read back by `from_dict`.
+///
+/// # Key casing
+///
+/// `key_case` selects the convention for multi-word keys, to serve APIs that
+/// expect one other than snake_case: `parent_id` and `sibling_index` become
+/// `parentId` and `siblingIndex` in camelCase, or `parent-id` and
+/// `sibling-index` in kebab-case. Single-word keys (`name`, `age`,
+/// `children` and `id`) read the same in every case, and the `__type__` tag
+/// is never changed. `from_dict` with `camel_case` reads camelCase keys back.
+#[derive(Debug, Clone, Default)]
+pub struct DictOptions {
+    pub age_format: AgeFormat,
+    pub with_ids: bool,
+    pub with_index: bool,
+    pub with_type: bool,
+    pub fields: Fields,
+    pub key_case: KeyCase,
+}
+
+/// The key and value of the type tag added by `DictOptions::with_type`.
+const TYPE_KEY: &str = "__type__";
+const TYPE_NAME: &str = "Person";
+
+/// The raw bytes of a node id.
+type NodeId = [u8; 8];
+
+/// Derives the id of the child at `index` of the node `parent`, or the id of
+/// the root if there is no parent.
+fn node_id(parent: Option<&NodeId>, index: usize) -> NodeId {
+    let mut hasher = Sha256::new();
+    if let Some(parent) = parent {
+        hasher.update(parent);
+        hasher.update((index as u64).to_le_bytes());
+    }
+    let digest = hasher.finalize();
+    digest[..8].try_into().unwrap()
+}
+
+fn id_to_hex(id: &NodeId) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Converts a tree to nested Python dicts.
+///
+/// Nodes are visited iteratively in pre-order, appending each dict to its
+/// parent's `children` list, so deep trees cannot overflow the stack.
+pub fn to_dict<'py>(
+    root: &Person,
+    py: Python<'py>,
+    options: &DictOptions,
+) -> PyResult<Bound<'py, PyDict>> {
+    struct Pending<'a, 'py> {
+        node: &'a Person,
+        /// The parent's `children` list, or `None` for the root.
+        siblings: Option<Bound<'py, PyList>>,
+        parent_id: Option<NodeId>,
+        index: usize,
+    }
+
+    let parent_id_key = options.key_case.apply("parent_id");
+    let sibling_index_key = options.key_case.apply("sibling_index");
+    let mut root_dict = None;
+    let mut stack = vec![Pending {
+        node: root,
+        siblings: None,
+        parent_id: None,
+        index: 0,
+    }];
+
+    while let Some(pending) = stack.pop() {
+        let (dict, children) = node_dict(pending.node, py, options)?;
+        let id = options
+            .with_ids
+            .then(|| node_id(pending.parent_id.as_ref(), pending.index));
+        if let Some(id) = &id {
+            dict.set_item("id", id_to_hex(id))?;
+            dict.set_item(&parent_id_key, pending.parent_id.as_ref().map(id_to_hex))?;
+        }
+        if options.with_index && pending.siblings.is_some() {
+            dict.set_item(&sibling_index_key, pending.index)?;
+        }
+        match pending.siblings {
+            Some(siblings) => siblings.append(dict)?,
+            None => root_dict = Some(dict),
+        }
+        if !options.fields.children {
+            continue;
+        }
+
+        stack.extend(
+            pending
+                .node
+                .children
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(index, child)| Pending {
+                    node: child,
+                    siblings: Some(children.clone()),
+                    parent_id: id,
+                    index,
+                }),
+        );
+    }
+
+    Ok(root_dict.unwrap())
+}
+
+/// Makes a dict tree produced by `to_dict` read-only, as described in the
+/// module documentation.
+///
+/// Nodes are collected in pre-order and frozen in reverse, so every child is
+/// already a read-only view when its parent's `children` becomes a tuple.
+pub fn freeze<'py>(root: Bound<'py, PyDict>) -> PyResult<Bound<'py, PyAny>> {
+    let py = root.py();
+    let mapping_proxy = py.import("types")?.getattr("MappingProxyType")?;
+
+    /// A parent's index with the position of the child in its `children`.
+    type Slot = (usize, usize);
+
+    // Every node dict with its slot, and separately its `children` list, if
+    // `children` was not excluded.
+    let mut nodes: Vec<(Bound<'py, PyDict>, Option<Slot>)> = Vec::new();
+    let mut lists: Vec<Option<Bound<'py, PyList>>> = Vec::new();
+    let mut stack = vec![(root, None)];
+    while let Some((dict, slot)) = stack.pop() {
+        let children = dict
+            .get_item("children")?
+            .map(|children| children.downcast_into::<PyList>())
+            .transpose()?;
+        let index = nodes.len();
+        for (position, child) in children.iter().flatten().enumerate() {
+            stack.push((child.downcast_into::<PyDict>()?, Some((index, position))));
+        }
+        nodes.push((dict, slot));
+        lists.push(children);
+    }
+
+    for index in (0..nodes.len()).rev() {
+        let (dict, slot) = &nodes[index];
+        if let Some(children) = &lists[index] {
+            dict.set_item("children", PyTuple::new(py, children.iter())?)?;
+        }
+        let view = mapping_proxy.call1((dict,))?;
+        match slot {
+            Some((parent, position)) => {
+                lists[*parent].as_ref().unwrap().set_item(*position, view)?
+            }
+            None => return Ok(view),
+        }
+    }
+    unreachable!("the root is the first node and is frozen last")
+}
+
+/// Converts a tree to nested Python dicts with children grouped by age
+/// decade: each node's `children` is a dict mapping labels such as `"0-9"` and
+/// `"30-39"` to lists of child dicts, for bucketed reports.
+///
+/// Only decades that occur are present, in ascending order, and children keep
+/// their order within a decade; a leaf has an empty dict. This form is export
+/// only: `from_dict` does not read it back.
+pub fn to_grouped_dict<'py>(root: &Person, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+    let mut root_dict = None;
+    // Nodes still to convert, with the decade list that receives them.
+    let mut stack: Vec<(&Person, Option<Bound<'py, PyList>>)> = vec![(root, None)];
+
+    while let Some((node, group)) = stack.pop() {
+        let dict = PyDict::new(py);
+        dict.set_item("name", &node.name)?;
+        dict.set_item("age", node.age)?;
+
+        let mut decades: BTreeMap<u32, Bound<'py, PyList>> = BTreeMap::new();
+        let mut pending = Vec::with_capacity(node.children.len());
+        for child in &node.children {
+            let list = decades
+                .entry(child.age / 10)
+                .or_insert_with(|| PyList::empty(py));
+            pending.push((child, Some(list.clone())));
+        }
+        let groups = PyDict::new(py);
+        for (decade, list) in decades {
+            let start = u64::from(decade) * 10;
+            groups.set_item(format!("{}-{}", start, start + 9), list)?;
+        }
+        dict.set_item("children", groups)?;
+
+        match group {
+            Some(group) => group.append(dict)?,
+            None => root_dict = Some(dict),
+        }
+        // Push in reverse so siblings are appended in order.
+        stack.extend(pending.into_iter().rev());
+    }
+
+    Ok(root_dict.unwrap())
+}
+
+/// Flattens a forest into a struct-of-arrays dict of `name`, `age` and
+/// `parent` lists, one row per node.
+///
+/// Rows are in pre-order, tree after tree. `parent` holds the row index of a
+/// node's parent, or `None` for the roots, so the hierarchy can be rebuilt from
+/// the columns alone.
+pub fn to_columns<'py>(people: &[Person], py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+    let mut names: Vec<&str> = Vec::new();
+    let mut ages: Vec<u32> = Vec::new();
+    let mut parents: Vec<Option<usize>> = Vec::new();
+    let mut stack: Vec<(&Person, Option<usize>)> =
+        people.iter().rev().map(|root| (root, None)).collect();
+
+    while let Some((node, parent)) = stack.pop() {
+        let row = names.len();
+        names.push(&node.name);
+        ages.push(node.age);
+        parents.push(parent);
+        stack.extend(node.children.iter().rev().map(|child| (child, Some(row))));
+    }
+
+    let columns = PyDict::new(py);
+    columns.set_item("name", names)?;
+    columns.set_item("age", ages)?;
+    columns.set_item("parent", parents)?;
+    Ok(columns)
+}
+
+/// Builds the dict for a single node, returning it with its still-empty
+/// `children` list, which is only attached if `children` is selected.
+fn node_dict<'py>(
+    node: &Person,
+    py: Python<'py>,
+    options: &DictOptions,
+) -> PyResult<(Bound<'py, PyDict>, Bound<'py, PyList>)> {
+    let dict = PyDict::new(py);
+    if options.with_type {
+        dict.set_item(TYPE_KEY, TYPE_NAME)?;
+    }
+    if options.fields.name {
+        dict.set_item("name", &node.name)?;
+    }
+    if options.fields.age {
+        match options.age_format {
+            AgeFormat::Int => dict.set_item("age", node.age)?,
+            AgeFormat::Iso8601 => dict.set_item("age", format!("P{}Y", node.age))?,
+        }
+    }
+    let children = PyList::empty(py);
+    if options.fields.children {
+        dict.set_item("children", &children)?;
+    }
+    Ok((dict, children))
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::types::PyAnyMethods;
+
+    use super::*;
+
+    fn keys(dict: &Bound<'_, PyDict>) -> Vec<String> {
+        dict.keys()
+            .iter()
+            .map(|key| key.extract().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn key_case_renames_multi_word_keys() {
+        let child = Person::new("kid".to_string(), 5, vec![]);
+        let root = Person::new("root".to_string(), 40, vec![child]);
+        Python::with_gil(|py| {
+            for (key_case, parent_id, sibling_index) in [
+                (KeyCase::default(), "parent_id", "sibling_index"),
+                (KeyCase::Snake, "parent_id", "sibling_index"),
+                (KeyCase::Camel, "parentId", "siblingIndex"),
+                (KeyCase::Kebab, "parent-id", "sibling-index"),
+            ] {
+                let options = DictOptions {
+                    with_ids: true,
+                    with_index: true,
+                    with_type: true,
+                    key_case,
+                    ..DictOptions::default()
+                };
+                let dict = to_dict(&root, py, &options).unwrap();
+                let children = dict.get_item("children").unwrap().unwrap();
+                let child = children.get_item(0).unwrap();
+                let child = child.downcast::<PyDict>().unwrap();
+                let mut expected = [
+                    "__type__",
+                    "id",
+                    parent_id,
+                    sibling_index,
+                    "name",
+                    "age",
+                    "children",
+                ];
+                expected.sort();
+                let mut actual = keys(child);
+                actual.sort();
+                assert_eq!(actual, expected, "{:?}", key_case);
+            }
+        });
+    }
+
+    #[test]
+    fn key_case_parses_only_known_cases() {
+        assert_eq!(KeyCase::parse("camel").unwrap(), KeyCase::Camel);
+        assert_eq!(KeyCase::parse("kebab").unwrap(), KeyCase::Kebab);
+        assert_eq!(KeyCase::parse("snake").unwrap(), KeyCase::Snake);
+        assert!(KeyCase::parse("Camel").is_err());
+        assert_eq!(KeyCase::Camel.apply("parent_id"), "parentId");
+        assert_eq!(KeyCase::Kebab.apply("parent_id"), "parent-id");
+        assert_eq!(KeyCase::Camel.apply("name"), "name");
+    }
+}
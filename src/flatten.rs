@@ -0,0 +1,136 @@
+//! Flattened views of `Person` trees.
+
+use pyo3::PyResult;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+
+/// Pairs every node with the size of its subtree, in pre-order.
+///
+/// The size counts the node itself, so leaves have size 1 and the root's size
+/// is the total node count. Nodes are collected in one iterative pre-order
+/// walk, then sizes are accumulated bottom-up by visiting them in reverse, since
+/// every node appears after its parent.
+pub fn flatten_weighted(root: &Person) -> Vec<(Person, usize)> {
+    let mut nodes: Vec<(&Person, Option<usize>)> = Vec::new();
+    let mut stack = vec![(root, None)];
+
+    while let Some((node, parent)) = stack.pop() {
+        let index = nodes.len();
+        nodes.push((node, parent));
+        stack.extend(node.children.iter().rev().map(|child| (child, Some(index))));
+    }
+
+    let mut sizes = vec![1usize; nodes.len()];
+    for index in (0..nodes.len()).rev() {
+        if let Some(parent) = nodes[index].1 {
+            sizes[parent] += sizes[index];
+        }
+    }
+
+    nodes
+        .into_iter()
+        .zip(sizes)
+        .map(|((node, _), size)| (node.clone(), size))
+        .collect()
+}
+
+/// Flattens a forest into parallel `(names, ages, parents)` arrays, one entry
+/// per node.
+///
+/// Entries are in pre-order, tree after tree, and `parents` holds the index of
+/// each node's parent, or -1 for the roots. This is the layout `to_columns`
+/// builds, with plain integers instead of `None`.
+pub fn to_flat_arrays(people: &[Person]) -> (Vec<String>, Vec<u32>, Vec<i64>) {
+    let mut names = Vec::new();
+    let mut ages = Vec::new();
+    let mut parents = Vec::new();
+    let mut stack: Vec<(&Person, i64)> = people.iter().rev().map(|root| (root, -1)).collect();
+
+    while let Some((node, parent)) = stack.pop() {
+        let index = names.len() as i64;
+        names.push(node.name.clone());
+        ages.push(node.age);
+        parents.push(parent);
+        stack.extend(node.children.iter().rev().map(|child| (child, index)));
+    }
+    (names, ages, parents)
+}
+
+/// Rebuilds a forest from parallel `(names, ages, parents)` arrays, as written
+/// by `to_flat_arrays`.
+///
+/// Entries need not be in pre-order: the roots are the entries whose parent is
+/// -1, in index order, and each node's children are the entries naming it as
+/// their parent, also in index order. The arrays must have equal lengths, and
+/// every parent must be -1 or the index of another entry. Parents that form a
+/// cycle are rejected too, since such entries are not reachable from a root.
+pub fn from_flat_arrays(
+    names: Vec<String>,
+    ages: &[u32],
+    parents: &[i64],
+) -> PyResult<Vec<Person>> {
+    let len = names.len();
+    if ages.len() != len || parents.len() != len {
+        return Err(ErrorCode::Malformed.err(format!(
+            "Arrays have different lengths: {} names, {} ages and {} parents",
+            len,
+            ages.len(),
+            parents.len()
+        )));
+    }
+
+    let mut roots = Vec::new();
+    let mut children: Vec<Vec<usize>> = vec![vec![]; len];
+    for (index, &parent) in parents.iter().enumerate() {
+        if parent == -1 {
+            roots.push(index);
+            continue;
+        }
+        match usize::try_from(parent) {
+            Ok(parent) if parent < len && parent != index => children[parent].push(index),
+            _ => {
+                return Err(ErrorCode::Malformed.err(format!(
+                    "Entry {} has invalid parent {}: expected -1 or another index below {}",
+                    index, parent, len
+                )));
+            }
+        }
+    }
+
+    let mut names: Vec<Option<String>> = names.into_iter().map(Some).collect();
+    let mut take = |index: usize| Person::new(names[index].take().unwrap(), ages[index], vec![]);
+    let mut built = 0;
+    let mut forest = Vec::with_capacity(roots.len());
+    for root in roots {
+        // Build bottom-up: each frame is a node with the next child to build.
+        built += 1;
+        let mut stack = vec![(root, take(root), 0)];
+        loop {
+            let (index, _, next) = stack.last_mut().unwrap();
+            if let Some(&child) = children[*index].get(*next) {
+                *next += 1;
+                built += 1;
+                stack.push((child, take(child), 0));
+                continue;
+            }
+
+            let (_, node, _) = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some((_, parent, _)) => parent.children.push(node),
+                None => {
+                    forest.push(node);
+                    break;
+                }
+            }
+        }
+    }
+
+    if built != len {
+        return Err(ErrorCode::Malformed.err(format!(
+            "The parents of {} entries form a cycle not connected to any root",
+            len - built
+        )));
+    }
+    Ok(forest)
+}
@@ -0,0 +1,86 @@
+//! A Python class for forests: several independent `Person` trees handled as
+//! one object.
+
+use pyo3::Bound;
+use pyo3::PyResult;
+use pyo3::Python;
+use pyo3::pyclass;
+use pyo3::pymethods;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyIterator;
+use pyo3::types::PyList;
+
+use crate::Person;
+use crate::msgpack;
+
+/// An ordered collection of independent root Persons.
+///
+/// The forest owns copies of its roots, so changing a Person after adding it
+/// does not change the forest, and vice versa.
+#[pyclass]
+pub struct Forest {
+    roots: Vec<Person>,
+}
+
+#[pymethods]
+impl Forest {
+    /// Creates a forest of copies of `roots`, in order.
+    #[new]
+    #[pyo3(signature = (roots = vec![]))]
+    fn new(roots: Vec<Person>) -> Self {
+        Forest { roots }
+    }
+
+    /// Returns an iterator over copies of the roots, in order.
+    fn iter_roots<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        PyList::new(py, self.roots.iter().cloned())?.try_iter()
+    }
+
+    /// Returns the number of nodes in all trees, roots included.
+    fn total_nodes(&self) -> usize {
+        self.roots
+            .iter()
+            .map(|root| root.shape().descendants + 1)
+            .sum()
+    }
+
+    /// Appends copies of the roots of `other` after this forest's roots.
+    ///
+    /// # Arguments
+    /// * `other` - The forest to merge in, which is left unchanged; it may be
+    ///   this forest itself
+    fn merge(slf: &Bound<'_, Self>, other: &Bound<'_, Forest>) {
+        let roots = other.borrow().roots.clone();
+        slf.borrow_mut().roots.extend(roots);
+    }
+
+    /// Encodes the forest as a msgpack array of person maps.
+    ///
+    /// The output matches `ormsgpack.packb` of the list of the roots' dicts.
+    fn to_msgpack(&self) -> Vec<u8> {
+        msgpack::encode_forest(&self.roots)
+    }
+
+    /// Decodes a forest from bytes produced by `to_msgpack`.
+    ///
+    /// # Arguments
+    /// * `data` - A msgpack array of persons, each in any form
+    ///   `Person.from_msgpack` accepts
+    ///
+    /// # Returns
+    /// A Result containing either the decoded Forest or an error
+    #[staticmethod]
+    fn from_msgpack(data: &[u8]) -> PyResult<Self> {
+        Ok(Forest {
+            roots: msgpack::decode_forest(data)?,
+        })
+    }
+
+    fn __len__(&self) -> usize {
+        self.roots.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Forest(roots={})", self.roots.len())
+    }
+}
@@ -0,0 +1,172 @@
+//! Conversion between `Person` trees and graph representations.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use pyo3::PyResult;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+
+/// Builds a tree from `(parent, child)` name pairs.
+///
+/// Names identify nodes, so each must be unique within the tree. The nodes are
+/// the names in `edges`, and each must have an entry in `ages`; other entries
+/// are ignored, except that with no edges a single entry gives a lone root.
+/// Children appear in the order of their edges. The edges must form a single
+/// rooted tree: exactly one node without a parent, no node with two parents,
+/// and no cycles.
+pub fn from_edge_list(edges: &[(String, String)], ages: &HashMap<String, u32>) -> PyResult<Person> {
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut parent_of: HashMap<&str, &str> = HashMap::new();
+    // Every node in first-seen order, so errors are deterministic.
+    let mut nodes: Vec<&str> = Vec::new();
+
+    for (parent, child) in edges {
+        for name in [parent.as_str(), child.as_str()] {
+            if !children.contains_key(name) {
+                children.insert(name, vec![]);
+                nodes.push(name);
+            }
+        }
+        if let Some(previous) = parent_of.insert(child, parent) {
+            return Err(ErrorCode::Malformed.err(format!(
+                "{:?} has two parents, {:?} and {:?}",
+                child, previous, parent
+            )));
+        }
+        children.get_mut(parent.as_str()).unwrap().push(child);
+    }
+    // Without edges, a lone root can only be named by `ages`.
+    if edges.is_empty() && ages.len() == 1 {
+        let (name, &age) = ages.iter().next().unwrap();
+        return Ok(Person::new(name.clone(), age, vec![]));
+    }
+
+    let roots: Vec<&str> = nodes
+        .iter()
+        .copied()
+        .filter(|name| !parent_of.contains_key(name))
+        .collect();
+    let root = match roots.as_slice() {
+        [root] => *root,
+        [] if nodes.is_empty() => return Err(ErrorCode::Malformed.err("The edge list is empty")),
+        [] => {
+            return Err(ErrorCode::Malformed.err("The edges form a cycle and have no root"));
+        }
+        _ => {
+            return Err(ErrorCode::Malformed.err(format!(
+                "The edges form more than one tree, with roots {:?}",
+                roots
+            )));
+        }
+    };
+
+    let age_of = |name: &str| {
+        ages.get(name)
+            .copied()
+            .ok_or_else(|| ErrorCode::MissingField.err(format!("No age given for {:?}", name)))
+    };
+
+    // Build bottom-up: each frame is a node with the children still to build.
+    let mut built = 1;
+    let mut stack = vec![(
+        root,
+        Person::new(root.to_string(), age_of(root)?, vec![]),
+        0,
+    )];
+    loop {
+        let (name, _, next) = stack.last_mut().unwrap();
+        if let Some(&child) = children[*name].get(*next) {
+            *next += 1;
+            built += 1;
+            stack.push((
+                child,
+                Person::new(child.to_string(), age_of(child)?, vec![]),
+                0,
+            ));
+            continue;
+        }
+
+        let (_, node, _) = stack.pop().unwrap();
+        match stack.last_mut() {
+            Some((_, parent, _)) => parent.children.push(node),
+            None => {
+                if built != nodes.len() {
+                    return Err(ErrorCode::Malformed
+                        .err("The edges contain a cycle not connected to the root"));
+                }
+                return Ok(node);
+            }
+        }
+    }
+}
+
+/// Writes a tree as a GraphML document.
+///
+/// Every person becomes a node with `name` and `age` data, and every
+/// parent-child link a directed edge from parent to child. Node ids are `n`
+/// followed by the node's pre-order index, so the root is always `n0`; names
+/// are not used as ids since they need not be unique. In names, `&`, `<`, `>`,
+/// `"` and `'` are escaped as entities, and control characters that XML 1.0
+/// cannot represent at all are replaced with U+FFFD.
+pub fn to_graphml(root: &Person) -> String {
+    let mut nodes = String::new();
+    let mut edges = String::new();
+    let mut next_id = 0;
+    // Nodes still to write, with the id of their parent.
+    let mut stack: Vec<(&Person, Option<usize>)> = vec![(root, None)];
+    while let Some((node, parent)) = stack.pop() {
+        let id = next_id;
+        next_id += 1;
+        writeln!(
+            nodes,
+            "    <node id=\"n{}\"><data key=\"name\">{}</data><data key=\"age\">{}</data></node>",
+            id,
+            escape_xml(&node.name),
+            node.age
+        )
+        .unwrap();
+        if let Some(parent) = parent {
+            writeln!(
+                edges,
+                "    <edge source=\"n{}\" target=\"n{}\"/>",
+                parent, id
+            )
+            .unwrap();
+        }
+        stack.extend(node.children.iter().rev().map(|child| (child, Some(id))));
+    }
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"name\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>\n",
+            "  <key id=\"age\" for=\"node\" attr.name=\"age\" attr.type=\"long\"/>\n",
+            "  <graph id=\"G\" edgedefault=\"directed\">\n",
+            "{}{}",
+            "  </graph>\n",
+            "</graphml>\n",
+        ),
+        nodes, edges
+    )
+}
+
+/// Escapes text for use in XML character data or attribute values.
+fn escape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() && c < '\u{80}' => out.push('\u{FFFD}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,291 @@
+//! Content hashing of `Person` trees.
+//!
+//! The content hash is a Merkle-style SHA-256 digest: the digest of a node
+//! covers its own fields and the digests of its children, so equal subtrees
+//! always have equal digests wherever they appear. A node is hashed as
+//!
+//! ```text
+//! SHA-256( varint(len(name)) || name || varint(age) || varint(child count)
+//!          || digest(child 0) || digest(child 1) || ... )
+//! ```
+//!
+//! where varints are unsigned LEB128 and names are UTF-8. Two trees with the
+//! same digest are equal except with negligible probability, but callers that
+//! need certainty (such as equality checks) still compare the trees themselves
+//! when digests match.
+//!
+//! Everything before the child digests is known as soon as a node is reached,
+//! so each node's hasher is started then and fed each child digest as it is
+//! computed. No preimage is ever buffered, and a traversal holds one hasher
+//! per level, so memory stays O(depth) however large the tree.
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::Person;
+
+/// A SHA-256 content digest.
+pub type ContentDigest = [u8; 32];
+
+fn update_varint(hasher: &mut Sha256, mut value: u64) {
+    // A u64 takes at most 10 LEB128 bytes.
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    while value >= 0x80 {
+        bytes[len] = (value as u8) | 0x80;
+        value >>= 7;
+        len += 1;
+    }
+    bytes[len] = value as u8;
+    hasher.update(&bytes[..=len]);
+}
+
+/// Starts the hasher for `node`, fed with everything before its child
+/// digests.
+fn start_node(node: &Person) -> Sha256 {
+    let mut hasher = Sha256::new();
+    update_varint(&mut hasher, node.name.len() as u64);
+    hasher.update(node.name.as_bytes());
+    update_varint(&mut hasher, u64::from(node.age));
+    update_varint(&mut hasher, node.children.len() as u64);
+    hasher
+}
+
+/// A node whose children are being hashed.
+struct Frame<'a> {
+    node: &'a Person,
+    /// The number of child digests fed to `hasher` so far.
+    hashed: usize,
+    hasher: Sha256,
+}
+
+impl<'a> Frame<'a> {
+    fn new(node: &'a Person) -> Self {
+        Frame {
+            node,
+            hashed: 0,
+            hasher: start_node(node),
+        }
+    }
+
+    /// Returns the next child to hash, if any.
+    fn next_child(&self) -> Option<&'a Person> {
+        self.node.children.get(self.hashed)
+    }
+
+    fn push_digest(&mut self, digest: &ContentDigest) {
+        self.hasher.update(digest);
+        self.hashed += 1;
+    }
+
+    fn finish(self) -> ContentDigest {
+        self.hasher.finalize().into()
+    }
+}
+
+/// Computes the content digest of a tree with an iterative post-order traversal.
+pub fn content_hash(root: &Person) -> ContentDigest {
+    combine(root, usize::MAX, std::iter::empty())
+}
+
+/// Hashes the tree like `content_hash`, but takes the digests of the nodes at
+/// `depth` from `precomputed` instead of descending into them.
+///
+/// Nodes at one depth are visited in the same left-to-right order by a
+/// pre-order and a level-order traversal, so `precomputed` must list them in
+/// level order.
+fn combine(
+    root: &Person,
+    depth: usize,
+    mut precomputed: impl Iterator<Item = ContentDigest>,
+) -> ContentDigest {
+    if depth == 0 {
+        return precomputed.next().unwrap();
+    }
+    let mut stack = vec![Frame::new(root)];
+
+    loop {
+        // The depth of the current node's children.
+        let child_depth = stack.len();
+        let frame = stack.last_mut().unwrap();
+        if let Some(child) = frame.next_child() {
+            if child_depth == depth {
+                frame.push_digest(&precomputed.next().unwrap());
+            } else {
+                stack.push(Frame::new(child));
+            }
+            continue;
+        }
+
+        let digest = stack.pop().unwrap().finish();
+        match stack.last_mut() {
+            Some(parent) => parent.push_digest(&digest),
+            None => return digest,
+        }
+    }
+}
+
+/// Computes the same digest as `content_hash`, hashing independent subtrees
+/// on the rayon thread pool.
+///
+/// The tree is split at the shallowest level with at least
+/// [`PARALLEL_FRONTIER`] nodes (or its deepest level, if none is that wide).
+/// The subtrees rooted at that level are hashed concurrently, then the levels
+/// above are combined serially. Since a node's digest only depends on its
+/// children's digests, the result is identical to the serial hash.
+#[cfg(feature = "parallel")]
+pub fn content_hash_parallel(root: &Person) -> ContentDigest {
+    use rayon::prelude::*;
+
+    let mut depth = 0;
+    let mut frontier = vec![root];
+    while frontier.len() < PARALLEL_FRONTIER {
+        let next: Vec<&Person> = frontier
+            .iter()
+            .flat_map(|node| node.children.iter())
+            .collect();
+        if next.is_empty() {
+            break;
+        }
+        frontier = next;
+        depth += 1;
+    }
+
+    let digests: Vec<ContentDigest> = frontier.par_iter().map(|node| content_hash(node)).collect();
+    combine(root, depth, digests.into_iter())
+}
+
+/// The number of subtrees `content_hash_parallel` aims to hash concurrently.
+#[cfg(feature = "parallel")]
+pub const PARALLEL_FRONTIER: usize = 256;
+
+/// Computes the content digest and node count of every subtree of a tree.
+///
+/// Entries are indexed by pre-order position, so entry 0 is the whole tree and
+/// the children of the node at position `i` start at `i + 1`, each following
+/// its predecessor's subtree. Like `content_hash`, this is a single iterative
+/// post-order traversal.
+pub fn subtree_digests(root: &Person) -> Vec<(ContentDigest, usize)> {
+    let mut entries: Vec<(ContentDigest, usize)> = Vec::new();
+    // Frames of the nodes whose children are being hashed, with their
+    // pre-order positions.
+    let mut stack = vec![(Frame::new(root), 0)];
+    entries.push(([0; 32], 0));
+
+    loop {
+        let (frame, _) = stack.last_mut().unwrap();
+        if let Some(child) = frame.next_child() {
+            stack.push((Frame::new(child), entries.len()));
+            entries.push(([0; 32], 0));
+            continue;
+        }
+
+        let (frame, position) = stack.pop().unwrap();
+        let digest = frame.finish();
+        entries[position] = (digest, entries.len() - position);
+        match stack.last_mut() {
+            Some((parent, _)) => parent.push_digest(&digest),
+            None => return entries,
+        }
+    }
+}
+
+/// Formats a digest as lowercase hexadecimal.
+pub fn to_hex(digest: &ContentDigest) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::write_varint;
+    use crate::create_balanced_person;
+    use crate::create_chain;
+
+    fn leaf(name: &str, age: u32) -> Person {
+        Person::new(name.to_string(), age, vec![])
+    }
+
+    fn wide(children: usize) -> Person {
+        let children = (0..children)
+            .map(|i| leaf(&format!("c{}", i), i as u32))
+            .collect();
+        Person::new("root".to_string(), 1, children)
+    }
+
+    fn chain(len: usize) -> Person {
+        create_chain(
+            (0..len).map(|i| format!("n{}", i)).collect(),
+            (0..len as u32).collect(),
+        )
+        .unwrap()
+    }
+
+    /// Buffers the documented preimage of each node and hashes it in one shot.
+    fn buffered_hash(node: &Person) -> ContentDigest {
+        let mut preimage = Vec::new();
+        write_varint(&mut preimage, node.name.len() as u64);
+        preimage.extend_from_slice(node.name.as_bytes());
+        write_varint(&mut preimage, u64::from(node.age));
+        write_varint(&mut preimage, node.children.len() as u64);
+        for child in &node.children {
+            preimage.extend_from_slice(&buffered_hash(child));
+        }
+        Sha256::digest(&preimage).into()
+    }
+
+    #[test]
+    fn streaming_hash_matches_the_documented_preimage() {
+        let trees = [
+            leaf("", 0),
+            leaf("ünïcode", u32::MAX),
+            // Names and ages long enough for multi-byte varints.
+            Person::new(
+                "x".repeat(300),
+                1 << 20,
+                vec![leaf("a", 127), leaf("b", 128)],
+            ),
+            wide(200),
+            create_balanced_person(4, 3),
+            chain(500),
+        ];
+        for tree in &trees {
+            assert_eq!(content_hash(tree), buffered_hash(tree));
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_hash_matches_serial_hash() {
+        let uneven = Person::new(
+            "uneven".to_string(),
+            2,
+            vec![
+                leaf("lone", 3),
+                wide(PARALLEL_FRONTIER + 7),
+                leaf("tail", 4),
+            ],
+        );
+        let mut deep_and_wide = chain(1_000);
+        deep_and_wide.children[0]
+            .children
+            .push(wide(PARALLEL_FRONTIER * 2));
+        let trees = [
+            leaf("alone", 0),
+            wide(PARALLEL_FRONTIER - 1),
+            wide(PARALLEL_FRONTIER),
+            wide(PARALLEL_FRONTIER + 1),
+            // Levels of 20 and 400 nodes, so the split is two levels down.
+            create_balanced_person(3, 20),
+            // A deepest level of exactly 256 nodes, then one with more below it.
+            create_balanced_person(8, 2),
+            create_balanced_person(9, 2),
+            uneven,
+            deep_and_wide,
+            chain(100_000),
+        ];
+        for tree in &trees {
+            assert_eq!(content_hash_parallel(tree), content_hash(tree));
+        }
+    }
+}
@@ -0,0 +1,633 @@
+//! Building `Person` trees from Python data, and parsing of individual fields.
+
+use std::collections::HashMap;
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyErr;
+use pyo3::PyResult;
+use pyo3::Python;
+use pyo3::exceptions::PyKeyError;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyBool;
+use pyo3::types::PyDict;
+use pyo3::types::PyDictMethods;
+use pyo3::types::PyMapping;
+use pyo3::types::PyMappingMethods;
+use pyo3::types::PyString;
+use pyo3::types::PyStringMethods;
+use regex::Regex;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+use crate::names;
+use crate::paths::describe;
+
+/// Options controlling how `from_dict` reads its input.
+///
+/// # Key conventions
+///
+/// By default the keys `name`, `age` and `children` are looked up exactly.
+/// With `camel_case` set, every string key of a person dict is first mapped to
+/// snake_case: each ASCII uppercase letter is lowercased and, unless it starts
+/// the key, preceded by `_`. So `fullName` maps to `full_name`, `Name` to
+/// `name` and `childCount` to `child_count`, while snake_case keys map to
+/// themselves. Acronyms are split letter by letter (`userID` maps to
+/// `user_i_d`).
+///
+/// A field is ambiguous when more than one key of the same dict maps to it,
+/// such as `name` and `Name`; this raises a `ValueError` naming the keys rather
+/// than picking one. Keys that map to no field are ignored, as without the
+/// option.
+///
+/// # Limits
+///
+/// `max_children_per_node` rejects input in which any node has more children
+/// than the limit, guarding against fan-out bombs in untrusted payloads. The
+/// `ValueError` names the path of the first offending node in pre-order.
+///
+/// `max_nodes` caps the size of the whole tree. A dict's children list is
+/// counted as soon as the dict is read, before any child is converted, so an
+/// enormous payload is rejected after reading at most about `max_nodes` dicts.
+/// The `ValueError` names the node whose children went over the limit.
+///
+/// # Trimmed names
+///
+/// `trim_names` strips leading and trailing whitespace from every name as it
+/// is read, the most common problem in dirty data, without validating names
+/// any further. Names are trimmed before the name pattern and uniqueness
+/// checks below, so `" Ann"` and `"Ann"` count as the same name.
+///
+/// # Age modulus
+///
+/// `age_modulo` reduces every age modulo the given positive value as it is
+/// read, for simulations in which ages wrap around. The result is the
+/// non-negative remainder, as with Python's `%` and a positive modulus, so it
+/// always lies in `0..age_modulo`: with a modulus of 100, 130 becomes 30 and
+/// -1 becomes 99. Integer ages are reduced before the range check, so any
+/// Python integer is accepted, however large or negative; ISO 8601 ages are
+/// parsed first and then reduced.
+///
+/// # Sentinel ages
+///
+/// `reject_sentinels` rejects any node whose age is one of the given values,
+/// such as `u32::MAX`, which some pipelines use to mean "unknown", catching
+/// data that was not cleaned before ingestion. Ages are compared as stored,
+/// after any `age_modulo` reduction. The `ValueError` names the age and the
+/// node's path.
+///
+/// # Name pattern
+///
+/// `name_pattern` rejects any node whose name the regular expression does not
+/// match, with a `ValueError` naming the name and its path. As with Python's
+/// `re.search`, the pattern may match anywhere in the name unless it is
+/// anchored with `^` and `$`.
+///
+/// # Unique names
+///
+/// `globally_unique_names` requires every name in the tree to be distinct, not
+/// just among siblings, for models that treat names as ids. The `ValueError`
+/// for the first repeat in pre-order names both paths.
+#[derive(Debug, Clone, Default)]
+pub struct FromDictOptions {
+    pub camel_case: bool,
+    pub max_children_per_node: Option<usize>,
+    pub max_nodes: Option<usize>,
+    pub trim_names: bool,
+    pub age_modulo: Option<u32>,
+    pub reject_sentinels: Vec<u32>,
+    pub name_pattern: Option<Regex>,
+    pub globally_unique_names: bool,
+}
+
+impl FromDictOptions {
+    /// The options used when the caller chooses none: no limits or
+    /// transformations, but the process-wide default name pattern, if one is
+    /// set, so that every entry point accepts the same input.
+    pub fn with_default_pattern() -> Self {
+        FromDictOptions {
+            name_pattern: names::default_pattern(),
+            ..FromDictOptions::default()
+        }
+    }
+}
+
+/// Builds a Person tree from nested person dicts.
+///
+/// Any mapping is accepted in place of a dict, at any level: an
+/// `OrderedDict`, a `MappingProxyType` or any object registered as a
+/// `collections.abc.Mapping`. Plain dicts (and their subclasses) are read
+/// directly rather than through the mapping protocol.
+///
+/// The input is walked iteratively in pre-order, so deeply nested dicts cannot
+/// overflow the stack.
+pub fn from_dict(dict: &Bound<'_, PyMapping>, options: &FromDictOptions) -> PyResult<Person> {
+    if options.age_modulo == Some(0) {
+        return Err(PyValueError::new_err("age_modulo must be positive"));
+    }
+    let mut seen = Seen {
+        names: options.globally_unique_names.then(HashMap::new),
+        nodes: 1,
+    };
+    let mut stack = vec![Frame::start(dict, options)?];
+    check_frame(&stack, options, &mut seen)?;
+
+    loop {
+        let frame = stack.last_mut().unwrap();
+        if let Some(child) = frame.pending.next() {
+            let child = Frame::start(child.downcast::<PyMapping>()?, options)?;
+            stack.push(child);
+            check_frame(&stack, options, &mut seen)?;
+            continue;
+        }
+
+        let frame = stack.pop().unwrap();
+        let person = Person::new(frame.name, frame.age, frame.children);
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(person),
+            None => return Ok(person),
+        }
+    }
+}
+
+/// Builds a Person tree from each person dict produced by `items`, any Python
+/// iterable, consuming it lazily.
+///
+/// An item that cannot be converted raises the error `from_dict` would, of
+/// the same type and with the same `code`, but with a message prefixed by the
+/// item's index and the original error as its `__cause__`. Errors raised by
+/// the iterable itself propagate unchanged.
+pub fn from_dicts(items: &Bound<'_, PyAny>, options: &FromDictOptions) -> PyResult<Vec<Person>> {
+    let py = items.py();
+    let mut people = Vec::new();
+    for (index, item) in items.try_iter()?.enumerate() {
+        let item = item?;
+        let person = item
+            .downcast::<PyMapping>()
+            .map_err(PyErr::from)
+            .and_then(|dict| from_dict(dict, options))
+            .map_err(|err| at_index(py, err, index))?;
+        people.push(person);
+    }
+    Ok(people)
+}
+
+/// Re-raises `err` with its message prefixed by `index`, keeping its type and
+/// `code`. If the type cannot be re-created from a message, `err` is returned
+/// as it is.
+fn at_index(py: Python<'_>, err: PyErr, index: usize) -> PyErr {
+    let message = format!("Item {}: {}", index, err.value(py));
+    let Ok(value) = err.get_type(py).call1((message,)) else {
+        return err;
+    };
+    if let Ok(code) = err.value(py).getattr("code") {
+        let _ = value.setattr("code", code);
+    }
+    let tagged = PyErr::from_value(value);
+    tagged.set_cause(py, Some(err));
+    tagged
+}
+
+/// Converts any supported Python value to a Person tree.
+///
+/// A value may be a `Person` (which is copied), a person dict (converted with
+/// `FromDictOptions::with_default_pattern`), or any object with `name`, `age`
+/// and `children` attributes, such as a dataclass or Pydantic model. The
+/// children of such an object may again be any of these, and `None` means no
+/// children. Objects are walked iteratively, so deep object graphs are safe.
+///
+/// The names of such objects must match the default name pattern too; only a
+/// `Person`, already validated when it was built, is taken as it is.
+pub fn from_value(value: &Bound<'_, PyAny>) -> PyResult<Person> {
+    let options = FromDictOptions::with_default_pattern();
+    let mut stack = match start_value(value, &options)? {
+        Started::Done(person) => return Ok(person),
+        Started::Open(frame) => {
+            check_pattern(&frame.name, &options, Vec::new)?;
+            vec![frame]
+        }
+    };
+
+    loop {
+        let frame = stack.last_mut().unwrap();
+        if let Some(child) = frame.pending.next() {
+            match start_value(&child, &options)? {
+                Started::Done(person) => frame.children.push(person),
+                Started::Open(child) => {
+                    // Each open frame's child count so far is the index of the
+                    // frame above it.
+                    check_pattern(&child.name, &options, || {
+                        stack.iter().map(|frame| frame.children.len()).collect()
+                    })?;
+                    stack.push(child);
+                }
+            }
+            continue;
+        }
+
+        let frame = stack.pop().unwrap();
+        let person = Person::new(frame.name, frame.age, frame.children);
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(person),
+            None => return Ok(person),
+        }
+    }
+}
+
+/// The attributes read from duck-typed objects by `from_value`.
+const ATTRIBUTES: [&str; 3] = ["name", "age", "children"];
+
+/// The outcome of starting to convert one value.
+enum Started<'py> {
+    /// The value converted in one step.
+    Done(Person),
+    /// An attribute-style object whose children still need converting.
+    Open(ObjectFrame<'py>),
+}
+
+/// An attribute-style object whose children are still being converted.
+struct ObjectFrame<'py> {
+    name: String,
+    age: u32,
+    pending: std::vec::IntoIter<Bound<'py, PyAny>>,
+    children: Vec<Person>,
+}
+
+fn start_value<'py>(
+    value: &Bound<'py, PyAny>,
+    options: &FromDictOptions,
+) -> PyResult<Started<'py>> {
+    if let Ok(person) = value.extract::<Person>() {
+        return Ok(Started::Done(person));
+    }
+    if let Ok(dict) = value.downcast::<PyMapping>() {
+        return Ok(Started::Done(from_dict(dict, options)?));
+    }
+
+    let mut missing = Vec::new();
+    for attribute in ATTRIBUTES {
+        if !value.hasattr(attribute)? {
+            missing.push(format!("`{}`", attribute));
+        }
+    }
+    if !missing.is_empty() {
+        return Err(ErrorCode::Malformed.err(format!(
+            "Cannot convert {:?} to Person: it is not a Person, not a dict, and has no {} attribute",
+            value,
+            missing.join(" or ")
+        )));
+    }
+
+    let name = names::ingest_name(&value.getattr("name")?)?;
+    let age = ingest_age(&value.getattr("age")?)?;
+    let children = value.getattr("children")?;
+    let pending = if children.is_none() {
+        vec![]
+    } else {
+        children.try_iter()?.collect::<PyResult<Vec<_>>>()?
+    };
+
+    Ok(Started::Open(ObjectFrame {
+        name,
+        age,
+        pending: pending.into_iter(),
+        children: vec![],
+    }))
+}
+
+/// What `from_dict` has read of its input so far.
+struct Seen {
+    /// The path of every name so far, if names must be unique.
+    names: Option<HashMap<String, Vec<usize>>>,
+    /// The number of nodes started, plus those announced by the children
+    /// lists of started nodes.
+    nodes: usize,
+}
+
+/// Checks the most recently started frame against the limits, the sentinel
+/// ages, the name pattern and the uniqueness requirement in `options`,
+/// recording it in
+/// `seen`.
+fn check_frame(stack: &[Frame<'_>], options: &FromDictOptions, seen: &mut Seen) -> PyResult<()> {
+    let frame = stack.last().unwrap();
+    seen.nodes += frame.pending.len();
+    if let Some(max) = options.max_nodes
+        && seen.nodes > max
+    {
+        return Err(ErrorCode::TooManyNodes.err(format!(
+            "Input has more than the maximum of {} nodes: {} counted by {}",
+            max,
+            seen.nodes,
+            describe(&frame_path(stack))
+        )));
+    }
+    if let Some(max) = options.max_children_per_node
+        && frame.pending.len() > max
+    {
+        return Err(ErrorCode::TooManyChildren.err(format!(
+            "Node at {} has {} children, more than the maximum of {}",
+            describe(&frame_path(stack)),
+            frame.pending.len(),
+            max
+        )));
+    }
+    if options.reject_sentinels.contains(&frame.age) {
+        return Err(ErrorCode::InvalidAge.err(format!(
+            "Age {} at {} is a sentinel value",
+            frame.age,
+            describe(&frame_path(stack))
+        )));
+    }
+    check_pattern(&frame.name, options, || frame_path(stack))?;
+    if let Some(seen) = &mut seen.names {
+        let path = frame_path(stack);
+        if let Some(first) = seen.get(&frame.name) {
+            return Err(ErrorCode::DuplicateName.err(format!(
+                "Name {:?} at {} is already used at {}",
+                frame.name,
+                describe(&path),
+                describe(first)
+            )));
+        }
+        seen.insert(frame.name.clone(), path);
+    }
+    Ok(())
+}
+
+/// Fails if `name`, of the node at `path`, does not match the name pattern in
+/// `options`.
+fn check_pattern(
+    name: &str,
+    options: &FromDictOptions,
+    path: impl FnOnce() -> Vec<usize>,
+) -> PyResult<()> {
+    match &options.name_pattern {
+        Some(pattern) if !pattern.is_match(name) => Err(ErrorCode::NamePattern.err(format!(
+            "Name {:?} at {} does not match the pattern {:?}",
+            name,
+            describe(&path()),
+            pattern.as_str()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The path of the most recently started frame.
+fn frame_path(stack: &[Frame<'_>]) -> Vec<usize> {
+    // Each open ancestor is about to receive the frame above it as its next
+    // child, so its child count so far is that frame's index.
+    stack[..stack.len() - 1]
+        .iter()
+        .map(|ancestor| ancestor.children.len())
+        .collect()
+}
+
+/// A person dict whose children are still being converted.
+struct Frame<'py> {
+    name: String,
+    age: u32,
+    /// Children not converted yet. They are only checked to be mappings when
+    /// converted, so that the limits apply before any child is looked at.
+    pending: std::vec::IntoIter<Bound<'py, PyAny>>,
+    children: Vec<Person>,
+}
+
+impl<'py> Frame<'py> {
+    /// Reads the fields of one person dict.
+    fn start(dict: &Bound<'py, PyMapping>, options: &FromDictOptions) -> PyResult<Self> {
+        let mut name = names::ingest_name(&field(dict, "name", options)?)?;
+        if options.trim_names {
+            let trimmed = name.trim();
+            if trimmed.len() != name.len() {
+                name = trimmed.to_owned();
+            }
+        }
+        let age = field(dict, "age", options)?;
+        let age = match options.age_modulo {
+            Some(modulo) => ingest_age_modulo(&age, modulo)?,
+            None => ingest_age(&age)?,
+        };
+        let pending = child_dicts(&field(dict, "children", options)?)?;
+
+        Ok(Frame {
+            name,
+            age,
+            pending: pending.into_iter(),
+            children: vec![],
+        })
+    }
+}
+
+/// Looks up the value of `field` in a person dict, following the key
+/// convention selected by `options`. A missing field raises `KeyError`.
+fn field<'py>(
+    dict: &Bound<'py, PyMapping>,
+    field: &str,
+    options: &FromDictOptions,
+) -> PyResult<Bound<'py, PyAny>> {
+    if !options.camel_case {
+        return dict.as_any().get_item(field);
+    }
+
+    let items: Vec<(Bound<'py, PyAny>, Bound<'py, PyAny>)> = match dict.downcast::<PyDict>() {
+        Ok(dict) => dict.iter().collect(),
+        Err(_) => dict.items()?.extract()?,
+    };
+    let mut found: Vec<(Bound<'py, PyAny>, Bound<'py, PyAny>)> = items
+        .into_iter()
+        .filter(|(key, _)| {
+            key.downcast::<PyString>()
+                .ok()
+                .and_then(|key| key.to_str().ok().map(to_snake_case))
+                .is_some_and(|key| key == field)
+        })
+        .collect();
+
+    match found.len() {
+        0 => Err(PyKeyError::new_err(field.to_string())),
+        1 => Ok(found.pop().unwrap().1),
+        _ => {
+            let keys: Vec<String> = found.iter().map(|(key, _)| format!("{:?}", key)).collect();
+            Err(ErrorCode::Malformed.err(format!(
+                "Ambiguous keys for `{}`: {} all map to it",
+                field,
+                keys.join(", ")
+            )))
+        }
+    }
+}
+
+/// Maps a camelCase or PascalCase key to snake_case, as described on
+/// `FromDictOptions`.
+fn to_snake_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len() + 4);
+    for (i, c) in key.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Extracts the `children` value of a person dict as a list of children, each
+/// expected to be a dict.
+///
+/// Besides a list, this accepts a dict keyed by the integers `0..n`, as left
+/// behind by exports that turned lists into index-keyed objects. Such a dict is
+/// ordered by key; any other set of keys is an error. `None`, as sent by
+/// Pydantic models with optional children, means no children.
+fn child_dicts<'py>(children: &Bound<'py, PyAny>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    if children.is_none() {
+        return Ok(vec![]);
+    }
+    let Ok(by_index) = children.downcast::<PyMapping>() else {
+        return children.extract();
+    };
+
+    let entries: Vec<(Bound<'py, PyAny>, Bound<'py, PyAny>)> = match by_index.downcast::<PyDict>() {
+        Ok(by_index) => by_index.iter().collect(),
+        Err(_) => by_index.items()?.extract()?,
+    };
+    let mut entries = entries
+        .into_iter()
+        .map(|(key, child)| {
+            let index = if key.is_instance_of::<PyBool>() {
+                None
+            } else {
+                key.extract::<usize>().ok()
+            };
+            index.map(|index| (index, child)).ok_or_else(|| {
+                ErrorCode::Malformed.err(format!(
+                    "Children dict keys must be integers, got {:?}",
+                    key
+                ))
+            })
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    entries.sort_by_key(|(index, _)| *index);
+
+    if entries
+        .iter()
+        .enumerate()
+        .any(|(i, (index, _))| i != *index)
+    {
+        let keys: Vec<usize> = entries.iter().map(|(index, _)| *index).collect();
+        return Err(ErrorCode::Malformed.err(format!(
+            "Children dict keys must be the contiguous integers 0..{}, got {:?}",
+            entries.len(),
+            keys
+        )));
+    }
+
+    Ok(entries.into_iter().map(|(_, child)| child).collect())
+}
+
+/// Extracts an age like `ingest_age`, reduced modulo `modulo` as described on
+/// `FromDictOptions`. `modulo` must be positive.
+fn ingest_age_modulo(value: &Bound<'_, PyAny>, modulo: u32) -> PyResult<u32> {
+    if value.is_instance_of::<PyString>() {
+        return Ok(ingest_age(value)? % modulo);
+    }
+    // Python's `%` already gives the non-negative remainder for any integer.
+    value.rem(modulo)?.extract()
+}
+
+/// Extracts an age, given either as an integer or as an ISO 8601 duration.
+///
+/// Durations must be whole years, as written by `to_dict(age_format="iso8601")`:
+/// `P` followed by one or more digits and `Y`, such as `"P30Y"`. Other duration
+/// components (months, days, times) are rejected, as is anything else that is
+/// not a valid age.
+pub fn ingest_age(value: &Bound<'_, PyAny>) -> PyResult<u32> {
+    let Ok(text) = value.downcast::<PyString>() else {
+        return value.extract();
+    };
+
+    let text = text.to_str()?;
+    text.strip_prefix('P')
+        .and_then(|rest| rest.strip_suffix('Y'))
+        .filter(|years| !years.is_empty() && years.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|years| years.parse().ok())
+        .ok_or_else(|| {
+            ErrorCode::InvalidAge.err(format!(
+                "Invalid ISO 8601 age {:?}: expected whole years such as \"P30Y\"",
+                text
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::Python;
+    use pyo3::exceptions::PyTypeError;
+    use pyo3::types::PyDict;
+    use pyo3::types::PyList;
+
+    use super::*;
+    use crate::errors::code_of;
+
+    fn person_dict<'py>(
+        py: Python<'py>,
+        children: impl pyo3::IntoPyObject<'py>,
+    ) -> Bound<'py, PyDict> {
+        let dict = PyDict::new(py);
+        dict.set_item("name", "x").unwrap();
+        dict.set_item("age", 1).unwrap();
+        dict.set_item("children", children).unwrap();
+        dict
+    }
+
+    #[test]
+    fn none_children_mean_no_children() {
+        Python::with_gil(|py| {
+            let dict = person_dict(py, py.None());
+            let person = from_dict(dict.as_mapping(), &FromDictOptions::default()).unwrap();
+            assert_eq!(person, Person::new("x".to_string(), 1, vec![]));
+
+            let root = person_dict(py, PyList::new(py, [dict]).unwrap());
+            let person = from_dict(root.as_mapping(), &FromDictOptions::default()).unwrap();
+            assert_eq!(person.children.len(), 1);
+            assert!(person.children[0].children.is_empty());
+        });
+    }
+
+    #[test]
+    fn string_children_are_a_type_error() {
+        Python::with_gil(|py| {
+            let dict = person_dict(py, "abc");
+            let err = from_dict(dict.as_mapping(), &FromDictOptions::default()).unwrap_err();
+            assert!(err.is_instance_of::<PyTypeError>(py), "{}", err);
+        });
+    }
+
+    #[test]
+    fn max_nodes_aborts_before_converting_wide_children() {
+        Python::with_gil(|py| {
+            // Children that would each fail to convert, so that converting
+            // even the first would raise a different error.
+            let root = person_dict(py, PyList::new(py, 0..100_000).unwrap());
+
+            let options = FromDictOptions {
+                max_nodes: Some(1000),
+                ..FromDictOptions::default()
+            };
+            let err = from_dict(root.as_mapping(), &options).unwrap_err();
+            assert_eq!(code_of(&err), Some(ErrorCode::TooManyNodes as u32));
+            assert!(
+                err.to_string().contains("100001 counted by root"),
+                "{}",
+                err
+            );
+
+            // Without the limit, the first child is converted and fails.
+            let err = from_dict(root.as_mapping(), &FromDictOptions::default()).unwrap_err();
+            assert_ne!(code_of(&err), Some(ErrorCode::TooManyNodes as u32));
+        });
+    }
+}
@@ -0,0 +1,297 @@
+//! Lazy Python iterators over `Person` trees.
+//!
+//! An iterator keeps a reference to the Python `Person` it was created from
+//! and the path of the next node to visit, rather than a copy of the tree.
+//! Each refill borrows the tree, finds that node once and then walks on from
+//! it with a stack of children slices, producing a small batch of items: at
+//! least as many as the node is deep, so the walk down to it costs O(1) per
+//! item. If the tree is mutated during iteration, the next step raises
+//! `RuntimeError`, even if items produced before the mutation are left over.
+
+use std::collections::VecDeque;
+
+use pyo3::Bound;
+use pyo3::Py;
+use pyo3::PyRef;
+use pyo3::PyResult;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::pyclass;
+use pyo3::pymethods;
+use pyo3::types::PyDict;
+use pyo3::types::PyDictMethods;
+use pyo3::types::PyTuple;
+
+use crate::Person;
+
+/// The fewest items produced per refill by the iterators with cheap items.
+const BATCH: usize = 64;
+
+/// A pre-order position in a tree owned by Python, with the items already
+/// produced from the nodes before it.
+struct PreorderCursor<T> {
+    root: Py<Person>,
+    /// The root's version when iteration started.
+    version: u64,
+    /// Path of the next node to visit, or `None` once every node is visited.
+    next: Option<Vec<usize>>,
+    /// Items produced but not yet returned, in order.
+    pending: VecDeque<T>,
+    /// The fewest items to produce per refill.
+    batch: usize,
+}
+
+impl<T> PreorderCursor<T> {
+    fn new(root: &Bound<'_, Person>, batch: usize) -> Self {
+        PreorderCursor {
+            root: root.clone().unbind(),
+            version: root.borrow().version(),
+            next: Some(vec![]),
+            pending: VecDeque::new(),
+            batch,
+        }
+    }
+
+    /// Returns the next item in pre-order, refilling from `root` with `f`
+    /// applied to each node's path and node when none is pending.
+    fn advance(
+        &mut self,
+        root: &Person,
+        f: impl FnMut(&[usize], &Person) -> PyResult<T>,
+    ) -> PyResult<Option<T>> {
+        if self.pending.is_empty() && self.next.is_none() {
+            return Ok(None);
+        }
+        if root.version() != self.version {
+            return Err(PyRuntimeError::new_err("Person changed during iteration"));
+        }
+        if self.pending.is_empty() {
+            self.refill(root, f)?;
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn refill(
+        &mut self,
+        root: &Person,
+        mut f: impl FnMut(&[usize], &Person) -> PyResult<T>,
+    ) -> PyResult<()> {
+        let Some(path) = self.next.take() else {
+            return Ok(());
+        };
+        let limit = self.batch.max(path.len());
+
+        // The siblings of each node from the root down to the current one,
+        // with its index among them. The root is the only node at its level,
+        // so `indices[1..]` is the current node's path.
+        let mut levels: Vec<&[Person]> = vec![std::slice::from_ref(root)];
+        let mut indices = vec![0];
+        for &index in &path {
+            // The path was found in this version of the tree, so it is in range.
+            let node = &levels[levels.len() - 1][indices[indices.len() - 1]];
+            levels.push(&node.children);
+            indices.push(index);
+        }
+
+        for _ in 0..limit {
+            let node = &levels[levels.len() - 1][indices[indices.len() - 1]];
+            self.pending.push_back(f(&indices[1..], node)?);
+
+            // The successor is the first child, or else the next sibling of
+            // the nearest ancestor that has one.
+            if !node.children.is_empty() {
+                levels.push(&node.children);
+                indices.push(0);
+                continue;
+            }
+            loop {
+                let last = indices.len() - 1;
+                indices[last] += 1;
+                if indices[last] < levels[last].len() {
+                    break;
+                }
+                levels.pop();
+                indices.pop();
+                if indices.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+
+        self.next = Some(indices[1..].to_vec());
+        Ok(())
+    }
+}
+
+/// Iterates over the names of a tree in pre-order: each node comes before its
+/// children, and children are visited in order.
+#[pyclass]
+pub struct NameIterator {
+    cursor: PreorderCursor<String>,
+}
+
+impl NameIterator {
+    pub fn new(root: &Bound<'_, Person>) -> Self {
+        NameIterator {
+            cursor: PreorderCursor::new(root, BATCH),
+        }
+    }
+}
+
+#[pymethods]
+impl NameIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: pyo3::Python<'_>) -> PyResult<Option<String>> {
+        let root = self.cursor.root.clone_ref(py);
+        let root = root.bind(py).try_borrow()?;
+        self.cursor.advance(&root, |_, node| Ok(node.name.clone()))
+    }
+}
+
+/// Iterates over a tree in pre-order, yielding one flat dict per node.
+///
+/// Each dict holds the node's `name` and `age`, its `path` from the root as a
+/// list of child indices, and its `child_count`. Children are not nested, so
+/// only one node's data is held at a time.
+#[pyclass]
+pub struct DictIterator {
+    cursor: PreorderCursor<Py<PyDict>>,
+}
+
+impl DictIterator {
+    pub fn new(root: &Bound<'_, Person>) -> Self {
+        DictIterator {
+            cursor: PreorderCursor::new(root, BATCH),
+        }
+    }
+}
+
+#[pymethods]
+impl DictIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: pyo3::Python<'_>) -> PyResult<Option<Py<PyDict>>> {
+        let root = self.cursor.root.clone_ref(py);
+        let root = root.bind(py).try_borrow()?;
+        self.cursor.advance(&root, |path, node| {
+            let dict = PyDict::new(py);
+            dict.set_item("name", &node.name)?;
+            dict.set_item("age", node.age)?;
+            dict.set_item("path", path)?;
+            dict.set_item("child_count", node.children.len())?;
+            Ok(dict.unbind())
+        })
+    }
+}
+
+/// Iterates over a tree in pre-order, yielding `(path, person)` pairs.
+///
+/// Nodes come in the same order as from `NameIterator`: each node before its
+/// children, and children in order. `path` is a tuple of child indices from
+/// the root, so the root comes first as `()`, and `person` is a copy of the
+/// subtree at that path. Since copying a node's subtree costs at least as
+/// much as finding it, pairs are produced one at a time rather than batched.
+#[pyclass]
+pub struct PathIterator {
+    cursor: PreorderCursor<(Py<PyTuple>, Person)>,
+}
+
+impl PathIterator {
+    pub fn new(root: &Bound<'_, Person>) -> Self {
+        PathIterator {
+            cursor: PreorderCursor::new(root, 1),
+        }
+    }
+}
+
+#[pymethods]
+impl PathIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: pyo3::Python<'_>) -> PyResult<Option<(Py<PyTuple>, Person)>> {
+        let root = self.cursor.root.clone_ref(py);
+        let root = root.bind(py).try_borrow()?;
+        self.cursor.advance(&root, |path, node| {
+            Ok((PyTuple::new(py, path)?.unbind(), node.clone()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::Python;
+
+    use super::*;
+    use crate::create_balanced_person;
+    use crate::create_chain;
+
+    /// Names in pre-order, collected with an explicit stack.
+    fn preorder_names(root: &Person) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            names.push(node.name.clone());
+            stack.extend(node.children.iter().rev());
+        }
+        names
+    }
+
+    fn collect_names(iterator: &mut NameIterator, py: Python<'_>) -> Vec<String> {
+        std::iter::from_fn(|| iterator.__next__(py).unwrap()).collect()
+    }
+
+    #[test]
+    fn visits_every_node_in_preorder() {
+        let mut balanced = create_balanced_person(4, 3);
+        let mut counter = 0;
+        let mut stack = vec![&mut balanced];
+        while let Some(node) = stack.pop() {
+            node.name = format!("n{}", counter);
+            counter += 1;
+            stack.extend(node.children.iter_mut());
+        }
+        let chain = create_chain(
+            (0..2_000).map(|i| format!("n{}", i)).collect(),
+            vec![1; 2_000],
+        )
+        .unwrap();
+
+        Python::with_gil(|py| {
+            for tree in [Person::new("alone".to_string(), 1, vec![]), balanced, chain] {
+                let expected = preorder_names(&tree);
+                let object = Bound::new(py, tree).unwrap();
+                assert_eq!(collect_names(&mut NameIterator::new(&object), py), expected);
+
+                let mut paths = PathIterator::new(&object);
+                let mut count = 0;
+                while let Some((path, person)) = paths.__next__(py).unwrap() {
+                    let path: Vec<usize> = path.extract(py).unwrap();
+                    let root = object.borrow();
+                    assert!(*crate::paths::node_at(&root, &path).unwrap() == person);
+                    count += 1;
+                }
+                assert_eq!(count, expected.len());
+            }
+        });
+    }
+
+    #[test]
+    fn mutation_stops_iteration_even_with_items_left() {
+        Python::with_gil(|py| {
+            let object = Bound::new(py, create_balanced_person(2, 3)).unwrap();
+            let mut names = NameIterator::new(&object);
+            assert_eq!(names.__next__(py).unwrap().as_deref(), Some("root"));
+            assert!(!names.cursor.pending.is_empty());
+
+            object.borrow_mut().mark_changed();
+            let err = names.__next__(py).unwrap_err();
+            assert!(err.is_instance_of::<PyRuntimeError>(py));
+        });
+    }
+}
@@ -0,0 +1,485 @@
+//! Conversions between Python objects and `serde_json` values, and between
+//! `Person` trees and JSON text.
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyResult;
+use pyo3::exceptions::PyTypeError;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyBool;
+use pyo3::types::PyBoolMethods;
+use pyo3::types::PyDict;
+use pyo3::types::PyDictMethods;
+use pyo3::types::PyFloat;
+use pyo3::types::PyFloatMethods;
+use pyo3::types::PyInt;
+use pyo3::types::PyList;
+use pyo3::types::PyListMethods;
+use pyo3::types::PyString;
+use pyo3::types::PyStringMethods;
+use pyo3::types::PyTuple;
+use pyo3::types::PyTupleMethods;
+use pyo3::types::PyTypeMethods;
+use serde_json::Map;
+use serde_json::Number;
+use serde_json::Value;
+
+use crate::Person;
+use crate::Wrapper;
+use crate::errors::ErrorCode;
+use crate::paths::describe;
+
+/// Serializes a person tree as JSON text, in the same shape as its dict form.
+///
+/// With `indent` unset the output is compact, with no whitespace at all. With
+/// `indent` set, every object entry and array element goes on its own line,
+/// indented by that many spaces per level, which matches Python's
+/// `json.dumps(person.to_dict(), indent=indent, ensure_ascii=False)`.
+///
+/// The text is written directly by an iterative traversal, rather than via a
+/// `serde_json::Value`, so deep trees cannot overflow the stack.
+pub fn person_to_json(root: &Person, indent: Option<usize>) -> String {
+    enum Step<'a> {
+        Node(&'a Person, usize),
+        Text(String),
+    }
+
+    let newline = |level: usize| match indent {
+        Some(width) => format!("\n{}", " ".repeat(width * level)),
+        None => String::new(),
+    };
+    let key_separator = if indent.is_some() { ": " } else { ":" };
+
+    let mut out = String::new();
+    let mut stack = vec![Step::Node(root, 0)];
+    while let Some(step) = stack.pop() {
+        let (node, level) = match step {
+            Step::Text(text) => {
+                out.push_str(&text);
+                continue;
+            }
+            Step::Node(node, level) => (node, level),
+        };
+
+        // Serializing a string cannot fail.
+        let name = serde_json::to_string(&node.name).unwrap();
+        out.push('{');
+        for (key, value) in [("name", name), ("age", node.age.to_string())] {
+            out.push_str(&newline(level + 1));
+            out.push_str(&format!("\"{}\"{}{},", key, key_separator, value));
+        }
+        out.push_str(&newline(level + 1));
+        out.push_str(&format!("\"children\"{}", key_separator));
+
+        if node.children.is_empty() {
+            out.push_str("[]");
+            out.push_str(&newline(level));
+            out.push('}');
+            continue;
+        }
+        out.push('[');
+        stack.push(Step::Text(format!(
+            "{}]{}}}",
+            newline(level + 1),
+            newline(level)
+        )));
+        for (index, child) in node.children.iter().enumerate().rev() {
+            stack.push(Step::Node(child, level + 2));
+            let separator = if index > 0 { "," } else { "" };
+            stack.push(Step::Text(format!("{}{}", separator, newline(level + 2))));
+        }
+    }
+
+    out
+}
+
+/// Parses a person tree from JSON text in its dict form, the inverse of
+/// `person_to_json`.
+///
+/// Every node must be an object with a string `name`, an integer `age` and a
+/// `children` array, which may also be `null` for no children; other keys are
+/// ignored, as by `from_dict`. Errors name the path of the offending node.
+///
+/// The text is parsed without recursion, so trees of any depth round-trip.
+pub fn person_from_json(text: &str) -> PyResult<Person> {
+    struct Frame {
+        name: String,
+        age: u32,
+        // The children still to parse, last first.
+        pending: Released,
+        children: Vec<Person>,
+    }
+
+    fn start(value: Value, stack: &[Frame]) -> PyResult<Frame> {
+        let error = |code: ErrorCode, message: &str| {
+            // Each open frame is about to receive this node as its next child.
+            let path: Vec<usize> = stack.iter().map(|frame| frame.children.len()).collect();
+            code.err(format!("{}: {}", describe(&path), message))
+        };
+
+        let mut map = match value {
+            Value::Object(map) => map,
+            other => {
+                drop(Released(vec![other]));
+                return Err(error(ErrorCode::Malformed, "expected an object"));
+            }
+        };
+        // A missing field reads as `null`, which is only valid for `children`.
+        let mut fields = Released(
+            ["name", "age", "children"]
+                .iter()
+                .map(|&key| map.remove(key).unwrap_or(Value::Null))
+                .collect(),
+        );
+        drop(Released(map.into_iter().map(|(_, value)| value).collect()));
+
+        let name = match &mut fields.0[0] {
+            Value::String(name) => std::mem::take(name),
+            _ => return Err(error(ErrorCode::MissingField, "missing string `name`")),
+        };
+        let age = match &fields.0[1] {
+            Value::Number(age) => age
+                .as_u64()
+                .and_then(|age| u32::try_from(age).ok())
+                .ok_or_else(|| {
+                    error(
+                        ErrorCode::InvalidAge,
+                        &format!("age {} is out of range", age),
+                    )
+                })?,
+            _ => return Err(error(ErrorCode::MissingField, "missing integer `age`")),
+        };
+        let pending = match &mut fields.0[2] {
+            Value::Array(children) => std::mem::take(children),
+            Value::Null => vec![],
+            _ => return Err(error(ErrorCode::MissingField, "missing array `children`")),
+        };
+
+        Ok(Frame {
+            name,
+            age,
+            pending: Released(pending.into_iter().rev().collect()),
+            children: vec![],
+        })
+    }
+
+    let value = parse_unbounded(text)
+        .map_err(|err| ErrorCode::Malformed.err(format!("Invalid JSON: {}", err)))?;
+    let mut stack = vec![start(value, &[])?];
+
+    loop {
+        let frame = stack.last_mut().unwrap();
+        if let Some(child) = frame.pending.0.pop() {
+            let child = start(child, &stack)?;
+            stack.push(child);
+            continue;
+        }
+
+        let frame = stack.pop().unwrap();
+        let person = Person::new(frame.name, frame.age, frame.children);
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(person),
+            None => return Ok(person),
+        }
+    }
+}
+
+/// A container still being parsed by `parse_unbounded`.
+enum Open {
+    Array(Vec<Value>),
+    /// An object and the key of the value being parsed.
+    Object(Map<String, Value>, String),
+}
+
+impl Open {
+    fn into_value(self) -> Value {
+        match self {
+            Open::Array(items) => Value::Array(items),
+            Open::Object(map, _) => Value::Object(map),
+        }
+    }
+}
+
+/// Parses JSON text of any nesting depth into a value.
+///
+/// Containers are tracked on an explicit stack rather than by recursion, and
+/// dropped iteratively if the text is invalid. Strings and numbers are
+/// delegated to `serde_json`, so they are accepted exactly as it accepts them.
+fn parse_unbounded(text: &str) -> Result<Value, String> {
+    let mut stack = Vec::new();
+    let result = parse_into(text, &mut stack);
+    drop(Released(stack.into_iter().map(Open::into_value).collect()));
+    result
+}
+
+fn parse_into(text: &str, stack: &mut Vec<Open>) -> Result<Value, String> {
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+    let skip_whitespace = |pos: &mut usize| {
+        while bytes
+            .get(*pos)
+            .is_some_and(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            *pos += 1;
+        }
+    };
+    let error = |message: &str, pos: usize| format!("{} at byte {}", message, pos);
+    let parse_string = |pos: &mut usize| -> Result<String, String> {
+        let start = *pos;
+        let mut end = start + 1;
+        loop {
+            match bytes.get(end) {
+                Some(b'"') => break,
+                Some(b'\\') => end += 2,
+                Some(_) => end += 1,
+                None => return Err(error("unterminated string", start)),
+            }
+        }
+        *pos = end + 1;
+        serde_json::from_str(&text[start..=end]).map_err(|err| error(&err.to_string(), start))
+    };
+    let parse_key = |pos: &mut usize| -> Result<String, String> {
+        skip_whitespace(pos);
+        if bytes.get(*pos) != Some(&b'"') {
+            return Err(error("expected a string key", *pos));
+        }
+        let key = parse_string(pos)?;
+        skip_whitespace(pos);
+        if bytes.get(*pos) != Some(&b':') {
+            return Err(error("expected `:`", *pos));
+        }
+        *pos += 1;
+        Ok(key)
+    };
+
+    loop {
+        skip_whitespace(&mut pos);
+        let start = pos;
+        let mut value = match bytes.get(pos) {
+            Some(b'[') => {
+                pos += 1;
+                skip_whitespace(&mut pos);
+                if bytes.get(pos) != Some(&b']') {
+                    stack.push(Open::Array(vec![]));
+                    continue;
+                }
+                pos += 1;
+                Value::Array(vec![])
+            }
+            Some(b'{') => {
+                pos += 1;
+                skip_whitespace(&mut pos);
+                if bytes.get(pos) != Some(&b'}') {
+                    let key = parse_key(&mut pos)?;
+                    stack.push(Open::Object(Map::new(), key));
+                    continue;
+                }
+                pos += 1;
+                Value::Object(Map::new())
+            }
+            Some(b'"') => Value::String(parse_string(&mut pos)?),
+            Some(b'-' | b'0'..=b'9') => {
+                while bytes
+                    .get(pos)
+                    .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+                {
+                    pos += 1;
+                }
+                let number: Number = serde_json::from_str(&text[start..pos])
+                    .map_err(|_| error("invalid number", start))?;
+                Value::Number(number)
+            }
+            _ => {
+                let (literal, value) = [
+                    ("true", Value::Bool(true)),
+                    ("false", Value::Bool(false)),
+                    ("null", Value::Null),
+                ]
+                .into_iter()
+                .find(|(literal, _)| text[pos..].starts_with(literal))
+                .ok_or_else(|| error("expected a value", pos))?;
+                pos += literal.len();
+                value
+            }
+        };
+
+        // Add the finished value to its container, closing every container
+        // that it completes.
+        loop {
+            skip_whitespace(&mut pos);
+            match stack.last_mut() {
+                None if pos == bytes.len() => return Ok(value),
+                None => {
+                    drop(Released(vec![value]));
+                    return Err(error("trailing characters", pos));
+                }
+                Some(Open::Array(items)) => {
+                    items.push(value);
+                    match bytes.get(pos) {
+                        Some(b',') => pos += 1,
+                        Some(b']') => {
+                            pos += 1;
+                            value = stack.pop().unwrap().into_value();
+                            continue;
+                        }
+                        _ => return Err(error("expected `,` or `]`", pos)),
+                    }
+                }
+                Some(Open::Object(map, key)) => {
+                    if let Some(replaced) = map.insert(std::mem::take(key), value) {
+                        drop(Released(vec![replaced]));
+                    }
+                    match bytes.get(pos) {
+                        Some(b',') => {
+                            pos += 1;
+                            *key = parse_key(&mut pos)?;
+                        }
+                        Some(b'}') => {
+                            pos += 1;
+                            value = stack.pop().unwrap().into_value();
+                            continue;
+                        }
+                        _ => return Err(error("expected `,` or `}`", pos)),
+                    }
+                }
+            }
+            break;
+        }
+    }
+}
+
+/// JSON values that are dropped iteratively, so that deeply nested ones
+/// cannot overflow the stack.
+struct Released(Vec<Value>);
+
+impl Drop for Released {
+    fn drop(&mut self) {
+        while let Some(value) = self.0.pop() {
+            match value {
+                Value::Array(items) => self.0.extend(items),
+                Value::Object(map) => self.0.extend(map.into_iter().map(|(_, value)| value)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Converts a Python object into the equivalent JSON value.
+///
+/// Supports `None`, `bool`, `int`, `float`, `str`, lists, tuples, dicts with
+/// string keys and `Person` instances (converted through their dict form).
+/// Anything else raises a `TypeError`.
+pub fn py_to_json(value: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if value.is_none() {
+        return Ok(Value::Null);
+    }
+    // `bool` is a subclass of `int`, so it has to be checked first.
+    if let Ok(b) = value.downcast::<PyBool>() {
+        return Ok(Value::Bool(b.is_true()));
+    }
+    if value.is_instance_of::<PyInt>() {
+        if let Ok(i) = value.extract::<i64>() {
+            return Ok(Value::from(i));
+        }
+        if let Ok(u) = value.extract::<u64>() {
+            return Ok(Value::from(u));
+        }
+        return Err(PyValueError::new_err(format!(
+            "Integer {} does not fit in 64 bits",
+            value
+        )));
+    }
+    if let Ok(f) = value.downcast::<PyFloat>() {
+        return Number::from_f64(f.value())
+            .map(Value::Number)
+            .ok_or_else(|| PyValueError::new_err(format!("{} has no JSON representation", f)));
+    }
+    if let Ok(s) = value.downcast::<PyString>() {
+        return Ok(Value::String(s.to_str()?.to_owned()));
+    }
+    if let Ok(list) = value.downcast::<PyList>() {
+        return list.iter().map(|item| py_to_json(&item)).collect();
+    }
+    if let Ok(tuple) = value.downcast::<PyTuple>() {
+        return tuple.iter().map(|item| py_to_json(&item)).collect();
+    }
+    if let Ok(dict) = value.downcast::<PyDict>() {
+        let mut map = Map::new();
+        for (key, item) in dict.iter() {
+            let key = key.downcast::<PyString>().map_err(|_| {
+                PyTypeError::new_err(format!("JSON object keys must be str, not {}", key))
+            })?;
+            map.insert(key.to_str()?.to_owned(), py_to_json(&item)?);
+        }
+        return Ok(Value::Object(map));
+    }
+    if let Ok(person) = value.extract::<Person>() {
+        return py_to_json(person.to_dict_with_py(value.py())?.as_any());
+    }
+
+    Err(PyTypeError::new_err(format!(
+        "Cannot convert {} to JSON",
+        value.get_type().name()?
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::Python;
+
+    fn chain(depth: usize) -> Person {
+        let mut node = Person::new("leaf".to_string(), 0, vec![]);
+        for age in 1..=depth {
+            node = Person::new(format!("n{}", age), age as u32, vec![node]);
+        }
+        node
+    }
+
+    #[test]
+    fn deep_trees_round_trip() {
+        for depth in [0, 63, 64, 200, 100_000] {
+            let root = chain(depth);
+            let parsed = person_from_json(&person_to_json(&root, None)).unwrap();
+            assert!(parsed == root, "depth {}", depth);
+        }
+    }
+
+    #[test]
+    fn parses_like_serde_json() {
+        for text in [
+            r#" { "a" : [1, -2.5e3, true, false, null, "x\"\u00e9\ud83d\ude00"], "b": {}, "a": [] } "#,
+            "[]",
+            "0",
+        ] {
+            let expected: Value = serde_json::from_str(text).unwrap();
+            assert_eq!(parse_unbounded(text).unwrap(), expected, "{}", text);
+        }
+        for text in [
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "01",
+            "[1] 2",
+            "\"abc",
+            "nul",
+            "{1: 2}",
+        ] {
+            assert!(serde_json::from_str::<Value>(text).is_err(), "{}", text);
+            assert!(parse_unbounded(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn deep_invalid_json_is_rejected() {
+        let text = format!("{}{}", "[".repeat(100_000), "]".repeat(99_999));
+        assert!(Python::with_gil(|_| person_from_json(&text).is_err()));
+
+        let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        let text = format!(r#"{{"name":"x","age":1,"children":[],"extra":{}}}"#, nested);
+        assert!(person_from_json(&text).is_ok());
+        let text = format!(r#"{{"name":{},"age":1,"children":[]}}"#, nested);
+        assert!(Python::with_gil(|_| person_from_json(&text).is_err()));
+    }
+}
@@ -1,32 +1,279 @@
+mod anonymize;
+mod cache;
+mod compact;
+mod cow;
+mod csv_format;
+mod decode_cache;
+mod diff;
+mod edit;
+mod errors;
+mod export;
+mod flatten;
+mod forest;
+mod graph;
+mod hash;
+mod ingest;
+mod iter;
+mod json;
+mod msgpack;
+mod msgpack_gz;
+mod msgpack_log;
+mod names;
+mod patch;
+mod paths;
+mod person_rng;
+mod query;
+mod rebalance;
+mod render;
+mod schema;
+mod sexpr;
+mod stats;
+mod toml_format;
+mod view;
+mod warnings;
+mod zipper;
+
 use std::ffi::CString;
+use std::fmt::Debug;
 use std::fmt::Display;
 
 use pyo3::Bound;
+use pyo3::IntoPyObject;
 use pyo3::Py;
 use pyo3::PyAny;
 use pyo3::PyResult;
+use pyo3::PyTraverseError;
+use pyo3::PyVisit;
+use pyo3::buffer::PyBuffer;
 use pyo3::pyclass;
 use pyo3::pyfunction;
 use pyo3::pymethods;
 use pyo3::types::PyAnyMethods;
+use pyo3::types::PyBytes;
 use pyo3::types::PyDict;
+use pyo3::types::PyDictMethods;
+use pyo3::types::PyMapping;
 use pyo3::types::PyModuleMethods;
 use pyo3::{Python, types::PyModule, wrap_pyfunction};
 use rand::Rng;
 use rand::rng;
+
+use crate::cache::NodeState;
+
 /// Define the Person struct as a Python class.
-#[pyclass(dict, eq, str)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// With the `serde` feature, `Person` also implements `Serialize` and
+/// `Deserialize` as a struct of `name`, `age` and `children`, the same shape as
+/// its dict form. Deserialized trees start with fresh bookkeeping state.
+#[pyclass(dict, str, module = "py03_pydantic_ormsgpack_experiment")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Person {
     /// The person's name.
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     pub name: String,
     /// The person's age.
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     pub age: u32,
 
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     pub children: Vec<Person>,
+
+    /// Mutation version and derived-value caches; not part of the value.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    state: NodeState,
+}
+
+impl Person {
+    /// Creates a Person with the given fields and fresh bookkeeping state.
+    pub fn new(name: String, age: u32, children: Vec<Person>) -> Self {
+        Person {
+            name,
+            age,
+            children,
+            state: NodeState::default(),
+        }
+    }
+
+    /// Records an in-place mutation, invalidating cached derived values.
+    ///
+    /// Every method that mutates a tree through `&mut self` must call this.
+    pub fn mark_changed(&mut self) {
+        self.state.bump();
+    }
+
+    /// The number of mutations recorded by `mark_changed`.
+    pub fn version(&self) -> u64 {
+        self.state.version()
+    }
+
+    /// Returns the content hash of this tree, computing it only if it is not
+    /// already cached for the current version.
+    pub fn structural_hash(&self) -> hash::ContentDigest {
+        if let Some(digest) = self.state.cached_hash() {
+            return digest;
+        }
+        let digest = hash::content_hash(self);
+        self.state.store_hash(digest);
+        digest
+    }
+
+    /// Returns the depth and descendant count of this tree, computing them
+    /// only if they are not already cached for the current version.
+    pub fn shape(&self) -> stats::Shape {
+        if let Some(shape) = self.state.cached_shape() {
+            return shape;
+        }
+        let shape = stats::Shape::compute(self);
+        self.state.store_shape(shape);
+        shape
+    }
+
+    /// Calls the observer set with `set_on_change`, if any, with a
+    /// `{"op": op, "value": value}` dict describing a completed change.
+    ///
+    /// The person is not borrowed during the call, so the observer may read it.
+    fn notify<'py>(
+        slf: &Bound<'py, Self>,
+        op: &str,
+        value: impl IntoPyObject<'py>,
+    ) -> PyResult<()> {
+        let py = slf.py();
+        let Some(observer) = slf.borrow().state.observer(py) else {
+            return Ok(());
+        };
+        let change = PyDict::new(py);
+        change.set_item("op", op)?;
+        change.set_item("value", value)?;
+        observer.call1(py, (change,))?;
+        Ok(())
+    }
+}
+
+impl Drop for Person {
+    /// Drops descendants iteratively, so that very deep trees such as long
+    /// chains cannot overflow the stack by recursing through `children`.
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.children);
+        while let Some(mut node) = pending.pop() {
+            pending.append(&mut node.children);
+        }
+    }
+}
+
+impl PartialEq for Person {
+    /// Compares two trees field by field, walking pairs of nodes with an
+    /// explicit stack so that deep trees cannot overflow it.
+    ///
+    /// If both nodes of a pair already have a cached content hash and the
+    /// hashes differ, the subtrees are known to differ without being walked.
+    /// Hashes are never computed here; see `__eq__` for the comparison that
+    /// does.
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if let (Some(x), Some(y)) = (a.state.cached_hash(), b.state.cached_hash())
+                && x != y
+            {
+                return false;
+            }
+            if a.name != b.name || a.age != b.age || a.children.len() != b.children.len() {
+                return false;
+            }
+            stack.extend(a.children.iter().zip(&b.children));
+        }
+        true
+    }
+}
+
+impl Eq for Person {}
+
+impl Clone for Person {
+    /// Copies the tree bottom-up with an explicit stack, so that very deep
+    /// trees cannot overflow the stack. Each copy gets the bookkeeping state
+    /// `NodeState::clone` gives it.
+    fn clone(&self) -> Self {
+        // Each frame is a node with its children copied so far.
+        let mut stack: Vec<(&Person, Vec<Person>)> =
+            vec![(self, Vec::with_capacity(self.children.len()))];
+        loop {
+            let (node, copied) = stack.last_mut().unwrap();
+            if let Some(child) = node.children.get(copied.len()) {
+                stack.push((child, Vec::with_capacity(child.children.len())));
+                continue;
+            }
+            let (node, children) = stack.pop().unwrap();
+            let copy = Person {
+                name: node.name.clone(),
+                age: node.age,
+                children,
+                state: node.state.clone(),
+            };
+            match stack.last_mut() {
+                Some((_, siblings)) => siblings.push(copy),
+                None => return copy,
+            }
+        }
+    }
+}
+
+impl Debug for Person {
+    /// Writes the same text as a derived `Debug` of `name`, `age` and
+    /// `children`, in both the `{:?}` and `{:#?}` forms, but iteratively, so
+    /// that very deep trees cannot overflow the stack.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        enum Step<'a> {
+            Node(&'a Person, usize),
+            Text(String),
+        }
+
+        let pretty = f.alternate();
+        let indent = |level: usize| " ".repeat(4 * level);
+        let mut stack = vec![Step::Node(self, 0)];
+        while let Some(step) = stack.pop() {
+            let (node, level) = match step {
+                Step::Text(text) => {
+                    f.write_str(&text)?;
+                    continue;
+                }
+                Step::Node(node, level) => (node, level),
+            };
+
+            if !pretty {
+                write!(
+                    f,
+                    "Person {{ name: {:?}, age: {:?}, children: [",
+                    node.name, node.age
+                )?;
+                stack.push(Step::Text("] }".to_string()));
+                for (index, child) in node.children.iter().enumerate().rev() {
+                    stack.push(Step::Node(child, level + 1));
+                    if index > 0 {
+                        stack.push(Step::Text(", ".to_string()));
+                    }
+                }
+                continue;
+            }
+
+            let inner = indent(level + 1);
+            write!(
+                f,
+                "Person {{\n{inner}name: {:?},\n{inner}age: {:?},\n{inner}children: [",
+                node.name, node.age
+            )?;
+            if node.children.is_empty() {
+                write!(f, "],\n{}}}", indent(level))?;
+                continue;
+            }
+            f.write_str("\n")?;
+            stack.push(Step::Text(format!("{inner}],\n{}}}", indent(level))));
+            for child in node.children.iter().rev() {
+                stack.push(Step::Text(",\n".to_string()));
+                stack.push(Step::Node(child, level + 2));
+                stack.push(Step::Text(indent(level + 2)));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A trait for converting Rust structs to/from Python dictionaries.
@@ -58,22 +305,9 @@ pub trait Wrapper {
 impl Wrapper for Person {
     /// Converts a Person instance to a Python dictionary.
     ///
-    /// This recursively converts all children to dictionaries as well.
-
+    /// This converts all children to dictionaries as well.
     fn to_dict_with_py<'a>(&'a self, py: Python<'a>) -> PyResult<Bound<'a, PyDict>> {
-        let dict = PyDict::new(py);
-        dict.set_item("name", self.name.clone())?;
-        dict.set_item("age", self.age)?;
-        dict.set_item(
-            "children",
-            self.children
-                .iter()
-                .map(|c| c.to_dict_with_py(py))
-                .collect::<PyResult<Vec<_>>>()?,
-        )?;
-
-        //dbg!(&dict);
-        Ok(dict)
+        export::to_dict(self, py, &export::DictOptions::default())
     }
 
     /// Converts a Person to a Python dictionary by acquiring the GIL.
@@ -86,95 +320,1155 @@ impl Wrapper for Person {
 
     /// Creates a Person instance from a Python dictionary.
     ///
-    /// This recursively converts all children dictionaries to Person instances.
+    /// This converts all children dictionaries to Person instances as well.
     fn from_dict(dict: &Bound<'_, PyDict>) -> PyResult<Self> {
-        let name: String = dict.get_item("name")?.extract()?;
-        let age: u32 = dict.get_item("age")?.extract()?;
-        let children: Vec<Bound<'_, PyDict>> = dict.get_item("children")?.extract()?;
-        let children: Vec<Person> = children
+        ingest::from_dict(
+            dict.as_mapping(),
+            &ingest::FromDictOptions::with_default_pattern(),
+        )
+    }
+
+    /// Validates and converts a Python object to a Person instance.
+    ///
+    /// This method attempts to convert the input to a Person instance in the following order:
+    /// 1. Direct extraction of a Person instance
+    /// 2. Conversion from a dictionary
+    /// 3. Reading `name`, `age` and `children` attributes, as found on
+    ///    dataclasses, Pydantic models and similar objects
+    /// 4. If none of these works, it returns an error naming what was tried
+    fn validate(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        ingest::from_value(value)
+    }
+}
+
+#[pymethods]
+impl Person {
+    /// The attributes matched by positional patterns, so that
+    /// `case Person(name, age, children):` works in a `match` statement.
+    #[classattr]
+    fn __match_args__() -> (&'static str, &'static str, &'static str) {
+        ("name", "age", "children")
+    }
+
+    /// Creates a Person from keyword arguments, e.g. `Person(name="Ann", age=30)`.
+    ///
+    /// # Arguments
+    /// * `name` - The person's name, accepted exactly as by the `name` setter
+    ///   and `from_dict`
+    /// * `age` - The person's age
+    /// * `children` - The person's children, empty if omitted
+    #[new]
+    #[pyo3(signature = (*, name, age, children = None))]
+    fn py_new(name: &Bound<'_, PyAny>, age: u32, children: Option<Vec<Person>>) -> PyResult<Self> {
+        let name = names::ingest_name(name)?;
+        Ok(Person::new(name, age, children.unwrap_or_default()))
+    }
+
+    /// Adds a child to this person's children.
+    ///
+    /// # Arguments
+    /// * `child` - A Person instance representing a child to add
+    fn add_child(slf: &Bound<'_, Self>, child: Person) -> PyResult<()> {
+        {
+            let mut this = slf.borrow_mut();
+            this.children.push(child.clone());
+            this.mark_changed();
+        }
+        Person::notify(slf, "add_child", child)
+    }
+
+    /// Sets or, with `None`, removes a callable notified after every change
+    /// made through `add_child` or the `name`, `age` and `children` setters.
+    ///
+    /// The callable receives one dict: `{"op": "add_child", "value": child}`,
+    /// or `{"op": "set_name", "value": name}` and likewise for `set_age` and
+    /// `set_children`, where `value` is the new value. It runs after the change
+    /// is applied, so an exception it raises propagates to the caller but does
+    /// not undo the change. Other in-place methods, such as `move_child`, do
+    /// not notify it. The observer is not copied with the person.
+    ///
+    /// The callable may read the person, but should not mutate it: a change
+    /// made from inside the callback notifies the callback again, recursively.
+    ///
+    /// # Arguments
+    /// * `cb` - The callable, or `None`
+    fn set_on_change(&mut self, cb: Option<Py<PyAny>>) {
+        self.state.set_observer(cb);
+    }
+
+    /// Lets the garbage collector see the change observer, which commonly
+    /// refers back to the person and would otherwise form an uncollectable cycle.
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        if let Some(observer) = self.state.observer_ref() {
+            visit.call(observer)?;
+        }
+        Ok(())
+    }
+
+    fn __clear__(&mut self) {
+        self.state.set_observer(None);
+    }
+
+    /// Returns a read-only view of this Person that navigates the tree without
+    /// copying subtrees.
+    ///
+    /// The view and any views reached from it become stale, raising
+    /// `RuntimeError` when used, once this Person is mutated.
+    fn view(slf: &Bound<'_, Self>) -> view::PersonRef {
+        view::PersonRef::new(slf)
+    }
+
+    /// Returns a lazy iterator over the names of this Person and its descendants.
+    ///
+    /// Names are yielded in pre-order (each node before its children, children
+    /// in order), copying a few names at a time instead of materializing a list.
+    /// Mutating this Person while iterating raises `RuntimeError`.
+    fn names(slf: &Bound<'_, Self>) -> iter::NameIterator {
+        iter::NameIterator::new(slf)
+    }
+
+    /// Returns a lazy iterator yielding one flat dict per node, in pre-order.
+    ///
+    /// Each dict has `name`, `age`, `path` (child indices from this Person) and
+    /// `child_count`, so large trees can be streamed without building a list.
+    /// Mutating this Person while iterating raises `RuntimeError`.
+    fn iter_dicts(slf: &Bound<'_, Self>) -> iter::DictIterator {
+        iter::DictIterator::new(slf)
+    }
+
+    /// Returns a lazy iterator yielding `(path, person)` for every node, in
+    /// pre-order.
+    ///
+    /// `path` is a tuple of child indices from this Person and `person` a copy
+    /// of the subtree there, like the pairs of `find_all` but for every node
+    /// and without building a list. Mutating this Person while iterating raises
+    /// `RuntimeError`.
+    fn iter_paths(slf: &Bound<'_, Self>) -> iter::PathIterator {
+        iter::PathIterator::new(slf)
+    }
+
+    /// Pairs every node with the number of nodes in its subtree, in pre-order.
+    ///
+    /// Sizes include the node itself, so sampling entries proportionally to
+    /// their size weights each node by its subtree.
+    ///
+    /// # Returns
+    /// A list of `(node, subtree_size)` tuples, starting with this Person
+    fn flatten_weighted(&self) -> Vec<(Person, usize)> {
+        flatten::flatten_weighted(self)
+    }
+
+    /// Returns the ancestors of the node at `path`, for breadcrumb navigation.
+    ///
+    /// Each ancestor is returned as a copy of its whole subtree, so the first
+    /// entry is a copy of this entire Person.
+    ///
+    /// # Arguments
+    /// * `path` - Child indices from this Person to the target node
+    ///
+    /// # Returns
+    /// The chain of nodes from this Person down to the target's parent, root
+    /// first; empty for the empty path. Raises `IndexError` if the path is
+    /// out of range
+    fn ancestors(&self, path: Vec<usize>) -> PyResult<Vec<Person>> {
+        Ok(paths::ancestors(self, &path)?
             .into_iter()
-            .map(|child_dict| Person::from_dict(&child_dict))
-            .collect::<PyResult<Vec<_>>>()?;
+            .cloned()
+            .collect())
+    }
 
-        Ok(Person {
-            name,
-            age,
-            children,
-        })
+    /// Swaps two of this Person's immediate children.
+    ///
+    /// # Arguments
+    /// * `i` - The index of one child
+    /// * `j` - The index of the other child
+    ///
+    /// # Returns
+    /// An IndexError if either index is out of range
+    fn swap_children(&mut self, i: usize, j: usize) -> PyResult<()> {
+        edit::swap_children(self, i, j)?;
+        self.mark_changed();
+        Ok(())
+    }
+
+    /// Sorts this Person's immediate children, like `list.sort(key=...)`.
+    ///
+    /// # Arguments
+    /// * `key` - A callable mapping a copy of each child to its sort key
+    ///
+    /// # Returns
+    /// An error raised by `key` or by comparing keys, in which case the
+    /// children are left in their original order
+    fn sort_children_by(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        edit::sort_children_by(self, key)?;
+        self.mark_changed();
+        Ok(())
+    }
+
+    /// Shuffles the children of every node in this tree, deterministically
+    /// for a given seed, to check that order-insensitive code really is.
+    ///
+    /// # Arguments
+    /// * `seed` - Seed for the shuffle; the same seed always gives the same
+    ///   order for the same tree
+    fn shuffle_children(&mut self, seed: u64) {
+        edit::shuffle_children(self, seed);
+        self.mark_changed();
+    }
+
+    /// Finds the deepest common ancestor of the nodes at two paths.
+    ///
+    /// A node is its own ancestor, so when one path leads to an ancestor of the
+    /// other, that ancestor is returned.
+    ///
+    /// # Arguments
+    /// * `path_a` - The path of the first node
+    /// * `path_b` - The path of the second node
+    ///
+    /// # Returns
+    /// The path of the common ancestor and a copy of it, or an IndexError if
+    /// either path is out of range
+    fn lca(&self, path_a: Vec<usize>, path_b: Vec<usize>) -> PyResult<(Vec<usize>, Person)> {
+        let (path, node) = paths::lowest_common_ancestor(self, &path_a, &path_b)?;
+        Ok((path, node.clone()))
+    }
+
+    /// Moves the subtree at `from_path` to become the last child of the node at `to_path`.
+    ///
+    /// Paths are lists of child indices from this Person, both taken before the move.
+    ///
+    /// # Arguments
+    /// * `from_path` - Path of the subtree to detach; must not be empty
+    /// * `to_path` - Path of the new parent; must not lie inside the moved subtree
+    ///
+    /// # Returns
+    /// An error if a path is out of range (`IndexError`) or the move would
+    /// create a cycle (`ValueError`)
+    fn move_child(&mut self, from_path: Vec<usize>, to_path: Vec<usize>) -> PyResult<()> {
+        edit::move_child(self, &from_path, &to_path)?;
+        self.mark_changed();
+        Ok(())
+    }
+
+    /// Replaces every name in this tree with a deterministic pseudonym.
+    ///
+    /// Equal names map to equal pseudonyms under the same seed, so the shape of
+    /// the tree and which nodes share a name are preserved.
+    ///
+    /// # Arguments
+    /// * `seed` - Seed mixed into every pseudonym
+    /// * `age_bucket` - If set, ages are also rounded down to a multiple of this
+    #[pyo3(signature = (seed, age_bucket = None))]
+    fn anonymize(&mut self, seed: u64, age_bucket: Option<u32>) -> PyResult<()> {
+        anonymize::anonymize(self, seed, age_bucket)?;
+        self.mark_changed();
+        Ok(())
+    }
+
+    /// Rebuilds this tree as a balanced binary tree of the same nodes, ordered
+    /// by name.
+    ///
+    /// This is a fixture tool for benchmarking traversals on different shapes:
+    /// the `(name, age)` of every node is kept, but the original parent-child
+    /// relationships are discarded.
+    fn rebalance(&mut self) {
+        rebalance::rebalance(self);
+        self.mark_changed();
+    }
+
+    /// Rounds every age in this tree down to the start of its bucket, e.g. 34
+    /// becomes 30 with a bucket size of 10.
+    ///
+    /// # Arguments
+    /// * `bucket_size` - The width of each age bucket, which must be positive
+    fn bucketize_ages(&mut self, bucket_size: u32) -> PyResult<()> {
+        anonymize::bucketize_ages(self, bucket_size)?;
+        self.mark_changed();
+        Ok(())
+    }
+
+    /// Applies a regular expression substitution to every name in this tree.
+    ///
+    /// # Arguments
+    /// * `pattern` - The regular expression to replace, in `regex` crate syntax
+    /// * `replacement` - The replacement text, which may refer to capture groups
+    ///   as `$1` or `${name}`; `$$` is a literal `$`
+    ///
+    /// # Returns
+    /// The number of names that changed, or a `ValueError` if `pattern` is not
+    /// a valid regular expression
+    fn regex_replace_names(&mut self, pattern: &str, replacement: &str) -> PyResult<usize> {
+        let pattern = names::compile_pattern(pattern)?;
+        let changed = names::replace_all(self, &pattern, replacement);
+        if changed > 0 {
+            self.mark_changed();
+        }
+        Ok(changed)
+    }
+
+    /// Sets the person's name, normalizing it if name normalization is enabled.
+    #[setter]
+    fn set_name(slf: &Bound<'_, Self>, name: &Bound<'_, PyAny>) -> PyResult<()> {
+        let name = names::ingest_name(name)?;
+        {
+            let mut this = slf.borrow_mut();
+            this.name = name.clone();
+            this.mark_changed();
+        }
+        Person::notify(slf, "set_name", name)
+    }
+
+    /// Sets the person's age.
+    #[setter]
+    fn set_age(slf: &Bound<'_, Self>, age: u32) -> PyResult<()> {
+        {
+            let mut this = slf.borrow_mut();
+            this.age = age;
+            this.mark_changed();
+        }
+        Person::notify(slf, "set_age", age)
+    }
+
+    /// Replaces the person's children.
+    #[setter]
+    fn set_children(slf: &Bound<'_, Self>, children: Vec<Person>) -> PyResult<()> {
+        {
+            let mut this = slf.borrow_mut();
+            this.children = children.clone();
+            this.mark_changed();
+        }
+        Person::notify(slf, "set_children", children)
+    }
+
+    /// The number of mutations made to this Person through the Python API.
+    ///
+    /// Cached values such as `to_dict_cached` are only reused while this is unchanged.
+    #[getter(version)]
+    fn py_version(&self) -> u64 {
+        self.version()
+    }
+
+    /// Compares two Person trees for equality.
+    ///
+    /// Both sides' content hashes are computed once and cached until the next
+    /// mutation, so comparing unequal trees again is answered from the hashes
+    /// alone. Matching hashes fall back to a full comparison, so a hash
+    /// collision can never make different trees compare equal.
+    fn __eq__(&self, other: &Self) -> bool {
+        self.structural_hash() == other.structural_hash() && self == other
+    }
+
+    /// Hashes this Person by its content, using the cached content hash.
+    ///
+    /// Like any mutable object used as a dict key or set member, a Person must
+    /// not be mutated while it is stored in one.
+    fn __hash__(&self) -> u64 {
+        let digest = self.structural_hash();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+
+    /// Returns the hex-encoded SHA-256 content hash of this Person's tree.
+    ///
+    /// Equal trees always have the same hash; see the `hash` module for the
+    /// exact construction.
+    fn content_hash(&self) -> String {
+        hash::to_hex(&self.structural_hash())
+    }
+
+    /// Counts the occurrences of every distinct subtree, this Person included.
+    ///
+    /// Subtrees are identified by their `content_hash`, all computed in one
+    /// bottom-up pass. Counts above 1 reveal repeated subtrees, which
+    /// `to_msgpack_deduped` would write only once.
+    ///
+    /// # Returns
+    /// A dict mapping each distinct subtree's content hash to its count, in
+    /// order of hash so the output is the same on every run
+    fn subtree_signatures(&self) -> std::collections::BTreeMap<String, usize> {
+        let mut counts = std::collections::BTreeMap::new();
+        for (digest, _) in hash::subtree_digests(self) {
+            *counts.entry(hash::to_hex(&digest)).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Returns the same hash as `content_hash`, computing it on several threads.
+    ///
+    /// Independent subtrees are hashed concurrently with the GIL released, which
+    /// pays off for very large trees. Without the `parallel` feature this is the
+    /// serial hash. The result is cached like that of `content_hash`.
+    fn content_hash_parallel(&self, py: Python<'_>) -> String {
+        if let Some(digest) = self.state.cached_hash() {
+            return hash::to_hex(&digest);
+        }
+        #[cfg(feature = "parallel")]
+        let digest = py.allow_threads(|| hash::content_hash_parallel(self));
+        #[cfg(not(feature = "parallel"))]
+        let digest = {
+            let _ = py;
+            hash::content_hash(self)
+        };
+        self.state.store_hash(digest);
+        hash::to_hex(&digest)
+    }
+
+    /// Provides the string representation of this Person for Python.
+    #[getter(__repr__)]
+    fn __repr__(&self) -> String {
+        if render::tree_repr_enabled() {
+            render::render_tree(self)
+        } else {
+            self.debug_repr()
+        }
+    }
+
+    /// Returns the machine-parseable `{:?}` form of this Person, regardless of
+    /// the mode chosen with `set_repr_mode`.
+    fn debug_repr(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// Provides a Python dictionary representation of this Person.
+    ///
+    /// This method returns a dictionary containing all the fields of the Person instance.
+    #[getter(__dict__)]
+    fn __dict__(&self) -> PyResult<Py<PyDict>> {
+        Wrapper::to_dict(self)
+    }
+
+    /// Converts this Person and its descendants to nested dictionaries.
+    ///
+    /// # Arguments
+    /// * `age_format` - `"int"` (the default) for integer ages, or `"iso8601"`
+    ///   for whole-year durations such as `"P30Y"`
+    /// * `with_ids` - Add a path-derived `id` and the parent's `parent_id` to
+    ///   every node, stable across runs for the same tree shape
+    /// * `with_index` - Add each child's position among its siblings as
+    ///   `sibling_index`, which `from_dict` ignores
+    /// * `frozen` - Return read-only `MappingProxyType` views with `children`
+    ///   tuples at every level instead of dicts and lists
+    /// * `with_type` - Add a `"__type__": "Person"` tag to every node, which
+    ///   `from_dict` ignores
+    /// * `fields` - If set, write only these of `"name"`, `"age"` and
+    ///   `"children"` for every node; `children` is written even if not named
+    /// * `exclude` - Omit these of `"name"`, `"age"` and `"children"` from
+    ///   every node; excluding `children` writes only this Person
+    /// * `key_case` - `"snake"` (the default), `"camel"` or `"kebab"`, the
+    ///   convention for multi-word keys. Exactly two keys change:
+    ///   `"camel"` writes `parent_id` as `parentId` and `sibling_index` as
+    ///   `siblingIndex`, and `"kebab"` writes them as `parent-id` and
+    ///   `sibling-index`. `name`, `age`, `children`, `id` and `__type__` are
+    ///   the same in every case
+    ///
+    /// # Returns
+    /// A dictionary with `name`, `age` and `children` keys, or a read-only
+    /// view of one
+    #[pyo3(signature = (
+        *,
+        age_format = "int",
+        with_ids = false,
+        with_index = false,
+        frozen = false,
+        with_type = false,
+        fields = None,
+        exclude = None,
+        key_case = "snake",
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn to_dict<'py>(
+        &self,
+        py: Python<'py>,
+        age_format: &str,
+        with_ids: bool,
+        with_index: bool,
+        frozen: bool,
+        with_type: bool,
+        fields: Option<Vec<String>>,
+        exclude: Option<Vec<String>>,
+        key_case: &str,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let mut fields = match fields {
+            Some(fields) => export::Fields::parse(&fields)?,
+            None => export::Fields::default(),
+        };
+        if let Some(exclude) = exclude {
+            fields.exclude(&exclude)?;
+        }
+        let options = export::DictOptions {
+            age_format: export::AgeFormat::parse(age_format)?,
+            with_ids,
+            with_index,
+            with_type,
+            fields,
+            key_case: export::KeyCase::parse(key_case)?,
+        };
+        let dict = export::to_dict(self, py, &options)?;
+        if frozen {
+            return export::freeze(dict);
+        }
+        Ok(dict.into_any())
+    }
+
+    /// Converts this Person to nested dictionaries whose `children` group the
+    /// child dicts by age decade, such as `{"0-9": [...], "30-39": [...]}`.
+    ///
+    /// This is an export-only reporting view; `from_dict` cannot read it.
+    fn to_grouped_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        export::to_grouped_dict(self, py)
+    }
+
+    /// Provides a memoized dictionary representation of this Person.
+    ///
+    /// While `version` is unchanged, every call returns the same dict object
+    /// instead of rebuilding it, so callers must not mutate the result.
+    fn to_dict_cached(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        if let Some(dict) = self.state.cached_dict(py) {
+            return Ok(dict);
+        }
+        let dict: Py<PyDict> = self.to_dict_with_py(py)?.into();
+        self.state.store_dict(py, &dict);
+        Ok(dict)
+    }
+
+    /// Creates a Person from a Python value, validating the input.
+    ///
+    /// # Arguments
+    /// * `value` - Any Python object that might be convertible to a Person
+    ///
+    /// # Returns
+    /// A Result containing either the valid Person or an error
+    #[staticmethod]
+    pub fn validate(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Wrapper::validate(value)
+    }
+
+    /// Creates a Person from a Python value, also reporting soft warnings.
+    ///
+    /// Conversion fails exactly like `validate`, but data that converts and is
+    /// merely suspicious (an age above 120, a blank name, or a name with
+    /// surrounding whitespace) is accepted and described in the warnings.
+    ///
+    /// # Arguments
+    /// * `value` - Any Python object that might be convertible to a Person
+    /// * `duplicate_names` - Also warn about every node whose name repeats
+    ///   that of an earlier sibling
+    ///
+    /// # Returns
+    /// A Result containing the Person and a list of warning messages, each
+    /// prefixed with the path of the node it concerns
+    #[staticmethod]
+    #[pyo3(signature = (value, *, duplicate_names = false))]
+    pub fn validate_with_warnings(
+        value: &Bound<'_, PyAny>,
+        duplicate_names: bool,
+    ) -> PyResult<(Self, Vec<String>)> {
+        let person: Person = Wrapper::validate(value)?;
+        let warnings = warnings::collect(&person, duplicate_names);
+        Ok((person, warnings))
+    }
+
+    /// Creates a Person from a Python value after checking it against a JSON Schema.
+    ///
+    /// # Arguments
+    /// * `value` - Any Python object that might be convertible to a Person
+    /// * `schema` - A JSON Schema, either as a Python dict or as JSON text
+    ///
+    /// # Returns
+    /// A Result containing either the valid Person or an error listing every
+    /// schema violation
+    #[staticmethod]
+    pub fn validate_against_schema(
+        value: &Bound<'_, PyAny>,
+        schema: &Bound<'_, PyAny>,
+    ) -> PyResult<Self> {
+        schema::validate_against_schema(value, schema)
+    }
+
+    /// Creates a Person from a Python dictionary.
+    ///
+    /// # Arguments
+    /// * `dict` - A Python dictionary with the required fields, or any other
+    ///   mapping such as an `OrderedDict`; nested children may be mappings too
+    /// * `camel_case` - Whether keys may be camelCase (or PascalCase) instead
+    ///   of snake_case; see `ingest::FromDictOptions` for the exact mapping
+    /// * `max_children_per_node` - If set, reject input where any node has
+    ///   more children than this
+    /// * `max_nodes` - If set, reject input with more nodes than this in
+    ///   total, before converting most of it
+    /// * `trim_names` - Strip leading and trailing whitespace from every name
+    /// * `age_modulo` - If set, reduce every age to its non-negative remainder
+    ///   modulo this, which must be positive
+    /// * `reject_sentinels` - If set, reject input where any age, after
+    ///   `age_modulo`, is one of these values
+    /// * `name_pattern` - If set, a regular expression every name must match;
+    ///   defaults to the pattern set with `set_default_name_pattern`, if any
+    /// * `globally_unique_names` - Reject input where any two nodes, not just
+    ///   siblings, share a name
+    ///
+    /// # Returns
+    /// A Result containing either the created Person or an error
+    #[staticmethod]
+    #[pyo3(signature = (
+        dict,
+        *,
+        camel_case = false,
+        max_children_per_node = None,
+        max_nodes = None,
+        trim_names = false,
+        age_modulo = None,
+        reject_sentinels = None,
+        name_pattern = None,
+        globally_unique_names = false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_dict(
+        dict: &Bound<'_, PyMapping>,
+        camel_case: bool,
+        max_children_per_node: Option<usize>,
+        max_nodes: Option<usize>,
+        trim_names: bool,
+        age_modulo: Option<u32>,
+        reject_sentinels: Option<Vec<u32>>,
+        name_pattern: Option<&str>,
+        globally_unique_names: bool,
+    ) -> PyResult<Self> {
+        let name_pattern = match name_pattern {
+            Some(pattern) => Some(names::compile_pattern(pattern)?),
+            None => names::default_pattern(),
+        };
+        let options = ingest::FromDictOptions {
+            camel_case,
+            max_children_per_node,
+            max_nodes,
+            trim_names,
+            age_modulo,
+            reject_sentinels: reject_sentinels.unwrap_or_default(),
+            name_pattern,
+            globally_unique_names,
+        };
+        ingest::from_dict(dict, &options)
+    }
+
+    /// Writes this tree as a GraphML document, for graph-analysis tools.
+    ///
+    /// # Returns
+    /// GraphML text with one node per person, carrying `name` and `age` data,
+    /// and a directed edge from each parent to each of its children
+    fn to_graphml(&self) -> String {
+        graph::to_graphml(self)
+    }
+
+    /// Reconstructs a tree from parent-to-child edges between unique names.
+    ///
+    /// # Arguments
+    /// * `edges` - `(parent, child)` name pairs; children keep the edge order
+    /// * `ages` - The age of every name; with no edges, a single entry here
+    ///   gives a lone root
+    ///
+    /// # Returns
+    /// The root Person, or a `ValueError` if the edges do not form a single
+    /// rooted tree or a name has no age
+    #[staticmethod]
+    fn from_edge_list(
+        edges: Vec<(String, String)>,
+        ages: std::collections::HashMap<String, u32>,
+    ) -> PyResult<Self> {
+        graph::from_edge_list(&edges, &ages)
+    }
+
+    /// Encodes this Person and its descendants into the compact binary format.
+    ///
+    /// Ages are LEB128 varints and names are length-prefixed UTF-8; see the
+    /// `compact` module for the exact byte layout.
+    fn to_compact_bytes(&self) -> Vec<u8> {
+        compact::encode(self)
+    }
+
+    /// Decodes a Person from bytes produced by `to_compact_bytes`.
+    ///
+    /// # Arguments
+    /// * `data` - The compact binary payload
+    ///
+    /// # Returns
+    /// A Result containing either the decoded Person or an error
+    #[staticmethod]
+    fn from_compact_bytes(data: &[u8]) -> PyResult<Self> {
+        compact::decode(data)
+    }
+
+    /// Renders this Person and its descendants as a box-drawing tree.
+    ///
+    /// Each line shows a node's name and age, in the style of the `tree` command.
+    fn render_tree(&self) -> String {
+        render::render_tree(self)
+    }
+
+    /// Finds the first difference between this Person and another.
+    ///
+    /// Handy for assertion messages: the result names just the differing field
+    /// instead of dumping both trees.
+    ///
+    /// # Arguments
+    /// * `other` - The Person to compare against
+    ///
+    /// # Returns
+    /// A path such as `children[1].age`, or None if the trees are equal
+    fn first_mismatch(&self, other: &Person) -> Option<String> {
+        diff::first_mismatch(self, other)
+    }
+
+    /// Compares only the names and shape of this Person and another, ignoring
+    /// every age.
+    ///
+    /// Useful for checking that a transformation kept the structure of a tree
+    /// while updating ages. Children must be in the same order.
+    ///
+    /// # Arguments
+    /// * `other` - The Person to compare against
+    fn equals_structure(&self, other: &Person) -> bool {
+        diff::equals_structure(self, other)
+    }
+
+    /// Computes the tree edit distance from this Person to another: the
+    /// fewest node insertions, deletions and relabellings (changes of name or
+    /// age) turning one tree into the other, each costing 1.
+    ///
+    /// Children are ordered. See `diff::edit_distance` for the cost model and
+    /// complexity; pairs of trees too large to compare quickly, including any
+    /// two of more than about 3,000 nodes each, raise `ValueError` instead.
+    ///
+    /// # Arguments
+    /// * `other` - The Person to compare against
+    fn edit_distance(&self, other: &Person) -> PyResult<usize> {
+        diff::edit_distance(self, other)
+    }
+
+    /// Builds a binary patch that turns this Person into `other`.
+    ///
+    /// The patch only carries what differs, so it is usually much smaller than
+    /// `other` itself. See the `patch` module for the format.
+    ///
+    /// # Arguments
+    /// * `other` - The target tree
+    ///
+    /// # Returns
+    /// The patch, to be passed to `apply_patch` on an equal copy of this Person
+    fn make_patch(&self, other: &Person) -> Vec<u8> {
+        patch::make_patch(self, other)
+    }
+
+    /// Applies a patch built by `make_patch`, updating this Person in place.
+    ///
+    /// # Arguments
+    /// * `patch` - The patch bytes
+    ///
+    /// # Returns
+    /// An error if the patch is malformed (`ValueError`) or does not fit this
+    /// tree (`IndexError`), in which case this Person is left unchanged
+    fn apply_patch(&mut self, patch: &[u8]) -> PyResult<()> {
+        patch::apply_patch(self, patch)?;
+        self.mark_changed();
+        Ok(())
+    }
+
+    /// Returns a pruned copy containing only the nodes that satisfy `predicate`.
+    ///
+    /// A node is kept if it or any of its descendants matches; this Person is
+    /// left untouched. The predicate receives a copy of each node's subtree, so
+    /// on deep trees the copying dominates.
+    ///
+    /// # Arguments
+    /// * `predicate` - A callable taking a Person and returning a truthy value
+    ///
+    /// # Returns
+    /// The pruned tree, or None if no node matches
+    fn filter_tree(&self, predicate: &Bound<'_, PyAny>) -> PyResult<Option<Person>> {
+        query::filter_tree(self, predicate)
+    }
+
+    /// Finds every node, including this Person, for which `predicate` is truthy.
+    ///
+    /// The predicate receives a copy of each node's subtree, so on deep trees
+    /// the copying dominates.
+    ///
+    /// # Arguments
+    /// * `predicate` - A callable taking a `Person`; exceptions it raises are propagated
+    ///
+    /// # Returns
+    /// A list of `(path, node)` tuples in pre-order, where `path` is the list of
+    /// child indices from this Person
+    fn find_all(&self, predicate: &Bound<'_, PyAny>) -> PyResult<Vec<(Vec<usize>, Person)>> {
+        query::find_all(self, predicate)
+    }
+
+    /// Visits this Person and its descendants level by level.
+    ///
+    /// The visitor receives a copy of each node's subtree, so on deep trees
+    /// the copying dominates.
+    ///
+    /// # Arguments
+    /// * `visitor` - A callable taking a `Person` and its depth (0 for this
+    ///   Person); exceptions it raises are propagated
+    /// * `max_depth` - If set, nodes deeper than this are not visited
+    #[pyo3(signature = (visitor, max_depth = None))]
+    fn bfs(&self, visitor: &Bound<'_, PyAny>, max_depth: Option<usize>) -> PyResult<()> {
+        query::bfs(self, visitor, max_depth)
+    }
+
+    /// Finds the node, including this Person, whose age is nearest to `target`.
+    ///
+    /// Ties are broken by the alphabetically first name.
+    ///
+    /// # Arguments
+    /// * `target` - The age to compare against
+    ///
+    /// # Returns
+    /// A copy of the closest node's subtree
+    fn closest_to_age(&self, target: u32) -> Person {
+        query::closest_to_age(self, target).clone()
+    }
+
+    /// Computes statistics over this Person and all descendants in one pass.
+    ///
+    /// # Returns
+    /// A dictionary with `count`, `height`, `min_age`, `max_age`, `mean_age`
+    /// and `max_branching`
+    fn stats(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        Ok(stats::TreeStats::compute(self).to_dict(py)?.into())
+    }
+
+    /// Returns the number of edges on the longest path from this Person down
+    /// to a leaf, 0 for a Person without children.
+    ///
+    /// The result is cached until the tree is next mutated, so repeated calls
+    /// between mutations are O(1).
+    fn depth(&self) -> usize {
+        self.shape().depth
+    }
+
+    /// Returns the number of nodes below this Person, not counting itself.
+    ///
+    /// The result is cached until the tree is next mutated, like `depth`.
+    fn count_descendants(&self) -> usize {
+        self.shape().descendants
+    }
+
+    /// Returns the number of nodes in this tree that have no children.
+    fn count_leaves(&self) -> usize {
+        stats::leaf_internal_counts(self).0
+    }
+
+    /// Returns the number of nodes in this tree that have children.
+    fn count_internal(&self) -> usize {
+        stats::leaf_internal_counts(self).1
+    }
+
+    /// Counts leaves and internal nodes together in one pass.
+    ///
+    /// # Returns
+    /// A `(leaves, internal)` tuple, which sums to the node count
+    fn leaf_internal_counts(&self) -> (usize, usize) {
+        stats::leaf_internal_counts(self)
+    }
+
+    /// Serializes this Person and its descendants to JSON.
+    ///
+    /// # Arguments
+    /// * `indent` - If set, pretty-print with this many spaces per level, like
+    ///   `json.dumps(indent=...)`; otherwise the output is compact
+    ///
+    /// # Returns
+    /// A JSON object with `name`, `age` and `children` keys
+    #[pyo3(signature = (indent = None))]
+    fn to_json(&self, indent: Option<usize>) -> String {
+        json::person_to_json(self, indent)
+    }
+
+    /// Parses a Person from JSON text such as `to_json` writes.
+    ///
+    /// # Arguments
+    /// * `text` - A JSON object with `name`, `age` and `children`, nested for
+    ///   every child
+    ///
+    /// # Returns
+    /// A Result containing either the parsed Person or an error naming the
+    /// offending node
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        json::person_from_json(text)
+    }
+
+    /// Writes this Person as an S-expression such as `(John 30 (Ann 5))`.
+    ///
+    /// Names containing whitespace, parentheses, quotes or backslashes are
+    /// quoted; see the `sexpr` module for the exact syntax.
+    fn to_sexpr(&self) -> String {
+        sexpr::to_sexpr(self)
+    }
+
+    /// Parses a Person from an S-expression written by `to_sexpr`.
+    ///
+    /// # Arguments
+    /// * `text` - A list of a name, an age and any number of child lists
+    ///
+    /// # Returns
+    /// A Result containing either the parsed Person or an error
+    #[staticmethod]
+    fn from_sexpr(text: &str) -> PyResult<Self> {
+        sexpr::from_sexpr(text)
+    }
+
+    /// Serializes this Person and its descendants to a TOML document.
+    ///
+    /// Children become nested arrays of tables; trees deeper than the TOML
+    /// depth limit are rejected with an error.
+    fn to_toml(&self) -> PyResult<String> {
+        toml_format::to_toml(self)
+    }
+
+    /// Parses a Person from a TOML document produced by `to_toml`.
+    ///
+    /// # Arguments
+    /// * `text` - The TOML document
+    ///
+    /// # Returns
+    /// A Result containing either the parsed Person or an error
+    #[staticmethod]
+    fn from_toml(text: &str) -> PyResult<Self> {
+        toml_format::from_toml(text)
+    }
+
+    /// Encodes this Person and its descendants as msgpack.
+    ///
+    /// With string keys the output matches `ormsgpack.packb(person.__dict__)`.
+    ///
+    /// # Arguments
+    /// * `compact_keys` - Use the integer keys `0`, `1` and `2` for `name`,
+    ///   `age` and `children`, which `from_msgpack` also accepts
+    #[pyo3(signature = (*, compact_keys = false))]
+    fn to_msgpack(&self, compact_keys: bool) -> Vec<u8> {
+        msgpack::encode_with_keys(self, compact_keys)
     }
 
-    /// Validates and converts a Python object to a Person instance.
+    /// Encodes this Person as msgpack with every person as a positional
+    /// `[name, age, children]` array instead of a map.
     ///
-    /// This method attempts to convert the input to a Person instance in the following order:
-    /// 1. Direct extraction of a Person instance
-    /// 2. Conversion from a dictionary
-    /// 3. If neither works, it returns an error
-    fn validate(value: &Bound<'_, PyAny>) -> PyResult<Self> {
-        // First check if it's already a Person instance
-        if let Ok(person) = value.extract::<Person>() {
-            return Ok(person);
-        }
+    /// The output matches `ormsgpack.packb` of nested `(name, age, children)`
+    /// tuples and is read back by `from_msgpack`, which detects the form.
+    fn to_msgpack_tuples(&self) -> Vec<u8> {
+        msgpack::encode_positional(self)
+    }
 
-        // Then try to convert from a dictionary
-        let value_for_error = format!("{:?}", value);
-        if let Ok(dict) = value.downcast::<PyDict>() {
-            return Person::from_dict(dict);
-        }
+    /// Encodes this Person as msgpack in breadth-first order: one flat array of
+    /// `[name, age, child_count]` arrays, the root first and then each level
+    /// in turn.
+    ///
+    /// The layout is described in the `msgpack` module and is read back by
+    /// `from_msgpack_bfs`, not `from_msgpack`.
+    fn to_msgpack_bfs(&self) -> Vec<u8> {
+        msgpack::encode_bfs(self)
+    }
 
-        // If neither works, return an error
-        Err(pyo3::exceptions::PyValueError::new_err(format!(
-            "Cannot convert {} to Person",
-            value_for_error
-        )))
+    /// Decodes a Person from bytes produced by `to_msgpack_bfs`.
+    ///
+    /// # Arguments
+    /// * `data` - The breadth-first msgpack payload
+    /// * `max_nodes` - If set, fail as soon as the payload claims more nodes
+    ///   than this
+    /// * `max_bytes` - If set, reject a payload longer than this before
+    ///   decoding it
+    ///
+    /// # Returns
+    /// A Result containing either the decoded Person or an error
+    #[staticmethod]
+    #[pyo3(signature = (data, *, max_nodes = None, max_bytes = None))]
+    fn from_msgpack_bfs(
+        data: &[u8],
+        max_nodes: Option<usize>,
+        max_bytes: Option<usize>,
+    ) -> PyResult<Self> {
+        let options = msgpack::DecodeOptions {
+            max_nodes,
+            max_bytes,
+            ..msgpack::DecodeOptions::default()
+        };
+        msgpack::decode_bfs(data, &options)
     }
-}
 
-#[pymethods]
-impl Person {
-    /// Adds a child to this person's children.
+    /// Encodes this Person as msgpack and compresses it with gzip.
     ///
     /// # Arguments
-    /// * `child` - A Person instance representing a child to add
-    fn add_child(&mut self, child: Person) {
-        self.children.push(child);
+    /// * `level` - The compression level, from 0 (none) to 9 (smallest)
+    ///
+    /// # Returns
+    /// A gzip stream that decompresses to the `to_msgpack` encoding
+    #[pyo3(signature = (level = msgpack_gz::DEFAULT_LEVEL))]
+    fn to_msgpack_gz(&self, level: u32) -> PyResult<Vec<u8>> {
+        msgpack_gz::encode(self, level)
     }
 
-    /// Provides the string representation of this Person for Python.
-    #[getter(__repr__)]
-    fn __repr__(&self) -> String {
-        format!("{:?}", self)
+    /// Decodes a Person from gzip-compressed msgpack written by `to_msgpack_gz`.
+    ///
+    /// # Arguments
+    /// * `data` - A gzip stream holding a msgpack person payload
+    /// * `max_bytes` - Reject a payload that decompresses to more than this
+    ///   many bytes, without inflating the rest; the default is 256 MiB
+    ///
+    /// # Returns
+    /// A Result containing either the decoded Person or an error
+    #[staticmethod]
+    #[pyo3(signature = (data, *, max_bytes = msgpack_gz::DEFAULT_MAX_BYTES))]
+    fn from_msgpack_gz(data: &[u8], max_bytes: usize) -> PyResult<Self> {
+        msgpack_gz::decode(data, max_bytes)
     }
 
-    /// Provides a Python dictionary representation of this Person.
+    /// Encodes this Person as msgpack, writing each repeated subtree only once.
     ///
-    /// This method returns a dictionary containing all the fields of the Person instance.
-    #[getter(__dict__)]
-    fn __dict__(&self) -> PyResult<Py<PyDict>> {
-        Person::to_dict(self)
+    /// Later copies of a subtree become references, so trees with much
+    /// repetition shrink dramatically. Read the result with
+    /// `from_msgpack_deduped`; see the `msgpack` module for the format.
+    fn to_msgpack_deduped(&self) -> Vec<u8> {
+        msgpack::encode_deduped(self)
     }
 
-    /// Creates a Person from a Python value, validating the input.
+    /// Decodes a Person from msgpack bytes written by `to_msgpack_deduped`,
+    /// restoring every referenced subtree.
     ///
     /// # Arguments
-    /// * `value` - Any Python object that might be convertible to a Person
+    /// * `data` - The deduplicated msgpack payload; plain `to_msgpack` output
+    ///   is accepted too
+    /// * `max_nodes` - Fail as soon as the tree, with every reference
+    ///   expanded, holds or claims more nodes than this; the default is
+    ///   10,000,000
+    /// * `max_bytes` - If set, reject a payload longer than this before
+    ///   decoding it
     ///
     /// # Returns
-    /// A Result containing either the valid Person or an error
+    /// A Result containing either the decoded Person or an error
     #[staticmethod]
-    pub fn validate(value: &Bound<'_, PyAny>) -> PyResult<Self> {
-        Wrapper::validate(value)
+    #[pyo3(signature = (data, *, max_nodes = None, max_bytes = None))]
+    fn from_msgpack_deduped(
+        data: &[u8],
+        max_nodes: Option<usize>,
+        max_bytes: Option<usize>,
+    ) -> PyResult<Self> {
+        msgpack::decode_deduped(data, max_nodes, max_bytes)
     }
 
-    /// Creates a Person from a Python dictionary.
+    /// Returns the length in bytes that `to_msgpack` would produce, without
+    /// encoding the tree.
+    ///
+    /// The result is exact, since each node's size follows from the lengths of
+    /// its fields; it is handy for pre-sizing buffers or rejecting oversized
+    /// trees up front.
+    ///
+    /// # Arguments
+    /// * `compact_keys` - Estimate for `to_msgpack(compact_keys=True)`
+    #[pyo3(signature = (*, compact_keys = false))]
+    fn msgpack_size_estimate(&self, compact_keys: bool) -> usize {
+        msgpack::encoded_len(self, compact_keys)
+    }
+
+    /// Decodes a Person from msgpack bytes.
     ///
     /// # Arguments
-    /// * `dict` - A Python dictionary with the required fields
+    /// * `data` - A msgpack map with `name`, `age` and `children` keys, or
+    ///   their compact integer keys, or a `[name, age, children]` array
+    /// * `lossy` - Replace invalid UTF-8 in strings with U+FFFD instead of
+    ///   raising a ValueError that names the field
+    /// * `max_nodes` - If set, fail as soon as the payload holds or claims
+    ///   more nodes than this
+    /// * `max_bytes` - If set, reject a payload longer than this before
+    ///   decoding it
+    ///
+    /// Decodes with any of these options bypass the decode cache.
     ///
     /// # Returns
-    /// A Result containing either the created Person or an error
+    /// A Result containing either the decoded Person or an error
     #[staticmethod]
-    pub fn from_dict(dict: &Bound<'_, PyDict>) -> PyResult<Self> {
-        Wrapper::from_dict(dict)
+    #[pyo3(signature = (data, *, lossy = false, max_nodes = None, max_bytes = None))]
+    fn from_msgpack(
+        data: &[u8],
+        lossy: bool,
+        max_nodes: Option<usize>,
+        max_bytes: Option<usize>,
+    ) -> PyResult<Self> {
+        let options = msgpack::DecodeOptions {
+            dedup: false,
+            lossy,
+            max_nodes,
+            max_bytes,
+        };
+        if lossy || max_nodes.is_some() || max_bytes.is_some() {
+            return msgpack::decode_with(data, &options);
+        }
+        decode_cache::decode(data)
+    }
+
+    /// Rebuilds a Person pickled with a protocol older than 3.
+    ///
+    /// Unlike `from_dict`, this ignores the default name pattern, so a pickle
+    /// still loads after `set_default_name_pattern` is given a pattern its
+    /// names do not match, as it would with a newer protocol.
+    ///
+    /// # Arguments
+    /// * `dict` - The dict form produced by `__reduce_ex__`
+    #[staticmethod]
+    fn _from_pickled_dict(dict: &Bound<'_, PyMapping>) -> PyResult<Self> {
+        ingest::from_dict(dict, &ingest::FromDictOptions::default())
+    }
+
+    /// Supports pickling, choosing the encoding by pickle protocol.
+    ///
+    /// Protocols 3 and newer, which store bytes natively, pickle the msgpack
+    /// encoding and restore it with `from_msgpack`. Older protocols fall back to
+    /// the dict form and `_from_pickled_dict`.
+    ///
+    /// # Arguments
+    /// * `protocol` - The pickle protocol in use
+    ///
+    /// # Returns
+    /// A `(callable, args)` pair that rebuilds this Person
+    fn __reduce_ex__<'py>(
+        &self,
+        py: Python<'py>,
+        protocol: i32,
+    ) -> PyResult<(Bound<'py, PyAny>, (Bound<'py, PyAny>,))> {
+        let class = py.get_type::<Person>();
+        if protocol >= 3 {
+            let bytes = PyBytes::new(py, &msgpack::encode(self));
+            Ok((class.getattr("from_msgpack")?, (bytes.into_any(),)))
+        } else {
+            let dict = export::to_dict(self, py, &export::DictOptions::default())?;
+            Ok((class.getattr("_from_pickled_dict")?, (dict.into_any(),)))
+        }
+    }
+
+    /// Appends this Person as one length-prefixed msgpack record to a log file.
+    ///
+    /// A partial record left at the end of the log by an interrupted write is
+    /// truncated first.
+    ///
+    /// # Arguments
+    /// * `path` - The log file, created if it does not exist
+    fn append_msgpack(&self, path: &str) -> PyResult<()> {
+        msgpack_log::append(self, path)
+    }
+
+    /// Writes the `to_msgpack` encoding of this Person to a binary file-like
+    /// object, such as an `io.BytesIO`, a socket file or a `gzip.GzipFile`.
+    ///
+    /// The encoding is produced and written in chunks of about 64 KiB, so the
+    /// whole payload is never held in memory. Exceptions raised by `write`
+    /// propagate, leaving whatever was already written in place.
+    ///
+    /// # Arguments
+    /// * `file_obj` - Any object with a `write(bytes)` method
+    fn dump_msgpack_to(&self, py: Python<'_>, file_obj: &Bound<'_, PyAny>) -> PyResult<()> {
+        msgpack::encode_chunked(self, msgpack::STREAM_CHUNK_SIZE, |chunk| {
+            file_obj.call_method1("write", (PyBytes::new(py, chunk),))?;
+            Ok(())
+        })
     }
 }
 
@@ -200,11 +1494,29 @@ pub fn create_random_person() -> Person {
     // Generate a random age (1-99)
     //let age = rng.random_range(1..=99);
 
-    Person {
-        name: "John".to_string(),
-        age: 30,
-        children: vec![],
-    }
+    Person::new("John".to_string(), 30, vec![])
+}
+
+/// Creates a Person with a name picked from a list, for plausible fixtures.
+///
+/// # Arguments
+/// * `names` - The names to pick from, which must not be empty
+/// * `min_age` - The smallest possible age
+/// * `max_age` - The largest possible age, at least `min_age`
+/// * `seed` - Seed determining the pick; the same arguments always give the
+///   same Person
+///
+/// # Returns
+/// A childless Person, or a `ValueError` if `names` is empty or the age range
+/// is empty
+#[pyfunction]
+pub fn create_random_person_from(
+    names: Vec<String>,
+    min_age: u32,
+    max_age: u32,
+    seed: u64,
+) -> PyResult<Person> {
+    person_rng::random_person_from(&names, min_age, max_age, seed)
 }
 
 /// Creates a deeply nested Person structure with random children.
@@ -215,31 +1527,144 @@ pub fn create_random_person() -> Person {
 /// # Arguments
 /// * `depth` - Maximum depth of the person hierarchy
 /// * `max_children` - Maximum number of children at each level
+/// * `name_fn` - Optional callable `name_fn(depth, index) -> str` naming each
+///   node from its depth (0 for the root) and its index among its siblings
+///   (0 for the root). Every node is named `John` if omitted. Exceptions
+///   raised by the callable propagate unchanged.
+/// * `max_total_nodes` - Optional cap on the number of nodes, root included,
+///   to bound memory when `max_children ^ depth` is huge. Nodes are generated
+///   depth-first, so once the cap is reached the remaining children are simply
+///   not added: the tree can be shallower and lopsided compared to what
+///   `depth` implies. The root is always created.
 ///
 /// # Returns
 /// A root Person with a nested hierarchy of children
 #[pyfunction]
-pub fn create_nested_person(depth: usize, max_children: usize) -> Person {
-    let mut root = create_random_person();
+#[pyo3(signature = (depth, max_children, name_fn = None, max_total_nodes = None))]
+pub fn create_nested_person(
+    depth: usize,
+    max_children: usize,
+    name_fn: Option<&Bound<'_, PyAny>>,
+    max_total_nodes: Option<usize>,
+) -> PyResult<Person> {
+    fn make_person(
+        name_fn: Option<&Bound<'_, PyAny>>,
+        depth: usize,
+        index: usize,
+    ) -> PyResult<Person> {
+        let mut person = create_random_person();
+        if let Some(name_fn) = name_fn {
+            person.name = names::ingest_name(&name_fn.call1((depth, index))?)?;
+        }
+        Ok(person)
+    }
 
-    fn add_children(person: &mut Person, current_depth: usize, max_children: usize) {
+    /// Adds children below `person`, creating at most `budget` more nodes.
+    fn add_children(
+        person: &mut Person,
+        name_fn: Option<&Bound<'_, PyAny>>,
+        level: usize,
+        current_depth: usize,
+        max_children: usize,
+        budget: &mut usize,
+    ) -> PyResult<()> {
         if current_depth == 0 {
-            return;
+            return Ok(());
         }
 
         // Add random number of children (1 to max_children)
         let mut rng = rng();
         let num_children = rng.random_range(1..=max_children);
 
-        for _ in 0..num_children {
-            let mut child = create_random_person();
-            add_children(&mut child, current_depth - 1, max_children);
-            person.add_child(child);
+        for index in 0..num_children {
+            if *budget == 0 {
+                break;
+            }
+            *budget -= 1;
+            let mut child = make_person(name_fn, level + 1, index)?;
+            add_children(
+                &mut child,
+                name_fn,
+                level + 1,
+                current_depth - 1,
+                max_children,
+                budget,
+            )?;
+            person.children.push(child);
+        }
+        Ok(())
+    }
+
+    let mut budget = max_total_nodes.map_or(usize::MAX, |max| max.saturating_sub(1));
+    let mut root = make_person(name_fn, 0, 0)?;
+    add_children(&mut root, name_fn, 0, depth, max_children, &mut budget)?;
+    Ok(root)
+}
+
+/// Creates a perfectly balanced Person tree with predictable, path-based names.
+///
+/// Every node above the deepest level has exactly `children_per_node` children.
+/// The root is named `root` and each child appends its index to its parent's
+/// name, so the second child of the first child is `root.0.1`. All ages are 30.
+/// The result is fully deterministic, which makes it a reproducible fixture.
+///
+/// # Arguments
+/// * `depth` - Number of levels below the root
+/// * `children_per_node` - Number of children of every non-leaf node
+///
+/// # Returns
+/// A root Person with `children_per_node ^ depth` leaves
+#[pyfunction]
+pub fn create_balanced_person(depth: usize, children_per_node: usize) -> Person {
+    // Nodes still receiving children, with their level and next child index.
+    let mut stack = vec![(Person::new("root".to_string(), 30, vec![]), 0, 0)];
+
+    loop {
+        let (node, level, next_child) = stack.last_mut().unwrap();
+        if *level < depth && *next_child < children_per_node {
+            let name = format!("{}.{}", node.name, next_child);
+            let level = *level + 1;
+            *next_child += 1;
+            stack.push((Person::new(name, 30, vec![]), level, 0));
+            continue;
         }
+
+        let (node, _, _) = stack.pop().unwrap();
+        match stack.last_mut() {
+            Some((parent, _, _)) => parent.children.push(node),
+            None => return node,
+        }
+    }
+}
+
+/// Creates a single-path chain: each Person has exactly one child, the next one.
+///
+/// The chain is built iteratively from the leaf upwards, so it can be
+/// arbitrarily long, e.g. as a fixture for deep-tree handling.
+///
+/// # Arguments
+/// * `names` - Names from the root down to the leaf
+/// * `ages` - Ages in the same order as `names`
+///
+/// # Returns
+/// The root of the chain, or a `ValueError` if the lists are empty or differ
+/// in length
+#[pyfunction]
+pub fn create_chain(names: Vec<String>, ages: Vec<u32>) -> PyResult<Person> {
+    if names.len() != ages.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "names and ages must have the same length, got {} and {}",
+            names.len(),
+            ages.len()
+        )));
     }
 
-    add_children(&mut root, depth, max_children);
-    root
+    let mut chain: Option<Person> = None;
+    for (name, age) in names.into_iter().zip(ages).rev() {
+        chain = Some(Person::new(name, age, chain.into_iter().collect()));
+    }
+    chain
+        .ok_or_else(|| pyo3::exceptions::PyValueError::new_err("A chain needs at least one person"))
 }
 
 /// Creates a new Person with the specified name and age.
@@ -255,11 +1680,236 @@ pub fn create_nested_person(depth: usize, max_children: usize) -> Person {
 /// A new Person instance with the given attributes
 #[pyfunction]
 pub fn new_person(name: String, age: u32) -> Person {
-    Person {
-        name,
-        age,
-        children: vec![],
-    }
+    Person::new(name, age, vec![])
+}
+
+/// Reads every Person from a log written by `Person.append_msgpack`.
+///
+/// A partially written record at the end of the log is ignored.
+///
+/// # Arguments
+/// * `path` - The log file to read
+///
+/// # Returns
+/// The logged Person instances, in the order they were appended
+#[pyfunction]
+pub fn read_msgpack_log(path: &str) -> PyResult<Vec<Person>> {
+    msgpack_log::read(path)
+}
+
+/// Decodes many msgpack payloads, in parallel when the `parallel` feature is
+/// enabled (the default).
+///
+/// The GIL is released while decoding, and only taken again, briefly, to
+/// build the error for an invalid payload. The output is in input order.
+///
+/// # Arguments
+/// * `payloads` - Payloads as produced by `Person.to_msgpack`: `bytes`,
+///   `bytearray`, `memoryview` or any other object exposing a byte buffer
+/// * `threads` - Number of worker threads; `0` uses rayon's global pool, one
+///   thread per core, and other sizes use a pool kept for later calls
+///
+/// # Returns
+/// The decoded Persons, or the error for the first invalid payload
+#[pyfunction]
+#[pyo3(signature = (payloads, threads = 0))]
+pub fn decode_persons_parallel(
+    py: Python<'_>,
+    payloads: Vec<PyBuffer<u8>>,
+    threads: usize,
+) -> PyResult<Vec<Person>> {
+    // Copy the payloads out of their Python objects, which cannot be touched
+    // once the GIL is released.
+    let payloads = payloads
+        .iter()
+        .map(|buffer| buffer.to_vec(py))
+        .collect::<PyResult<Vec<Vec<u8>>>>()?;
+    py.allow_threads(|| msgpack::decode_all(&payloads, threads))
+}
+
+/// Flattens a forest into columns, ready for e.g. `pandas.DataFrame(columns)`.
+///
+/// # Arguments
+/// * `people` - The roots of the forest
+///
+/// # Returns
+/// A dict of equal-length `name`, `age` and `parent` lists with one row per
+/// node in pre-order, where `parent` is the row index of the node's parent or
+/// `None` for a root
+#[pyfunction]
+pub fn to_columns(py: Python<'_>, people: Vec<Person>) -> PyResult<Py<PyDict>> {
+    Ok(export::to_columns(&people, py)?.into())
+}
+
+/// Counts the nodes of a forest by their number of children, e.g. to check
+/// the distribution produced by `create_nested_person`.
+///
+/// # Arguments
+/// * `people` - The roots of the forest
+///
+/// # Returns
+/// A dict mapping each branching factor that occurs to the number of nodes
+/// with that many children, in increasing order of branching factor
+#[pyfunction]
+pub fn children_histogram(people: Vec<Person>) -> std::collections::BTreeMap<usize, usize> {
+    stats::children_histogram(&people)
+}
+
+/// Flattens a forest into parallel arrays, the most compact in-memory form.
+///
+/// # Arguments
+/// * `people` - The roots of the forest
+///
+/// # Returns
+/// A `(names, ages, parents)` tuple of equal-length lists with one entry per
+/// node in pre-order, where `parents` holds the index of the node's parent or
+/// -1 for a root
+#[pyfunction]
+pub fn to_flat_arrays(people: Vec<Person>) -> (Vec<String>, Vec<u32>, Vec<i64>) {
+    flatten::to_flat_arrays(&people)
+}
+
+/// Rebuilds a forest from the parallel arrays written by `to_flat_arrays`.
+///
+/// # Arguments
+/// * `names` - The name of every node
+/// * `ages` - The age of every node
+/// * `parents` - The index of every node's parent, or -1 for a root
+///
+/// # Returns
+/// The roots in index order, or a `ValueError` if the arrays differ in length
+/// or the parents do not form a forest
+#[pyfunction]
+pub fn from_flat_arrays(
+    names: Vec<String>,
+    ages: Vec<u32>,
+    parents: Vec<i64>,
+) -> PyResult<Vec<Person>> {
+    flatten::from_flat_arrays(names, &ages, &parents)
+}
+
+/// Sets the capacity of the process-wide `Person.from_msgpack` result cache.
+///
+/// The cache is off by default. When enabled, decoding a payload that was
+/// decoded recently returns a copy of the cached tree instead of decoding it
+/// again; the least recently used trees are evicted beyond the capacity. The
+/// cache is safe to use from multiple threads.
+///
+/// # Arguments
+/// * `size` - The maximum number of cached trees; `0` disables the cache
+#[pyfunction]
+pub fn set_decode_cache_size(size: usize) {
+    decode_cache::set_capacity(size);
+}
+
+/// Empties the `Person.from_msgpack` result cache, keeping its capacity.
+#[pyfunction]
+pub fn clear_decode_cache() {
+    decode_cache::clear();
+}
+
+/// Builds a Person from each dict of an iterable, as `Person.from_dict` with
+/// its default options would.
+///
+/// The iterable is consumed lazily, so generators and other streaming sources
+/// need not be collected into a list first.
+///
+/// # Arguments
+/// * `it` - Any iterable of person dicts
+///
+/// # Returns
+/// The Persons in iteration order, or the error for the first invalid item,
+/// of the type `from_dict` would raise, with a message naming the item's index
+#[pyfunction]
+pub fn persons_from_iter(it: &Bound<'_, PyAny>) -> PyResult<Vec<Person>> {
+    ingest::from_dicts(it, &ingest::FromDictOptions::with_default_pattern())
+}
+
+/// Reads a forest of Persons from a CSV file of `name,age,parent_name` rows.
+///
+/// The header row names the columns. A row with an empty `parent_name` is a
+/// root; otherwise it is a child of the row with that name.
+///
+/// # Arguments
+/// * `path` - The CSV file
+///
+/// # Returns
+/// The roots in row order, or a `ValueError` for malformed rows, duplicate
+/// names, unknown parents or cycles
+#[pyfunction]
+pub fn from_csv(path: &str) -> PyResult<Vec<Person>> {
+    csv_format::from_csv(path)
+}
+
+/// Returns type stubs (a `.pyi` file) for this module, for mypy and pyright.
+///
+/// The stubs are the ones maintained alongside the sources, embedded at build
+/// time, so they describe exactly the classes and functions of this build.
+/// Write them to `py03_pydantic_ormsgpack_experiment.pyi` next to the module.
+#[pyfunction]
+pub fn emit_type_stubs() -> &'static str {
+    include_str!("python/py03_pydantic_ormsgpack_experiment.pyi")
+}
+
+/// Reports which optional formats and features this build includes, so Python
+/// code can feature-detect at runtime.
+///
+/// # Returns
+/// A dict mapping `"msgpack"`, `"json"`, `"yaml"`, `"serde"` and `"parallel"`
+/// to whether each is available. `serde` and `parallel` follow the cargo
+/// features of the same names; msgpack and JSON are always built in, and
+/// YAML is not supported.
+#[pyfunction]
+pub fn capabilities(py: Python<'_>) -> PyResult<Py<PyDict>> {
+    let capabilities = PyDict::new(py);
+    capabilities.set_item("msgpack", true)?;
+    capabilities.set_item("json", true)?;
+    capabilities.set_item("yaml", false)?;
+    capabilities.set_item("serde", cfg!(feature = "serde"))?;
+    capabilities.set_item("parallel", cfg!(feature = "parallel"))?;
+    Ok(capabilities.unbind())
+}
+
+/// Enables or disables NFC normalization of names, process-wide.
+///
+/// When enabled, names passed to the constructor, the `name` setter and
+/// `from_dict` are converted to Unicode Normalization Form C, so visually
+/// identical names compare equal. Disabled by default.
+///
+/// # Arguments
+/// * `enabled` - Whether to normalize incoming names
+#[pyfunction]
+pub fn set_name_normalization(enabled: bool) {
+    names::set_normalization(enabled);
+}
+
+/// Sets the name pattern that Python data must match when converted to a
+/// Person.
+///
+/// It is enforced by `Person.from_dict` called without `name_pattern`, by
+/// `Person.validate` (and so by Pydantic validation) and
+/// `validate_with_warnings`, whether given dicts or attribute-style objects,
+/// and by `persons_from_iter`. The pattern is compiled once here rather than
+/// on every call.
+///
+/// # Arguments
+/// * `pattern` - A regular expression, or `None` to stop enforcing a default
+#[pyfunction]
+pub fn set_default_name_pattern(pattern: Option<String>) -> PyResult<()> {
+    names::set_default_pattern(pattern.as_deref())
+}
+
+/// Chooses how `Person.__repr__` formats trees, process-wide.
+///
+/// `"debug"` (the default) gives the `{:?}` form, also available through
+/// `Person.debug_repr`; `"tree"` gives the `render_tree` output, which reads
+/// better in notebooks and at the REPL.
+///
+/// # Arguments
+/// * `mode` - Either `"tree"` or `"debug"`
+#[pyfunction]
+pub fn set_repr_mode(mode: &str) -> PyResult<()> {
+    render::set_repr_mode(mode)
 }
 
 /// The main entry point for the application.
@@ -282,9 +1932,37 @@ fn main() -> anyhow::Result<()> {
         let my_module = PyModule::new(py, "py03_pydantic_ormsgpack_experiment")?;
 
         my_module.add_class::<Person>()?;
+        my_module.add_class::<cow::CowPerson>()?;
+        my_module.add_class::<forest::Forest>()?;
+        my_module.add_class::<iter::NameIterator>()?;
+        my_module.add_class::<iter::DictIterator>()?;
+        my_module.add_class::<iter::PathIterator>()?;
+        my_module.add_class::<zipper::PersonZipper>()?;
+        my_module.add_class::<person_rng::PersonRng>()?;
+        my_module.add_class::<view::PersonRef>()?;
+        errors::register(&my_module)?;
+        my_module.add("__version__", env!("CARGO_PKG_VERSION"))?;
         my_module.add_function(wrap_pyfunction!(new_person, &my_module)?)?;
         my_module.add_function(wrap_pyfunction!(create_random_person, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(create_random_person_from, &my_module)?)?;
         my_module.add_function(wrap_pyfunction!(create_nested_person, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(create_balanced_person, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(create_chain, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(read_msgpack_log, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(from_csv, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(persons_from_iter, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(to_columns, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(children_histogram, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(to_flat_arrays, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(from_flat_arrays, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(decode_persons_parallel, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(set_decode_cache_size, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(clear_decode_cache, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(set_name_normalization, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(set_default_name_pattern, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(set_repr_mode, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(emit_type_stubs, &my_module)?)?;
+        my_module.add_function(wrap_pyfunction!(capabilities, &my_module)?)?;
 
         // Import and get sys.modules
         let sys = PyModule::import(py, "sys")?;
@@ -307,3 +1985,302 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use pyo3::types::PyAnyMethods;
+    use pyo3::types::PyList;
+    use pyo3::types::PyListMethods;
+
+    use super::*;
+
+    fn person() -> impl Strategy<Value = Person> {
+        let leaf =
+            (any::<String>(), any::<u32>()).prop_map(|(name, age)| Person::new(name, age, vec![]));
+        leaf.prop_recursive(4, 32, 4, |inner| {
+            (
+                any::<String>(),
+                any::<u32>(),
+                prop::collection::vec(inner, 0..4),
+            )
+                .prop_map(|(name, age, children)| Person::new(name, age, children))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn dict_round_trip(person in person()) {
+            Python::with_gil(|py| {
+                let dict = export::to_dict(&person, py, &export::DictOptions::default()).unwrap();
+                let mapping = dict.as_any().downcast::<PyMapping>().unwrap();
+                let decoded = ingest::from_dict(mapping, &ingest::FromDictOptions::default()).unwrap();
+                prop_assert_eq!(decoded, person);
+                Ok(())
+            })?;
+        }
+
+        #[test]
+        fn json_round_trip(person in person()) {
+            let decoded = json::person_from_json(&json::person_to_json(&person, None)).unwrap();
+            prop_assert_eq!(decoded, person);
+        }
+
+        #[test]
+        fn msgpack_round_trip(person in person()) {
+            let decoded = msgpack::decode(&msgpack::encode(&person)).unwrap();
+            prop_assert_eq!(decoded, person);
+        }
+    }
+
+    fn chain(len: usize, leaf_age: u32) -> Person {
+        let mut ages: Vec<u32> = (0..len as u32).collect();
+        *ages.last_mut().unwrap() = leaf_age;
+        create_chain((0..len).map(|i| format!("n{}", i)).collect(), ages).unwrap()
+    }
+
+    #[test]
+    fn deep_trees_compare_without_overflowing() {
+        let a = chain(100_000, 0);
+        let b = chain(100_000, 0);
+        assert!(a == b);
+        assert!(a.__eq__(&b));
+        assert!(a != chain(100_000, 1));
+        assert!(!a.__eq__(&chain(100_000, 1)));
+        assert!(a != chain(99_999, 0));
+    }
+
+    /// A tree shaped like `Person` with a derived `Debug`, as the reference for
+    /// the hand-written one.
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Derived {
+        name: String,
+        age: u32,
+        children: Vec<Derived>,
+    }
+
+    fn derived(person: &Person) -> Derived {
+        Derived {
+            name: person.name.clone(),
+            age: person.age,
+            children: person.children.iter().map(derived).collect(),
+        }
+    }
+
+    #[test]
+    fn debug_matches_the_derived_form() {
+        let leaf = |name: &str| Person::new(name.to_string(), 1, vec![]);
+        let root = Person::new(
+            "root \"quoted\"".to_string(),
+            40,
+            vec![
+                Person::new("mid".to_string(), 20, vec![leaf("a"), leaf("b")]),
+                leaf("c"),
+            ],
+        );
+        for person in [&root, &root.children[1]] {
+            assert_eq!(
+                format!("{:?}", person),
+                format!("{:?}", derived(person)).replace("Derived", "Person")
+            );
+            assert_eq!(
+                format!("{:#?}", person),
+                format!("{:#?}", derived(person)).replace("Derived", "Person")
+            );
+        }
+    }
+
+    #[test]
+    fn deep_chains_clone_compare_and_format() {
+        let original = chain(100_000, 0);
+        let copy = original.clone();
+        assert!(copy == original);
+        assert!(format!("{:?}", copy).ends_with(&"] }".repeat(100_000)));
+
+        Python::with_gil(|py| {
+            let object = Py::new(py, copy).unwrap();
+            let children = object.getattr(py, "children").unwrap();
+            let child: Person = children.bind(py).get_item(0).unwrap().extract().unwrap();
+            assert!(child == original.children[0]);
+            let extracted: Person = object.extract(py).unwrap();
+            assert!(extracted == original);
+        });
+    }
+
+    #[test]
+    fn observers_survive_apply_patch() {
+        Python::with_gil(|py| {
+            let old = create_balanced_person(2, 2);
+            let mut new = old.clone();
+            new.age = 99;
+            new.children.pop();
+            let patch = PyBytes::new(py, &patch::make_patch(&old, &new));
+
+            let object = Py::new(py, old).unwrap();
+            let changes = PyList::empty(py);
+            let observer = changes.getattr("append").unwrap();
+            object
+                .call_method1(py, "set_on_change", (observer,))
+                .unwrap();
+            object.call_method1(py, "apply_patch", (patch,)).unwrap();
+            assert!(*object.borrow(py) == new);
+
+            object.setattr(py, "age", 7).unwrap();
+            assert_eq!(changes.len(), 1);
+            let change = changes.get_item(0).unwrap();
+            assert_eq!(
+                change.get_item("op").unwrap().extract::<String>().unwrap(),
+                "set_age"
+            );
+        });
+    }
+
+    /// Registers a module holding `Person` under the name its class reports,
+    /// so that pickle can find it.
+    fn register_module(py: Python<'_>) {
+        let module = PyModule::new(py, "py03_pydantic_ormsgpack_experiment").unwrap();
+        module.add_class::<Person>().unwrap();
+        let modules = PyModule::import(py, "sys")
+            .unwrap()
+            .getattr("modules")
+            .unwrap();
+        modules
+            .set_item("py03_pydantic_ormsgpack_experiment", module)
+            .unwrap();
+    }
+
+    #[test]
+    fn pickles_round_trip_with_every_protocol() {
+        let person = Person::new(
+            "Ann §".to_string(),
+            60,
+            vec![
+                create_balanced_person(2, 2),
+                create_chain(vec!["Bob".to_string()], vec![7]).unwrap(),
+            ],
+        );
+        Python::with_gil(|py| {
+            register_module(py);
+            let object = Py::new(py, person.clone()).unwrap();
+            for protocol in 0..=5 {
+                let (callable, _) = object
+                    .call_method1(py, "__reduce_ex__", (protocol,))
+                    .unwrap()
+                    .extract::<(Bound<'_, PyAny>, Bound<'_, PyAny>)>(py)
+                    .unwrap();
+                let name: String = callable.getattr("__name__").unwrap().extract().unwrap();
+                let expected = if protocol < 3 {
+                    "_from_pickled_dict"
+                } else {
+                    "from_msgpack"
+                };
+                assert_eq!(name, expected, "protocol {}", protocol);
+            }
+
+            let locals = PyDict::new(py);
+            locals.set_item("person", &object).unwrap();
+            let check = c"
+import pickle
+for protocol in range(pickle.HIGHEST_PROTOCOL + 1):
+    restored = pickle.loads(pickle.dumps(person, protocol))
+    assert restored == person, protocol
+";
+            py.run(check, None, Some(&locals)).unwrap();
+
+            // Names with a section sign do not match the default pattern.
+            set_default_name_pattern(Some("^[^§]*$".to_string())).unwrap();
+            let result = py.run(check, None, Some(&locals));
+            set_default_name_pattern(None).unwrap();
+            result.unwrap();
+        });
+    }
+
+    #[test]
+    fn positional_match_patterns_bind_name_age_and_children() {
+        let person = Person::new(
+            "Ann".to_string(),
+            60,
+            vec![Person::new("Bob".to_string(), 35, vec![])],
+        );
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            locals.set_item("p", person).unwrap();
+            locals.set_item("Person", py.get_type::<Person>()).unwrap();
+            py.run(
+                c"
+match p:
+    case Person(n, a, [Person(child, 35, [])]):
+        result = (n, a, child)
+    case _:
+        result = None
+match p.children[0]:
+    case Person(n, a, [_, *_]):
+        leaf = False
+    case Person(n, a, c):
+        leaf = (n, a, c) == ('Bob', 35, [])
+",
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+            let result: (String, u32, String) = locals
+                .get_item("result")
+                .unwrap()
+                .unwrap()
+                .extract()
+                .unwrap();
+            assert_eq!(result, ("Ann".to_string(), 60, "Bob".to_string()));
+            assert!(
+                locals
+                    .get_item("leaf")
+                    .unwrap()
+                    .unwrap()
+                    .extract::<bool>()
+                    .unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn the_constructor_accepts_the_names_from_dict_does() {
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            locals.set_item("Person", py.get_type::<Person>()).unwrap();
+            py.run(
+                c"
+for name in ['', ' ', 'Ann']:
+    built = Person(name=name, age=1)
+    assert built == Person.from_dict({'name': name, 'age': 1, 'children': []})
+    assert built == Person.validate({'name': name, 'age': 1, 'children': []})
+    renamed = Person(name='Bob', age=1)
+    renamed.name = name
+    assert renamed == built
+",
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn decode_persons_parallel_accepts_any_buffer() {
+        let person = create_balanced_person(2, 2);
+        let payload = msgpack::encode(&person);
+        Python::with_gil(|py| {
+            let bytes = PyBytes::new(py, &payload);
+            let buffers = [
+                bytes.clone().into_any(),
+                pyo3::types::PyByteArray::new(py, &payload).into_any(),
+                pyo3::types::PyMemoryView::from(&bytes).unwrap().into_any(),
+            ];
+            let buffers = buffers
+                .iter()
+                .map(|buffer| PyBuffer::get(buffer).unwrap())
+                .collect();
+            let people = decode_persons_parallel(py, buffers, 0).unwrap();
+            assert_eq!(people, [person.clone(), person.clone(), person]);
+        });
+    }
+}
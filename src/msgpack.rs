@@ -0,0 +1,1560 @@
+//! Native msgpack encoding for `Person` trees.
+//!
+//! Each person is encoded as a map with the keys `name`, `age` and `children`,
+//! in that order, which is exactly what `ormsgpack.packb(person.__dict__)`
+//! produces. Payloads written by either side can be read by the other.
+//!
+//! Both directions are iterative, so deep trees cannot overflow the stack.
+//! When decoding, keys may appear in any order and unknown keys are skipped.
+//!
+//! # Invalid UTF-8
+//!
+//! Producers in other languages can write msgpack strings that are not valid
+//! UTF-8, which a Rust `String` cannot hold. By default `decode` rejects them
+//! with a `PersonError` naming the field and the path of the person, such as
+//! ``Field `name` of children[0] is not valid UTF-8``. With
+//! `DecodeOptions::lossy` set, each invalid sequence is instead replaced with
+//! U+FFFD, like `bytes.decode("utf-8", "replace")`.
+//!
+//! # Compact keys
+//!
+//! To save bytes in large forests, maps may instead use integer keys:
+//!
+//! | Key | Field      |
+//! |-----|------------|
+//! | `0` | `name`     |
+//! | `1` | `age`      |
+//! | `2` | `children` |
+//! | `3` | `birthdate` (reserved, see below) |
+//!
+//! Each key is a single positive fixint byte, so a node saves 15 bytes over
+//! string keys. The decoder accepts either style, and even a mix, without
+//! being told which one was used; other integer keys are skipped like unknown
+//! string keys.
+//!
+//! # Positional arrays
+//!
+//! `encode_positional` writes each person as a three-element array
+//! `[name, age, children]` instead of a map, the form of a `(name, age,
+//! children)` tuple, saving the keys entirely. The decoder detects each node's
+//! form from its msgpack type, so maps and arrays may be mixed freely; elements
+//! after the third are skipped, like unknown map keys.
+//!
+//! # Breadth-first layout
+//!
+//! `encode_bfs` writes a different, flat layout: a single array with one
+//! element per person in level order, the root first, each a three-element
+//! array `[name, age, child_count]`:
+//!
+//! ```text
+//! [["Ann", 60, 2], ["Bob", 35, 1], ["Cid", 33, 0], ["Dan", 8, 0]]
+//! ```
+//!
+//! The children of each person are the next `child_count` people not yet
+//! claimed by an earlier person, so the counts alone give the shape of the
+//! tree. `decode_bfs` reads this layout only; as in the positional form,
+//! elements after the third are skipped. Since every node is a flat array,
+//! neither direction nests, and the decoder lays a wide tree's siblings out
+//! next to each other. Every node takes at least four bytes, so `decode_bfs`
+//! rejects an outer array claiming more nodes than the rest of the payload
+//! could hold before reading any of them; it takes the `max_nodes` and
+//! `max_bytes` limits described below.
+//!
+//! # Deduplicated payloads
+//!
+//! `encode_deduped` writes each distinct subtree once and replaces later copies
+//! with references, which `decode_deduped` expands again. It extends the
+//! format in two ways:
+//!
+//! - The first occurrence of a subtree that is repeated later carries an extra
+//!   `id` entry, written before the other entries: an unsigned integer,
+//!   numbering such subtrees 0, 1, 2, ... in pre-order.
+//! - An element of a `children` array may be an unsigned integer instead of a
+//!   map, meaning a copy of the subtree with that id. Identical subtrees are
+//!   found by content hash (see [`crate::hash`]).
+//!
+//! A tree without repeated subtrees encodes exactly as with `encode`, and the
+//! regular decoder reads any payload without references, ignoring `id`. On
+//! generated trees the savings can be large: a perfectly balanced tree
+//! (`create_balanced_person(6, 4)`, whose path-based names make every subtree
+//! distinct) does not shrink, but the same shape with uniform names shrinks
+//! from 125,603 to 203 bytes, since each level is one repeated subtree.
+//!
+//! # Limits
+//!
+//! Untrusted payloads can claim arrays far longer than the data behind them.
+//! The decoder never allocates by a claimed length, so a lying length prefix
+//! only ever fails as truncated, but a payload can still be genuinely huge, or
+//! expand hugely through subtree references. `DecodeOptions::max_bytes`
+//! rejects a payload longer than the limit before reading it, and
+//! `max_nodes` fails as soon as the nodes read plus those claimed by
+//! `children` arrays exceed the limit, so a claim of billions of children is
+//! rejected the moment its length prefix is read. A reference counts every
+//! node of the subtree it copies.
+//!
+//! Since a few bytes of references can expand to exponentially many nodes,
+//! `decode_deduped` applies `DEDUP_MAX_NODES` when no `max_nodes` is given.
+//!
+//! # Reserved: `birthdate`
+//!
+//! `Person` has no birthdate yet, but the `birthdate` key is reserved for one.
+//! When it is added it will be encoded as a msgpack timestamp (extension type
+//! -1) at midnight UTC of the date, never as a string, and `age` will remain
+//! the authoritative age so the two cannot disagree on decode. Until then the
+//! decoder skips a `birthdate` entry that is a well-formed timestamp and rejects
+//! any other encoding, so that producers cannot start relying on one.
+
+use pyo3::PyErr;
+use pyo3::PyResult;
+#[cfg(feature = "parallel")]
+use pyo3::exceptions::PyRuntimeError;
+use std::collections::HashMap;
+#[cfg(feature = "parallel")]
+use std::sync::Arc;
+#[cfg(feature = "parallel")]
+use std::sync::LazyLock;
+#[cfg(feature = "parallel")]
+use std::sync::Mutex;
+
+use rmp::Marker;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+use crate::hash;
+use crate::hash::ContentDigest;
+use crate::paths::describe;
+
+/// Key reserved for a future birthdate, encoded as a msgpack timestamp.
+const BIRTHDATE_KEY: &str = "birthdate";
+
+/// Key numbering repeated subtrees in deduplicated payloads.
+const ID_KEY: &str = "id";
+
+/// The node limit of `decode_deduped` when the caller sets none.
+pub const DEDUP_MAX_NODES: usize = 10_000_000;
+
+/// The chunk size used when streaming an encoding to a Python file object.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The msgpack extension type of timestamps.
+const TIMESTAMP_EXT_TYPE: i8 = -1;
+
+/// The compact integer keys of each field.
+const NAME_KEY: u8 = 0;
+const AGE_KEY: u8 = 1;
+const CHILDREN_KEY: u8 = 2;
+const BIRTHDATE_INT_KEY: u8 = 3;
+
+/// A decoded map key.
+enum Key {
+    Name,
+    Age,
+    Children,
+    Birthdate,
+    Id,
+    Unknown,
+}
+
+/// Encodes a person tree as msgpack with string keys.
+pub fn encode(person: &Person) -> Vec<u8> {
+    encode_with_keys(person, false)
+}
+
+/// Encodes a person tree as msgpack, with integer keys if `compact_keys` is set.
+pub fn encode_with_keys(person: &Person, compact_keys: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut stack = vec![person];
+
+    while let Some(node) = stack.pop() {
+        write_node(&mut out, node, compact_keys);
+        // Push in reverse so the first child is encoded first.
+        stack.extend(node.children.iter().rev());
+    }
+
+    out
+}
+
+/// Encodes a person tree as msgpack with string keys, passing the output to
+/// `emit` in chunks of roughly `chunk_size` bytes instead of building it whole.
+///
+/// The concatenated chunks equal `encode(person)`. Only about one chunk is held
+/// in memory at a time, and the first error returned by `emit` stops encoding.
+pub fn encode_chunked(
+    person: &Person,
+    chunk_size: usize,
+    mut emit: impl FnMut(&[u8]) -> PyResult<()>,
+) -> PyResult<()> {
+    let mut out = Vec::with_capacity(chunk_size);
+    let mut stack = vec![person];
+
+    while let Some(node) = stack.pop() {
+        write_node(&mut out, node, false);
+        if out.len() >= chunk_size {
+            emit(&out)?;
+            out.clear();
+        }
+        stack.extend(node.children.iter().rev());
+    }
+
+    if !out.is_empty() {
+        emit(&out)?;
+    }
+    Ok(())
+}
+
+/// Writes one person map up to and including its children array header.
+fn write_node(out: &mut Vec<u8>, node: &Person, compact_keys: bool) {
+    // Writing into a Vec cannot fail, so the results are safe to unwrap.
+    let write_key = |out: &mut Vec<u8>, name: &str, key: u8| {
+        if compact_keys {
+            rmp::encode::write_pfix(out, key).unwrap();
+        } else {
+            rmp::encode::write_str(out, name).unwrap();
+        }
+    };
+
+    rmp::encode::write_map_len(out, 3).unwrap();
+    write_key(out, "name", NAME_KEY);
+    rmp::encode::write_str(out, &node.name).unwrap();
+    write_key(out, "age", AGE_KEY);
+    rmp::encode::write_uint(out, u64::from(node.age)).unwrap();
+    write_key(out, "children", CHILDREN_KEY);
+    rmp::encode::write_array_len(out, node.children.len() as u32).unwrap();
+}
+
+/// Encodes a person tree as msgpack with each person as a positional
+/// `[name, age, children]` array.
+pub fn encode_positional(person: &Person) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut stack = vec![person];
+
+    // Writing into a Vec cannot fail, so the results are safe to unwrap.
+    while let Some(node) = stack.pop() {
+        rmp::encode::write_array_len(&mut out, 3).unwrap();
+        rmp::encode::write_str(&mut out, &node.name).unwrap();
+        rmp::encode::write_uint(&mut out, u64::from(node.age)).unwrap();
+        rmp::encode::write_array_len(&mut out, node.children.len() as u32).unwrap();
+
+        // Push in reverse so the first child is encoded first.
+        stack.extend(node.children.iter().rev());
+    }
+
+    out
+}
+
+/// Encodes a person tree in the breadth-first layout described in the module
+/// documentation.
+pub fn encode_bfs(person: &Person) -> Vec<u8> {
+    // The nodes in level order; the vector doubles as the queue.
+    let mut order = vec![person];
+    let mut next = 0;
+    while let Some(node) = order.get(next) {
+        order.extend(node.children.iter());
+        next += 1;
+    }
+
+    let mut out = Vec::new();
+    // Writing into a Vec cannot fail, so the results are safe to unwrap.
+    rmp::encode::write_array_len(&mut out, order.len() as u32).unwrap();
+    for node in order {
+        rmp::encode::write_array_len(&mut out, 3).unwrap();
+        rmp::encode::write_str(&mut out, &node.name).unwrap();
+        rmp::encode::write_uint(&mut out, u64::from(node.age)).unwrap();
+        rmp::encode::write_uint(&mut out, node.children.len() as u64).unwrap();
+    }
+    out
+}
+
+/// The fewest bytes a breadth-first node can take: a fixarray header, an
+/// empty fixstr name and fixint age and child count.
+const MIN_BFS_NODE_LEN: usize = 4;
+
+/// Decodes a person tree written by `encode_bfs`, within the limits in
+/// `options`; `dedup` and `lossy` do not apply to this layout.
+///
+/// Returns a `PersonError` if the payload is truncated, is not an array of
+/// node arrays, or has child counts that do not describe a single tree of
+/// exactly its nodes. Nothing is allocated by a claimed length before the
+/// bytes behind it are known to be there.
+pub fn decode_bfs(bytes: &[u8], options: &DecodeOptions) -> PyResult<Person> {
+    check_payload_len(bytes, options)?;
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        lossy: false,
+    };
+    let len = reader.read_array_header("Expected an array of nodes")? as usize;
+    if len == 0 {
+        return Err(reader.error("Breadth-first payload has no nodes"));
+    }
+    let room = (bytes.len() - reader.pos) / MIN_BFS_NODE_LEN;
+    if len > room {
+        return Err(reader.error(&format!(
+            "Breadth-first payload claims {} nodes, but has room for at most {}",
+            len, room
+        )));
+    }
+    reader.check_nodes(len, options)?;
+
+    // Every node's name, age, first child and child count. Children are
+    // claimed in order, so the first children never decrease.
+    let mut nodes: Vec<(String, u32, usize, usize)> = Vec::new();
+    // The number of nodes claimed as children so far; the next claimed node
+    // is `claimed + 1`.
+    let mut claimed = 0;
+    for index in 0..len {
+        if index > claimed {
+            return Err(reader.error(&format!(
+                "Node {} is not a child of any earlier node",
+                index
+            )));
+        }
+        let start = reader.pos;
+        let elements = reader.read_array_header("Expected a node array")?;
+        if elements < 3 {
+            reader.pos = start;
+            return Err(reader.error(&format!(
+                "Node array has {} elements, expected at least 3",
+                elements
+            )));
+        }
+        let name = reader.read_name(|| bfs_path(&nodes, index))?;
+        let age = reader.read_age()?;
+        let count_start = reader.pos;
+        let count = reader.read_uint("Expected an unsigned integer child count")?;
+        let first = claimed + 1;
+        if count > (len - first) as u64 {
+            reader.pos = count_start;
+            return Err(reader.error(&format!(
+                "Node {} claims {} children, but only {} unclaimed nodes remain",
+                index,
+                count,
+                len - first
+            )));
+        }
+        for _ in 3..elements {
+            reader.skip_value()?;
+        }
+        let count = count as usize;
+        claimed += count;
+        nodes.push((name, age, first, count));
+    }
+    if reader.pos != bytes.len() {
+        return Err(reader.error("Unexpected trailing data"));
+    }
+
+    // Every node's children come after it, so building in reverse level order
+    // finds them all built.
+    let mut built: Vec<Option<Person>> = (0..len).map(|_| None).collect();
+    for (index, (name, age, first, count)) in nodes.into_iter().enumerate().rev() {
+        let children = (first..first + count)
+            .map(|child| built[child].take().unwrap())
+            .collect();
+        built[index] = Some(Person::new(name, age, children));
+    }
+    Ok(built[0].take().unwrap())
+}
+
+/// The path of node `index` of a breadth-first payload, given the nodes read
+/// before it.
+fn bfs_path(nodes: &[(String, u32, usize, usize)], index: usize) -> Vec<usize> {
+    let mut path = vec![];
+    let mut node = index;
+    while node > 0 {
+        // The parent is the last node whose children start at or before this one.
+        let parent = nodes.partition_point(|&(_, _, first, _)| first <= node) - 1;
+        path.push(node - nodes[parent].2);
+        node = parent;
+    }
+    path.reverse();
+    path
+}
+
+/// Computes the exact length of `encode_with_keys(person, compact_keys)`
+/// without encoding anything.
+///
+/// Every value is written in the smallest msgpack form that holds it, so the
+/// size of each node follows from its name length, age and child count alone:
+/// a one-byte map header, the three keys, and headers sized by magnitude for
+/// the name string, the age integer and the children array.
+pub fn encoded_len(person: &Person, compact_keys: bool) -> usize {
+    let keys_len = if compact_keys {
+        3
+    } else {
+        // "name", "age" and "children" as fixstrs.
+        5 + 4 + 9
+    };
+    let mut total = 0;
+    let mut stack = vec![person];
+
+    while let Some(node) = stack.pop() {
+        let name_header = match node.name.len() {
+            0..32 => 1,
+            32..256 => 2,
+            256..65536 => 3,
+            _ => 5,
+        };
+        let age_len = match node.age {
+            0..128 => 1,
+            128..256 => 2,
+            256..65536 => 3,
+            _ => 5,
+        };
+        let children_header = match node.children.len() {
+            0..16 => 1,
+            16..65536 => 3,
+            _ => 5,
+        };
+        total += 1 + keys_len + name_header + node.name.len() + age_len + children_header;
+        stack.extend(node.children.iter());
+    }
+
+    total
+}
+
+/// Encodes a person tree as msgpack, replacing repeated subtrees with
+/// references as described in the module documentation.
+pub fn encode_deduped(person: &Person) -> Vec<u8> {
+    let subtrees = hash::subtree_digests(person);
+    let mut occurrences: HashMap<ContentDigest, usize> = HashMap::new();
+    for (digest, _) in &subtrees {
+        *occurrences.entry(*digest).or_default() += 1;
+    }
+
+    let mut out = Vec::new();
+    let mut ids: HashMap<ContentDigest, u64> = HashMap::new();
+    let mut stack = vec![(person, 0)];
+
+    while let Some((node, position)) = stack.pop() {
+        // Writing into a Vec cannot fail, so the results are safe to unwrap.
+        let digest = subtrees[position].0;
+        if let Some(&id) = ids.get(&digest) {
+            rmp::encode::write_uint(&mut out, id).unwrap();
+            continue;
+        }
+        let repeated = occurrences[&digest] > 1;
+
+        rmp::encode::write_map_len(&mut out, if repeated { 4 } else { 3 }).unwrap();
+        // The id comes first, since the children follow their array header.
+        if repeated {
+            let id = ids.len() as u64;
+            ids.insert(digest, id);
+            rmp::encode::write_str(&mut out, ID_KEY).unwrap();
+            rmp::encode::write_uint(&mut out, id).unwrap();
+        }
+        rmp::encode::write_str(&mut out, "name").unwrap();
+        rmp::encode::write_str(&mut out, &node.name).unwrap();
+        rmp::encode::write_str(&mut out, "age").unwrap();
+        rmp::encode::write_uint(&mut out, u64::from(node.age)).unwrap();
+        rmp::encode::write_str(&mut out, "children").unwrap();
+        rmp::encode::write_array_len(&mut out, node.children.len() as u32).unwrap();
+
+        // Children start right after their parent in pre-order, each after the
+        // previous child's whole subtree.
+        let mut child_position = position + 1;
+        let mut children = Vec::with_capacity(node.children.len());
+        for child in &node.children {
+            children.push((child, child_position));
+            child_position += subtrees[child_position].1;
+        }
+        // Push in reverse so the first child is encoded first.
+        stack.extend(children.into_iter().rev());
+    }
+
+    out
+}
+
+/// Options controlling how `decode_with` reads a payload.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Accept subtree references written by `encode_deduped`.
+    pub dedup: bool,
+    /// Replace invalid UTF-8 in strings with U+FFFD instead of failing.
+    pub lossy: bool,
+    /// Reject trees with more nodes than this, as described under "Limits".
+    pub max_nodes: Option<usize>,
+    /// Reject payloads longer than this many bytes.
+    pub max_bytes: Option<usize>,
+}
+
+/// Decodes a person tree from msgpack, expanding subtree references written
+/// by `encode_deduped`.
+///
+/// Expanded nodes count toward `max_nodes`, which defaults to
+/// `DEDUP_MAX_NODES`; `max_bytes` limits the payload as in `decode_with`.
+pub fn decode_deduped(
+    bytes: &[u8],
+    max_nodes: Option<usize>,
+    max_bytes: Option<usize>,
+) -> PyResult<Person> {
+    decode_with(
+        bytes,
+        &DecodeOptions {
+            dedup: true,
+            max_nodes: Some(max_nodes.unwrap_or(DEDUP_MAX_NODES)),
+            max_bytes,
+            ..DecodeOptions::default()
+        },
+    )
+}
+
+/// Decodes a person tree from msgpack.
+///
+/// Each person may be a map or a positional array. Returns a `PersonError` if
+/// the payload is truncated, is not a person map or array, or has trailing
+/// bytes after the root.
+pub fn decode(bytes: &[u8]) -> PyResult<Person> {
+    decode_with(bytes, &DecodeOptions::default())
+}
+
+/// Fails if `bytes` is longer than `options.max_bytes`.
+fn check_payload_len(bytes: &[u8], options: &DecodeOptions) -> PyResult<()> {
+    match options.max_bytes {
+        Some(max) if bytes.len() > max => Err(ErrorCode::PayloadTooLarge.err(format!(
+            "Msgpack payload is {} bytes, more than the maximum of {}",
+            bytes.len(),
+            max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Decodes a person tree from msgpack as selected by `options`.
+pub fn decode_with(bytes: &[u8], options: &DecodeOptions) -> PyResult<Person> {
+    check_payload_len(bytes, options)?;
+
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        lossy: options.lossy,
+    };
+    let person = read_tree(&mut reader, options)?;
+    if reader.pos != bytes.len() {
+        return Err(reader.error("Unexpected trailing data"));
+    }
+    Ok(person)
+}
+
+/// Encodes a forest as a msgpack array of person maps with string keys, as
+/// `ormsgpack.packb` produces for a list of person dicts.
+pub fn encode_forest(roots: &[Person]) -> Vec<u8> {
+    let mut out = Vec::new();
+    // Writing into a Vec cannot fail, so the result is safe to unwrap.
+    rmp::encode::write_array_len(&mut out, roots.len() as u32).unwrap();
+    for root in roots {
+        out.extend(encode(root));
+    }
+    out
+}
+
+/// Decodes a forest written by `encode_forest`.
+///
+/// Each root may use any form `decode` accepts.
+pub fn decode_forest(bytes: &[u8]) -> PyResult<Vec<Person>> {
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        lossy: false,
+    };
+    let len = reader.read_array_header("Expected an array of persons")?;
+    let mut roots = Vec::new();
+    for _ in 0..len {
+        roots.push(read_tree(&mut reader, &DecodeOptions::default())?);
+    }
+    if reader.pos != bytes.len() {
+        return Err(reader.error("Unexpected trailing data"));
+    }
+    Ok(roots)
+}
+
+/// Reads one person tree starting at the reader's position, leaving the
+/// reader just after it.
+fn read_tree(reader: &mut Reader<'_>, options: &DecodeOptions) -> PyResult<Person> {
+    // Nodes read so far plus those claimed by `children` arrays, as described
+    // under "Limits".
+    let mut nodes = 1;
+    let mut open: Vec<Frame> = vec![reader.read_frame(nodes)?];
+    reader.check_nodes(nodes, options)?;
+    // Subtrees with an `id`, once complete, with their node counts.
+    let mut shared: HashMap<u64, (Person, usize)> = HashMap::new();
+
+    loop {
+        let frame = open.last_mut().unwrap();
+
+        if frame.children_left > 0 {
+            if options.dedup
+                && let Some(id) = reader.read_reference()?
+            {
+                let (subtree, size) = shared
+                    .get(&id)
+                    .ok_or_else(|| reader.error(&format!("Unknown subtree reference {}", id)))?;
+                // The reference itself was counted as one claimed child.
+                nodes += size - 1;
+                reader.check_nodes(nodes, options)?;
+                frame.children.push(subtree.clone());
+                frame.children_left -= 1;
+                continue;
+            }
+            let child = reader.read_frame(nodes)?;
+            open.push(child);
+            continue;
+        }
+
+        if frame.entries_left > 0 {
+            frame.entries_left -= 1;
+            let key = match frame.position.as_mut() {
+                Some(position) => {
+                    *position += 1;
+                    match *position {
+                        1 => Key::Name,
+                        2 => Key::Age,
+                        3 => Key::Children,
+                        _ => Key::Unknown,
+                    }
+                }
+                None => reader.read_key()?,
+            };
+            match key {
+                Key::Name => {
+                    // Children are attached once complete, so each open ancestor's
+                    // child count is the index of the frame below it.
+                    let path = || -> Vec<usize> {
+                        open[..open.len() - 1]
+                            .iter()
+                            .map(|frame| frame.children.len())
+                            .collect()
+                    };
+                    let name = reader.read_name(path)?;
+                    open.last_mut().unwrap().name = Some(name);
+                }
+                Key::Age => frame.age = Some(reader.read_age()?),
+                Key::Children => {
+                    frame.children_left = reader.read_array_len()?;
+                    nodes += frame.children_left as usize;
+                    reader.check_nodes(nodes, options)?;
+                }
+                Key::Birthdate => reader.skip_timestamp()?,
+                Key::Id if options.dedup => frame.id = Some(reader.read_id()?),
+                Key::Id | Key::Unknown => reader.skip_value()?,
+            }
+            continue;
+        }
+
+        let frame = open.pop().unwrap();
+        let id = frame.id;
+        let size = nodes - frame.nodes_at_start + 1;
+        let person = frame.finish()?;
+        if let Some(id) = id {
+            shared.insert(id, (person.clone(), size));
+        }
+        match open.last_mut() {
+            Some(parent) => {
+                parent.children.push(person);
+                parent.children_left -= 1;
+            }
+            None => return Ok(person),
+        }
+    }
+}
+
+/// Thread pools for `decode_all`, by number of threads, built on first use
+/// and kept for the life of the process.
+#[cfg(feature = "parallel")]
+static POOLS: LazyLock<Mutex<HashMap<usize, Arc<rayon::ThreadPool>>>> =
+    LazyLock::new(Default::default);
+
+/// Returns the cached pool of `threads` workers, building it if needed.
+#[cfg(feature = "parallel")]
+fn pool(threads: usize) -> PyResult<Arc<rayon::ThreadPool>> {
+    // The map holds no invariants a panic could break, so recover from poisoning.
+    let mut pools = POOLS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(pool) = pools.get(&threads) {
+        return Ok(Arc::clone(pool));
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|err| PyRuntimeError::new_err(format!("Cannot start thread pool: {}", err)))?;
+    let pool = Arc::new(pool);
+    pools.insert(threads, Arc::clone(&pool));
+    Ok(pool)
+}
+
+/// Decodes many independent payloads, preserving their order.
+///
+/// With the `parallel` feature the payloads are spread over rayon workers:
+/// with `threads` set to `0`, those of rayon's global pool, normally one per
+/// core, and otherwise those of a pool of exactly `threads` workers, built
+/// once per size and reused by later calls. Without the feature they are
+/// decoded in turn on the calling thread. Valid payloads are decoded without
+/// the Python interpreter, so callers should release the GIL around this. An
+/// invalid payload is different: its `PersonError` is built, GIL held, on the
+/// worker that found it, which would deadlock if the caller still held the
+/// GIL while waiting for the workers. If several payloads are invalid, the
+/// error for the earliest one is returned.
+pub fn decode_all(payloads: &[Vec<u8>], threads: usize) -> PyResult<Vec<Person>> {
+    #[cfg(feature = "parallel")]
+    let results: Vec<PyResult<Person>> = {
+        use rayon::prelude::*;
+
+        let decode_each = || payloads.par_iter().map(|payload| decode(payload)).collect();
+        match threads {
+            0 => decode_each(),
+            _ => pool(threads)?.install(decode_each),
+        }
+    };
+    #[cfg(not(feature = "parallel"))]
+    let results: Vec<PyResult<Person>> = {
+        let _ = threads;
+        payloads.iter().map(|payload| decode(payload)).collect()
+    };
+
+    results.into_iter().collect()
+}
+
+/// A person map that is still being decoded.
+struct Frame {
+    start: usize,
+    /// The decoder's node count when the frame was started, which counted
+    /// the frame itself.
+    nodes_at_start: usize,
+    name: Option<String>,
+    age: Option<u32>,
+    children: Vec<Person>,
+    /// The `id` of a subtree referenced later in a deduplicated payload.
+    id: Option<u64>,
+    /// For a positional array, the number of elements read so far; `None` for
+    /// a map.
+    position: Option<u32>,
+    /// Map entries or array elements not read yet.
+    entries_left: u32,
+    /// Elements of the `children` array not read yet.
+    children_left: u32,
+}
+
+impl Frame {
+    fn finish(self) -> PyResult<Person> {
+        let start = self.start;
+        let missing = move |field: &str| {
+            ErrorCode::MissingField.err(format!(
+                "Person map at byte {} is missing `{}`",
+                start, field
+            ))
+        };
+
+        Ok(Person::new(
+            self.name.ok_or_else(|| missing("name"))?,
+            self.age.ok_or_else(|| missing("age"))?,
+            self.children,
+        ))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Whether invalid UTF-8 in strings is replaced rather than rejected.
+    lossy: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> PyErr {
+        self.coded_error(ErrorCode::Malformed, message)
+    }
+
+    fn coded_error(&self, code: ErrorCode, message: &str) -> PyErr {
+        code.err(format!(
+            "{} at byte {} of msgpack payload",
+            message, self.pos
+        ))
+    }
+
+    /// Fails if `nodes` exceeds the node limit in `options`.
+    fn check_nodes(&self, nodes: usize, options: &DecodeOptions) -> PyResult<()> {
+        match options.max_nodes {
+            Some(max) if nodes > max => Err(self.coded_error(
+                ErrorCode::TooManyNodes,
+                &format!("Tree has more than the maximum of {} nodes", max),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> PyResult<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| self.error("Truncated msgpack value"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_be(&mut self, len: usize) -> PyResult<u64> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0u64, |acc, &byte| (acc << 8) | u64::from(byte)))
+    }
+
+    fn read_marker(&mut self) -> PyResult<Marker> {
+        Ok(Marker::from_u8(self.take(1)?[0]))
+    }
+
+    /// Reads the header of a person map or array and starts a frame for it,
+    /// given the decoder's node count including the new frame.
+    fn read_frame(&mut self, nodes_at_start: usize) -> PyResult<Frame> {
+        let start = self.pos;
+        let (entries_left, position) = match self.read_marker()? {
+            Marker::FixMap(len) => (u32::from(len), None),
+            Marker::Map16 => (self.read_be(2)? as u32, None),
+            Marker::Map32 => (self.read_be(4)? as u32, None),
+            Marker::FixArray(len) => (u32::from(len), Some(0)),
+            Marker::Array16 => (self.read_be(2)? as u32, Some(0)),
+            Marker::Array32 => (self.read_be(4)? as u32, Some(0)),
+            _ => {
+                self.pos = start;
+                return Err(self.error("Expected a person map or array"));
+            }
+        };
+        if position.is_some() && entries_left < 3 {
+            self.pos = start;
+            return Err(self.error(&format!(
+                "Person array has {} elements, expected at least 3",
+                entries_left
+            )));
+        }
+
+        Ok(Frame {
+            start,
+            nodes_at_start,
+            name: None,
+            age: None,
+            children: vec![],
+            id: None,
+            position,
+            entries_left,
+            children_left: 0,
+        })
+    }
+
+    /// Reads a map key, either a string or a compact integer key.
+    fn read_key(&mut self) -> PyResult<Key> {
+        let start = self.pos;
+        if let Marker::FixPos(key) = self.read_marker()? {
+            return Ok(match key {
+                NAME_KEY => Key::Name,
+                AGE_KEY => Key::Age,
+                CHILDREN_KEY => Key::Children,
+                BIRTHDATE_INT_KEY => Key::Birthdate,
+                _ => Key::Unknown,
+            });
+        }
+        self.pos = start;
+
+        Ok(match self.read_str()?.as_str() {
+            "name" => Key::Name,
+            "age" => Key::Age,
+            "children" => Key::Children,
+            BIRTHDATE_KEY => Key::Birthdate,
+            ID_KEY => Key::Id,
+            _ => Key::Unknown,
+        })
+    }
+
+    fn read_str(&mut self) -> PyResult<String> {
+        let start = self.pos;
+        let bytes = self.read_str_bytes()?;
+        if self.lossy {
+            return Ok(String::from_utf8_lossy(bytes).into_owned());
+        }
+        String::from_utf8(bytes.to_vec()).map_err(|_| {
+            self.pos = start;
+            self.error("String is not valid UTF-8")
+        })
+    }
+
+    /// Reads the `name` of a person, naming the person's path if it is not
+    /// valid UTF-8.
+    fn read_name(&mut self, path: impl FnOnce() -> Vec<usize>) -> PyResult<String> {
+        let start = self.pos;
+        let bytes = self.read_str_bytes()?;
+        if self.lossy {
+            return Ok(String::from_utf8_lossy(bytes).into_owned());
+        }
+        String::from_utf8(bytes.to_vec()).map_err(|_| {
+            self.pos = start;
+            self.coded_error(
+                ErrorCode::InvalidName,
+                &format!("Field `name` of {} is not valid UTF-8", describe(&path())),
+            )
+        })
+    }
+
+    /// Reads a string header and returns the raw bytes of the string.
+    fn read_str_bytes(&mut self) -> PyResult<&'a [u8]> {
+        let start = self.pos;
+        let len = match self.read_marker()? {
+            Marker::FixStr(len) => usize::from(len),
+            Marker::Str8 => self.read_be(1)? as usize,
+            Marker::Str16 => self.read_be(2)? as usize,
+            Marker::Str32 => self.read_be(4)? as usize,
+            _ => {
+                self.pos = start;
+                return Err(self.error("Expected a string"));
+            }
+        };
+        self.take(len)
+    }
+
+    fn read_age(&mut self) -> PyResult<u32> {
+        let start = self.pos;
+        let age: i128 = match self.read_marker()? {
+            Marker::FixPos(n) => i128::from(n),
+            Marker::FixNeg(n) => i128::from(n),
+            Marker::U8 => i128::from(self.read_be(1)?),
+            Marker::U16 => i128::from(self.read_be(2)?),
+            Marker::U32 => i128::from(self.read_be(4)?),
+            Marker::U64 => i128::from(self.read_be(8)?),
+            Marker::I8 => i128::from(self.read_be(1)? as u8 as i8),
+            Marker::I16 => i128::from(self.read_be(2)? as u16 as i16),
+            Marker::I32 => i128::from(self.read_be(4)? as u32 as i32),
+            Marker::I64 => i128::from(self.read_be(8)? as i64),
+            _ => {
+                self.pos = start;
+                return Err(self.coded_error(ErrorCode::InvalidAge, "Expected an integer age"));
+            }
+        };
+
+        u32::try_from(age).map_err(|_| {
+            self.pos = start;
+            self.coded_error(
+                ErrorCode::InvalidAge,
+                &format!("Age {} is out of range", age),
+            )
+        })
+    }
+
+    /// Reads an unsigned integer subtree id.
+    fn read_id(&mut self) -> PyResult<u64> {
+        self.read_uint("Expected an unsigned integer subtree id")
+    }
+
+    /// Reads an unsigned integer, failing with `expected` otherwise.
+    fn read_uint(&mut self, expected: &str) -> PyResult<u64> {
+        let start = self.pos;
+        match self.read_marker()? {
+            Marker::FixPos(n) => Ok(u64::from(n)),
+            Marker::U8 => self.read_be(1),
+            Marker::U16 => self.read_be(2),
+            Marker::U32 => self.read_be(4),
+            Marker::U64 => self.read_be(8),
+            _ => {
+                self.pos = start;
+                Err(self.error(expected))
+            }
+        }
+    }
+
+    /// Reads a subtree reference if the next value is one, leaving the reader
+    /// untouched if it is anything else.
+    fn read_reference(&mut self) -> PyResult<Option<u64>> {
+        let start = self.pos;
+        match self.read_id() {
+            Ok(id) => Ok(Some(id)),
+            Err(_) => {
+                self.pos = start;
+                Ok(None)
+            }
+        }
+    }
+
+    fn read_array_len(&mut self) -> PyResult<u32> {
+        self.read_array_header("Expected an array of children")
+    }
+
+    /// Reads an array header, failing with `expected` otherwise.
+    fn read_array_header(&mut self, expected: &str) -> PyResult<u32> {
+        let start = self.pos;
+        match self.read_marker()? {
+            Marker::FixArray(len) => Ok(u32::from(len)),
+            Marker::Array16 => Ok(self.read_be(2)? as u32),
+            Marker::Array32 => Ok(self.read_be(4)? as u32),
+            _ => {
+                self.pos = start;
+                Err(self.error(expected))
+            }
+        }
+    }
+
+    /// Skips a msgpack timestamp, failing if the value is anything else.
+    ///
+    /// Timestamps are extension type -1 with 4, 8 or 12 bytes of data.
+    fn skip_timestamp(&mut self) -> PyResult<()> {
+        let start = self.pos;
+        let len = match self.read_marker()? {
+            Marker::FixExt4 => 4,
+            Marker::FixExt8 => 8,
+            Marker::Ext8 if self.read_be(1)? == 12 => 12,
+            _ => 0,
+        };
+        if len == 0 || self.read_be(1)? as u8 as i8 != TIMESTAMP_EXT_TYPE {
+            self.pos = start;
+            return Err(self.error("Expected `birthdate` to be a msgpack timestamp"));
+        }
+        self.take(len)?;
+        Ok(())
+    }
+
+    /// Skips one complete value of any type, including nested containers.
+    fn skip_value(&mut self) -> PyResult<()> {
+        let mut pending: u64 = 1;
+
+        while pending > 0 {
+            pending -= 1;
+            let start = self.pos;
+            let (skip, nested) = match self.read_marker()? {
+                Marker::FixPos(_)
+                | Marker::FixNeg(_)
+                | Marker::Null
+                | Marker::True
+                | Marker::False => (0, 0),
+                Marker::U8 | Marker::I8 => (1, 0),
+                Marker::U16 | Marker::I16 => (2, 0),
+                Marker::U32 | Marker::I32 | Marker::F32 => (4, 0),
+                Marker::U64 | Marker::I64 | Marker::F64 => (8, 0),
+                Marker::FixStr(len) => (u64::from(len), 0),
+                Marker::Str8 | Marker::Bin8 => (self.read_be(1)?, 0),
+                Marker::Str16 | Marker::Bin16 => (self.read_be(2)?, 0),
+                Marker::Str32 | Marker::Bin32 => (self.read_be(4)?, 0),
+                Marker::FixArray(len) => (0, u64::from(len)),
+                Marker::Array16 => (0, self.read_be(2)?),
+                Marker::Array32 => (0, self.read_be(4)?),
+                Marker::FixMap(len) => (0, 2 * u64::from(len)),
+                Marker::Map16 => (0, 2 * self.read_be(2)?),
+                Marker::Map32 => (0, 2 * self.read_be(4)?),
+                // The extension type byte is skipped along with the data.
+                Marker::FixExt1 => (2, 0),
+                Marker::FixExt2 => (3, 0),
+                Marker::FixExt4 => (5, 0),
+                Marker::FixExt8 => (9, 0),
+                Marker::FixExt16 => (17, 0),
+                Marker::Ext8 => (self.read_be(1)? + 1, 0),
+                Marker::Ext16 => (self.read_be(2)? + 1, 0),
+                Marker::Ext32 => (self.read_be(4)? + 1, 0),
+                Marker::Reserved => {
+                    self.pos = start;
+                    return Err(self.error("Reserved msgpack marker"));
+                }
+            };
+
+            let skip = usize::try_from(skip).map_err(|_| self.error("Truncated msgpack value"))?;
+            self.take(skip)?;
+            pending += nested;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::code_of;
+
+    /// A payload of `levels` nested definitions, each holding the previous
+    /// one and a reference to it, so that it expands to `2^(levels + 1) - 1`
+    /// nodes.
+    fn doubling_payload(levels: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        for id in (0..=levels).rev() {
+            rmp::encode::write_map_len(&mut out, 4).unwrap();
+            rmp::encode::write_str(&mut out, ID_KEY).unwrap();
+            rmp::encode::write_uint(&mut out, id).unwrap();
+            rmp::encode::write_str(&mut out, "name").unwrap();
+            rmp::encode::write_str(&mut out, "x").unwrap();
+            rmp::encode::write_str(&mut out, "age").unwrap();
+            rmp::encode::write_uint(&mut out, 1).unwrap();
+            rmp::encode::write_str(&mut out, "children").unwrap();
+            rmp::encode::write_array_len(&mut out, if id == 0 { 0 } else { 2 }).unwrap();
+        }
+        // Close each definition but the leaf with its reference, innermost first.
+        for id in 0..levels {
+            rmp::encode::write_uint(&mut out, id).unwrap();
+        }
+        out
+    }
+
+    fn payloads(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|index| encode(&Person::new(format!("p{}", index), index as u32, vec![])))
+            .collect()
+    }
+
+    #[test]
+    fn decode_all_preserves_order() {
+        let payloads = payloads(100);
+        for threads in [0, 1, 3] {
+            let people = decode_all(&payloads, threads).unwrap();
+            let ages: Vec<u32> = people.iter().map(|person| person.age).collect();
+            assert_eq!(ages, (0..100).collect::<Vec<u32>>(), "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn decode_all_reports_the_earliest_error() {
+        let mut payloads = payloads(10);
+        payloads[7] = vec![0xc1];
+        payloads[3] = vec![0x93];
+        let err = decode_all(&payloads, 2).unwrap_err();
+        assert!(err.to_string().contains("Truncated"), "{}", err);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn decode_all_reuses_pools() {
+        let first = pool(2).unwrap();
+        let second = pool(2).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.current_num_threads(), 2);
+        assert!(!Arc::ptr_eq(&first, &pool(3).unwrap()));
+    }
+
+    /// Compares `decode_all` with a sequential decode; run with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_decode_all_against_sequential() {
+        let payloads: Vec<Vec<u8>> = (0..64)
+            .map(|_| encode(&crate::create_balanced_person(5, 4)))
+            .collect();
+        let time = |label: &str, run: &dyn Fn() -> Vec<Person>| {
+            let start = std::time::Instant::now();
+            for _ in 0..10 {
+                assert_eq!(run().len(), payloads.len());
+            }
+            println!("{}: {:?} per batch", label, start.elapsed() / 10);
+        };
+        time("sequential", &|| {
+            payloads
+                .iter()
+                .map(|payload| decode(payload).unwrap())
+                .collect()
+        });
+        time("decode_all, global pool", &|| {
+            decode_all(&payloads, 0).unwrap()
+        });
+        time("decode_all, 2 threads", &|| {
+            decode_all(&payloads, 2).unwrap()
+        });
+    }
+
+    #[test]
+    fn decodes_either_key_style() {
+        let person = crate::create_balanced_person(2, 3);
+        let string_keys = encode_with_keys(&person, false);
+        let compact_keys = encode_with_keys(&person, true);
+        assert_eq!(string_keys, encode(&person));
+        assert_eq!(string_keys.len() - compact_keys.len(), 15 * 13);
+        assert_eq!(decode(&string_keys).unwrap(), person);
+        assert_eq!(decode(&compact_keys).unwrap(), person);
+    }
+
+    #[test]
+    fn decodes_maps_mixing_key_styles() {
+        let mut out = Vec::new();
+        rmp::encode::write_map_len(&mut out, 3).unwrap();
+        rmp::encode::write_uint(&mut out, u64::from(AGE_KEY)).unwrap();
+        rmp::encode::write_uint(&mut out, 40).unwrap();
+        rmp::encode::write_str(&mut out, "name").unwrap();
+        rmp::encode::write_str(&mut out, "root").unwrap();
+        rmp::encode::write_uint(&mut out, u64::from(CHILDREN_KEY)).unwrap();
+        rmp::encode::write_array_len(&mut out, 1).unwrap();
+        // The child uses the other style for each field, plus an unknown key.
+        rmp::encode::write_map_len(&mut out, 4).unwrap();
+        rmp::encode::write_uint(&mut out, u64::from(NAME_KEY)).unwrap();
+        rmp::encode::write_str(&mut out, "kid").unwrap();
+        rmp::encode::write_uint(&mut out, 9).unwrap();
+        rmp::encode::write_str(&mut out, "ignored").unwrap();
+        rmp::encode::write_str(&mut out, "age").unwrap();
+        rmp::encode::write_uint(&mut out, 5).unwrap();
+        rmp::encode::write_str(&mut out, "children").unwrap();
+        rmp::encode::write_array_len(&mut out, 0).unwrap();
+
+        let kid = Person::new("kid".to_string(), 5, vec![]);
+        assert_eq!(
+            decode(&out).unwrap(),
+            Person::new("root".to_string(), 40, vec![kid])
+        );
+    }
+
+    /// Encodes `root`, then replaces the name `marker` with invalid UTF-8 of
+    /// the same length: a valid "a", a stray continuation byte, then "b".
+    fn with_invalid_name(root: &Person, marker: &str) -> Vec<u8> {
+        assert_eq!(marker.len(), 3);
+        let mut bytes = encode(root);
+        let at = bytes
+            .windows(3)
+            .position(|window| window == marker.as_bytes())
+            .unwrap();
+        bytes[at..at + 3].copy_from_slice(b"a\x80b");
+        bytes
+    }
+
+    fn family_with(marker: &str) -> Person {
+        let leaf = |name: &str| Person::new(name.to_string(), 1, vec![]);
+        Person::new(
+            "root".to_string(),
+            40,
+            vec![
+                leaf("kid"),
+                Person::new("mid".to_string(), 20, vec![leaf(marker)]),
+            ],
+        )
+    }
+
+    #[test]
+    fn invalid_utf8_names_are_rejected_with_their_path() {
+        let bytes = with_invalid_name(&family_with("BAD"), "BAD");
+        let err = decode(&bytes).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::InvalidName as u32));
+        let message = err.to_string();
+        assert!(
+            message.contains("Field `name` of children[1].children[0] is not valid UTF-8"),
+            "{}",
+            message
+        );
+
+        let root = Person::new("BAD".to_string(), 1, vec![]);
+        let err = decode(&with_invalid_name(&root, "BAD")).unwrap_err();
+        assert!(err.to_string().contains("Field `name` of root"), "{}", err);
+    }
+
+    #[test]
+    fn lossy_decoding_replaces_invalid_utf8() {
+        let bytes = with_invalid_name(&family_with("BAD"), "BAD");
+        let options = DecodeOptions {
+            lossy: true,
+            ..DecodeOptions::default()
+        };
+        let person = decode_with(&bytes, &options).unwrap();
+        assert_eq!(person.children[1].children[0].name, "a\u{fffd}b");
+        assert_eq!(person.children[0].name, "kid");
+    }
+
+    /// A root map whose `children` header claims `claimed` children, with no
+    /// children behind it.
+    fn lying_children_header(claimed: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        rmp::encode::write_map_len(&mut out, 3).unwrap();
+        rmp::encode::write_str(&mut out, "name").unwrap();
+        rmp::encode::write_str(&mut out, "root").unwrap();
+        rmp::encode::write_str(&mut out, "age").unwrap();
+        rmp::encode::write_uint(&mut out, 1).unwrap();
+        rmp::encode::write_str(&mut out, "children").unwrap();
+        out.push(0xdd);
+        out.extend_from_slice(&claimed.to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn huge_children_claims_fail_fast_under_max_nodes() {
+        let bytes = lying_children_header(4_000_000_000);
+        let options = DecodeOptions {
+            max_nodes: Some(1000),
+            ..DecodeOptions::default()
+        };
+        let start = std::time::Instant::now();
+        let err = decode_with(&bytes, &options).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::TooManyNodes as u32));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        // Without a limit the claim still fails, as truncated, without
+        // allocating for it.
+        let err = decode(&bytes).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+    }
+
+    #[test]
+    fn max_nodes_counts_claimed_and_read_nodes() {
+        let person = crate::create_balanced_person(2, 3);
+        let bytes = encode(&person);
+        let with_max = |max_nodes| DecodeOptions {
+            max_nodes: Some(max_nodes),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode_with(&bytes, &with_max(13)).unwrap(), person);
+        let err = decode_with(&bytes, &with_max(12)).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::TooManyNodes as u32));
+    }
+
+    #[test]
+    fn max_bytes_rejects_oversized_payloads() {
+        let bytes = encode(&crate::create_balanced_person(2, 3));
+        let with_max = |max_bytes| DecodeOptions {
+            max_bytes: Some(max_bytes),
+            ..DecodeOptions::default()
+        };
+        assert!(decode_with(&bytes, &with_max(bytes.len())).is_ok());
+        let err = decode_with(&bytes, &with_max(bytes.len() - 1)).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::PayloadTooLarge as u32));
+        assert!(err.to_string().contains("more than the maximum"), "{}", err);
+    }
+
+    #[test]
+    fn decodes_map_array_and_mixed_nodes() {
+        let person = crate::create_balanced_person(2, 3);
+        assert_eq!(decode(&encode(&person)).unwrap(), person);
+        assert_eq!(decode(&encode_positional(&person)).unwrap(), person);
+
+        // A positional root with a map child, which has a positional child
+        // with an extra element to skip.
+        let mut out = Vec::new();
+        rmp::encode::write_array_len(&mut out, 3).unwrap();
+        rmp::encode::write_str(&mut out, "root").unwrap();
+        rmp::encode::write_uint(&mut out, 40).unwrap();
+        rmp::encode::write_array_len(&mut out, 1).unwrap();
+        rmp::encode::write_map_len(&mut out, 3).unwrap();
+        rmp::encode::write_str(&mut out, "name").unwrap();
+        rmp::encode::write_str(&mut out, "kid").unwrap();
+        rmp::encode::write_str(&mut out, "age").unwrap();
+        rmp::encode::write_uint(&mut out, 5).unwrap();
+        rmp::encode::write_str(&mut out, "children").unwrap();
+        rmp::encode::write_array_len(&mut out, 1).unwrap();
+        rmp::encode::write_array_len(&mut out, 4).unwrap();
+        rmp::encode::write_str(&mut out, "baby").unwrap();
+        rmp::encode::write_uint(&mut out, 0).unwrap();
+        rmp::encode::write_array_len(&mut out, 0).unwrap();
+        rmp::encode::write_str(&mut out, "extra").unwrap();
+
+        let baby = Person::new("baby".to_string(), 0, vec![]);
+        let kid = Person::new("kid".to_string(), 5, vec![baby]);
+        assert_eq!(
+            decode(&out).unwrap(),
+            Person::new("root".to_string(), 40, vec![kid])
+        );
+    }
+
+    #[test]
+    fn rejects_roots_that_are_not_containers() {
+        for root in [vec![0xc0], vec![0x2a], vec![0xa3, b'a', b'b', b'c']] {
+            let err = decode(&root).unwrap_err();
+            assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+            assert!(
+                err.to_string().contains("Expected a person map or array"),
+                "{}",
+                err
+            );
+        }
+
+        let mut short = Vec::new();
+        rmp::encode::write_array_len(&mut short, 2).unwrap();
+        rmp::encode::write_str(&mut short, "x").unwrap();
+        rmp::encode::write_uint(&mut short, 1).unwrap();
+        let err = decode(&short).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("has 2 elements, expected at least 3"),
+            "{}",
+            err
+        );
+    }
+
+    /// A breadth-first payload of `(name, age, child_count)` nodes, with
+    /// `claimed` as the length of the outer array.
+    fn bfs_payload(claimed: u32, nodes: &[(&str, u32, u64)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        rmp::encode::write_array_len(&mut out, claimed).unwrap();
+        for &(name, age, count) in nodes {
+            rmp::encode::write_array_len(&mut out, 3).unwrap();
+            rmp::encode::write_str(&mut out, name).unwrap();
+            rmp::encode::write_uint(&mut out, u64::from(age)).unwrap();
+            rmp::encode::write_uint(&mut out, count).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn bfs_round_trips_wide_and_deep_trees() {
+        let leaves = (0..10_000)
+            .map(|i| Person::new(format!("leaf {}", i), i, vec![]))
+            .collect();
+        let wide = Person::new("root".to_string(), 1, leaves);
+        assert_eq!(
+            decode_bfs(&encode_bfs(&wide), &DecodeOptions::default()).unwrap(),
+            wide
+        );
+
+        let balanced = crate::create_balanced_person(4, 3);
+        assert_eq!(
+            decode_bfs(&encode_bfs(&balanced), &DecodeOptions::default()).unwrap(),
+            balanced
+        );
+
+        let deep = crate::create_chain(
+            (0..100_000).map(|i| format!("n{}", i)).collect(),
+            (0..100_000).collect(),
+        )
+        .unwrap();
+        let decoded = decode_bfs(&encode_bfs(&deep), &DecodeOptions::default()).unwrap();
+        assert!(decoded == deep);
+    }
+
+    #[test]
+    fn bfs_writes_the_documented_layout() {
+        let person = Person::new(
+            "Ann".to_string(),
+            60,
+            vec![
+                Person::new(
+                    "Bob".to_string(),
+                    35,
+                    vec![Person::new("Dan".to_string(), 8, vec![])],
+                ),
+                Person::new("Cid".to_string(), 33, vec![]),
+            ],
+        );
+        let expected = bfs_payload(
+            4,
+            &[
+                ("Ann", 60, 2),
+                ("Bob", 35, 1),
+                ("Cid", 33, 0),
+                ("Dan", 8, 0),
+            ],
+        );
+        assert_eq!(encode_bfs(&person), expected);
+        assert_eq!(
+            decode_bfs(&expected, &DecodeOptions::default()).unwrap(),
+            person
+        );
+    }
+
+    #[test]
+    fn bfs_rejects_bad_child_counts() {
+        let cases = [
+            // More children than nodes remain.
+            (
+                bfs_payload(3, &[("a", 1, 1), ("b", 1, 2), ("c", 1, 0)]),
+                "Node 1 claims 2 children, but only 1 unclaimed nodes remain",
+            ),
+            (
+                bfs_payload(2, &[("a", 1, 5), ("b", 1, 0)]),
+                "Node 0 claims 5 children, but only 1 unclaimed nodes remain",
+            ),
+            // Fewer children than nodes, leaving one unclaimed.
+            (
+                bfs_payload(3, &[("a", 1, 1), ("b", 1, 0), ("c", 1, 0)]),
+                "Node 2 is not a child of any earlier node",
+            ),
+            // An outer array claiming nodes that are not there.
+            (
+                bfs_payload(4, &[("a", 1, 3), ("b", 1, 0)]),
+                "claims 4 nodes, but has room for at most 2",
+            ),
+            // A huge outer array and child count, which must fail without
+            // allocating for either.
+            (
+                vec![
+                    0xdd, 0xff, 0xff, 0xff, 0xff, 0x93, 0xa1, 0x61, 0x00, 0xce, 0xff, 0xff, 0xff,
+                    0xfe,
+                ],
+                "claims 4294967295 nodes, but has room for at most 2",
+            ),
+            // Room for the nodes, but a final node cut short.
+            (
+                {
+                    let mut bytes = bfs_payload(2, &[("a", 1, 1), ("bbbbbbbb", 1, 0)]);
+                    bytes.pop();
+                    bytes
+                },
+                "Truncated msgpack value",
+            ),
+            (bfs_payload(0, &[]), "Breadth-first payload has no nodes"),
+        ];
+        for (bytes, message) in cases {
+            let err = decode_bfs(&bytes, &DecodeOptions::default()).unwrap_err();
+            assert_eq!(
+                code_of(&err),
+                Some(ErrorCode::Malformed as u32),
+                "{}",
+                message
+            );
+            assert!(err.to_string().contains(message), "{}: {}", message, err);
+        }
+    }
+
+    #[test]
+    fn bfs_applies_max_nodes_and_max_bytes() {
+        let person = crate::create_balanced_person(2, 3);
+        let bytes = encode_bfs(&person);
+        let limits = |max_nodes, max_bytes| DecodeOptions {
+            max_nodes,
+            max_bytes,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            decode_bfs(&bytes, &limits(Some(13), Some(bytes.len()))).unwrap(),
+            person
+        );
+        let err = decode_bfs(&bytes, &limits(Some(12), None)).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::TooManyNodes as u32));
+        let err = decode_bfs(&bytes, &limits(None, Some(bytes.len() - 1))).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::PayloadTooLarge as u32));
+    }
+
+    #[test]
+    fn bfs_names_the_path_of_invalid_names() {
+        let mut bytes = bfs_payload(
+            5,
+            &[
+                ("a", 1, 2),
+                ("b", 1, 0),
+                ("c", 1, 2),
+                ("d", 1, 0),
+                ("BAD", 1, 0),
+            ],
+        );
+        let at = bytes
+            .windows(3)
+            .position(|window| window == b"BAD")
+            .unwrap();
+        bytes[at..at + 3].copy_from_slice(b"a\x80b");
+        let err = decode_bfs(&bytes, &DecodeOptions::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Field `name` of children[1].children[1]"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn deduped_references_count_toward_max_nodes() {
+        let payload = doubling_payload(10);
+        let person = decode_deduped(&payload, Some(2047), None).unwrap();
+        assert_eq!(person.shape().descendants + 1, 2047);
+
+        let err = decode_deduped(&payload, Some(2046), None).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::TooManyNodes as u32));
+    }
+
+    #[test]
+    fn deduped_default_cap_stops_expansion() {
+        let payload = doubling_payload(40);
+        assert!(payload.len() < 2000);
+        let err = decode_deduped(&payload, None, None).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::TooManyNodes as u32));
+    }
+
+    #[test]
+    fn deduped_max_bytes_rejects_long_payloads() {
+        let payload = encode_deduped(&Person::new("x".to_string(), 1, vec![]));
+        assert!(decode_deduped(&payload, None, Some(payload.len())).is_ok());
+        let err = decode_deduped(&payload, None, Some(payload.len() - 1)).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::PayloadTooLarge as u32));
+    }
+}
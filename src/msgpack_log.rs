@@ -0,0 +1,208 @@
+//! An append-only log of msgpack-encoded `Person` records.
+//!
+//! Each record is a 4-byte big-endian length followed by that many bytes of
+//! msgpack, as produced by [`crate::msgpack::encode`]. Records are appended with
+//! a single write, so a crash can at worst leave one partial record at the end
+//! of the file. Readers ignore such a trailing record instead of failing, and
+//! the next append truncates it before writing, so that it cannot swallow the
+//! records after it.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+use crate::msgpack;
+
+/// Size of the length prefix in front of every record.
+const LENGTH_PREFIX: usize = 4;
+
+/// Appends one length-prefixed msgpack record to the log at `path`, creating
+/// the file if needed.
+///
+/// A partial final record left by an interrupted write, one that `read` would
+/// drop, is truncated first, so the new record directly follows the last
+/// complete one.
+pub fn append(person: &Person, path: &str) -> PyResult<()> {
+    let payload = msgpack::encode(person);
+    let len = u32::try_from(payload.len())
+        .map_err(|_| PyValueError::new_err("Person is too large for a log record"))?;
+
+    let mut record = Vec::with_capacity(LENGTH_PREFIX + payload.len());
+    record.extend_from_slice(&len.to_be_bytes());
+    record.extend_from_slice(&payload);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let end = complete_len(&mut file)?;
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(end))?;
+    file.write_all(&record)?;
+    Ok(())
+}
+
+/// Returns the length of the log up to the end of its last complete record,
+/// treating the final record as partial exactly when `read` would drop it.
+///
+/// Only the length prefixes are read, skipping over the payloads, except for
+/// the final record, which is decoded to check it.
+fn complete_len(file: &mut File) -> PyResult<u64> {
+    let len = file.metadata()?.len();
+    let mut pos = 0;
+
+    while len - pos >= LENGTH_PREFIX as u64 {
+        let mut prefix = [0; LENGTH_PREFIX];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut prefix)?;
+        let end = pos + LENGTH_PREFIX as u64 + u64::from(u32::from_be_bytes(prefix));
+        if end > len {
+            break;
+        }
+        if end == len {
+            let mut payload = Vec::new();
+            file.read_to_end(&mut payload)?;
+            if msgpack::decode(&payload).is_ok() {
+                pos = end;
+            }
+            break;
+        }
+        pos = end;
+    }
+
+    Ok(pos)
+}
+
+/// Reads every record from the log at `path`, in the order they were appended.
+///
+/// A truncated or undecodable final record is treated as an interrupted write
+/// and silently dropped. A record that fails to decode anywhere else means the
+/// log is corrupt, and raises a `PersonError` naming its offset.
+pub fn read(path: &str) -> PyResult<Vec<Person>> {
+    let bytes = std::fs::read(path)?;
+    let mut people = Vec::new();
+    let mut pos = 0;
+
+    while bytes.len() - pos >= LENGTH_PREFIX {
+        let prefix: [u8; LENGTH_PREFIX] = bytes[pos..pos + LENGTH_PREFIX].try_into().unwrap();
+        let start = pos + LENGTH_PREFIX;
+        let end = start + u32::from_be_bytes(prefix) as usize;
+        if end > bytes.len() {
+            break;
+        }
+
+        match msgpack::decode(&bytes[start..end]) {
+            Ok(person) => people.push(person),
+            Err(_) if end == bytes.len() => break,
+            Err(e) => {
+                return Err(ErrorCode::Malformed
+                    .err(format!("Corrupt record at byte {} of {}: {}", pos, path, e)));
+            }
+        }
+        pos = end;
+    }
+
+    Ok(people)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::content_hash;
+
+    /// A log path in the temporary directory, removed when dropped.
+    struct TempLog(String);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "msgpack_log_{}_{}.bin",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            TempLog(path.to_str().unwrap().to_string())
+        }
+
+        fn push_raw(&self, bytes: &[u8]) {
+            let mut file = OpenOptions::new().append(true).open(&self.0).unwrap();
+            file.write_all(bytes).unwrap();
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn person(age: u32) -> Person {
+        Person::new(format!("p{}", age), age, vec![])
+    }
+
+    fn ages(path: &str) -> Vec<u32> {
+        read(path)
+            .unwrap()
+            .iter()
+            .map(|person| person.age)
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_records() {
+        let log = TempLog::new("round_trip");
+        let tree = Person::new("root".to_string(), 40, vec![person(1), person(2)]);
+        append(&tree, &log.0).unwrap();
+        append(&person(3), &log.0).unwrap();
+        let people = read(&log.0).unwrap();
+        assert_eq!(people.len(), 2);
+        assert_eq!(content_hash(&people[0]), content_hash(&tree));
+        assert_eq!(people[1].age, 3);
+    }
+
+    #[test]
+    fn append_truncates_a_partial_record() {
+        let partial_tails: [&[u8]; 3] = [
+            // A length prefix claiming more bytes than follow.
+            &[0, 0, 0, 100, 0x83, 0xa4],
+            // Part of a length prefix.
+            &[0, 0],
+            // A complete record that does not decode.
+            &[0, 0, 0, 2, 0x83, 0xa4],
+        ];
+        for (index, tail) in partial_tails.iter().enumerate() {
+            let log = TempLog::new(&format!("partial_{}", index));
+            append(&person(1), &log.0).unwrap();
+            log.push_raw(tail);
+            assert_eq!(ages(&log.0), [1]);
+
+            append(&person(2), &log.0).unwrap();
+            append(&person(3), &log.0).unwrap();
+            assert_eq!(ages(&log.0), [1, 2, 3], "tail {}", index);
+        }
+    }
+
+    #[test]
+    fn corrupt_records_before_the_end_still_fail() {
+        let log = TempLog::new("corrupt");
+        append(&person(1), &log.0).unwrap();
+        log.push_raw(&[0, 0, 0, 1, 0xc1]);
+        append(&person(2), &log.0).unwrap();
+        // The corrupt record was final when appending, so it was truncated.
+        assert_eq!(ages(&log.0), [1, 2]);
+
+        log.push_raw(&[0, 0, 0, 1, 0xc1]);
+        log.push_raw(&[0, 0, 0, 1, 0xc0]);
+        assert!(read(&log.0).is_err());
+    }
+}
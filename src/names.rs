@@ -0,0 +1,137 @@
+//! Validation and normalization of names entering a `Person` from Python.
+
+use std::borrow::Cow;
+use std::sync::RwLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyString;
+use pyo3::types::PyStringMethods;
+use regex::Regex;
+use unicode_normalization::IsNormalized;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::is_nfc_quick;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+
+/// Whether incoming names are normalized to NFC. Off by default.
+static NORMALIZE_NAMES: AtomicBool = AtomicBool::new(false);
+
+/// The name pattern `from_dict` enforces when none is passed, compiled once
+/// when it is set. Unset by default.
+static DEFAULT_NAME_PATTERN: RwLock<Option<Regex>> = RwLock::new(None);
+
+/// Turns NFC normalization of incoming names on or off, process-wide.
+pub fn set_normalization(enabled: bool) {
+    NORMALIZE_NAMES.store(enabled, Ordering::Relaxed);
+}
+
+/// Converts a Python name to a Rust `String`.
+///
+/// Strings containing lone surrogates cannot be represented as UTF-8 and are
+/// rejected with a `PersonError`. When normalization is enabled the name is
+/// also converted to Unicode Normalization Form C, so that e.g. `"e\u{301}"`
+/// (`e` plus a combining acute accent) and `"\u{e9}"` become the same name.
+pub fn ingest_name(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    ingest_name_with(value, NORMALIZE_NAMES.load(Ordering::Relaxed))
+}
+
+/// Converts a Python name like `ingest_name`, normalizing it if `normalize`
+/// is set rather than as configured.
+fn ingest_name_with(value: &Bound<'_, PyAny>, normalize: bool) -> PyResult<String> {
+    let name = value.downcast::<PyString>()?;
+    let name = name.to_str().map_err(|_| {
+        ErrorCode::InvalidName.err(format!(
+            "Name {:?} contains a lone surrogate and is not valid Unicode",
+            value
+        ))
+    })?;
+
+    if !normalize || is_nfc_quick(name.chars()) == IsNormalized::Yes {
+        return Ok(name.to_owned());
+    }
+    Ok(name.nfc().collect())
+}
+
+/// Compiles a name pattern, raising a `PyValueError` if it is not a valid
+/// regular expression.
+pub fn compile_pattern(pattern: &str) -> PyResult<Regex> {
+    Regex::new(pattern).map_err(|err| {
+        PyValueError::new_err(format!("Invalid name pattern {:?}: {}", pattern, err))
+    })
+}
+
+/// Sets or, with `None`, clears the process-wide default name pattern.
+pub fn set_default_pattern(pattern: Option<&str>) -> PyResult<()> {
+    let compiled = pattern.map(compile_pattern).transpose()?;
+    *DEFAULT_NAME_PATTERN.write().unwrap() = compiled;
+    Ok(())
+}
+
+/// Returns the process-wide default name pattern, if one is set.
+///
+/// `Regex` shares its compiled program between clones, so this is cheap.
+pub fn default_pattern() -> Option<Regex> {
+    DEFAULT_NAME_PATTERN.read().unwrap().clone()
+}
+
+/// Applies `pattern.replace_all(name, replacement)` to every name in the tree,
+/// returning the number of names that changed.
+///
+/// `replacement` may refer to capture groups as `$1` or `${name}`; write `$$`
+/// for a literal `$`. A name counts as changed only if the substitution gives a
+/// different string, so matches replaced by the text they matched are not
+/// counted.
+pub fn replace_all(root: &mut Person, pattern: &Regex, replacement: &str) -> usize {
+    let mut changed = 0;
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if let Cow::Owned(name) = pattern.replace_all(&node.name, replacement)
+            && name != node.name
+        {
+            node.name = name;
+            changed += 1;
+        }
+        stack.extend(node.children.iter_mut());
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::Python;
+
+    use super::*;
+    use crate::errors::code_of;
+
+    #[test]
+    fn normalization_composes_names_only_when_enabled() {
+        Python::with_gil(|py| {
+            let decomposed = PyString::new(py, "e\u{301}");
+            assert_eq!(ingest_name_with(&decomposed, true).unwrap(), "\u{e9}");
+            assert_eq!(ingest_name_with(&decomposed, false).unwrap(), "e\u{301}");
+
+            let composed = PyString::new(py, "\u{e9}");
+            assert_eq!(ingest_name_with(&composed, true).unwrap(), "\u{e9}");
+            assert_eq!(ingest_name_with(&composed, false).unwrap(), "\u{e9}");
+        });
+    }
+
+    #[test]
+    fn lone_surrogates_are_rejected() {
+        Python::with_gil(|py| {
+            let name = py.eval(c"'a\\ud800b'", None, None).unwrap();
+            for normalize in [false, true] {
+                let err = ingest_name_with(&name, normalize).unwrap_err();
+                assert_eq!(code_of(&err), Some(ErrorCode::InvalidName as u32));
+                assert!(err.to_string().contains("lone surrogate"), "{}", err);
+            }
+        });
+    }
+}
@@ -0,0 +1,259 @@
+//! Binary patches that turn one `Person` tree into another.
+//!
+//! A patch lets a tree be updated over the wire by sending only what changed.
+//! It is built from the compact format's primitives (see [`crate::compact`]).
+//!
+//! # Byte layout
+//!
+//! A patch is a format version byte, currently `0x01`, followed by operations
+//! until the end of the payload. Each operation is
+//!
+//! | Field | Encoding                                      |
+//! |-------|-----------------------------------------------|
+//! | tag   | one byte, see below                           |
+//! | path  | varint index count, then one varint per index |
+//! | value | depends on the tag                            |
+//!
+//! where varints are unsigned LEB128 and the path is the list of child indices
+//! from the root to the node the operation applies to:
+//!
+//! | Tag    | Operation   | Value                                              |
+//! |--------|-------------|----------------------------------------------------|
+//! | `0x00` | set name    | varint byte length, then that many bytes of UTF-8  |
+//! | `0x01` | set age     | varint age, at most `u32::MAX`                     |
+//! | `0x02` | truncate    | varint number of children to keep                  |
+//! | `0x03` | append      | varint byte length, then a compact-encoded subtree |
+//!
+//! Operations apply in order. Patches from `make_patch` match children by
+//! index: shared positions are patched in place, surplus children of the old
+//! tree are truncated and surplus children of the new tree are appended. An
+//! unchanged tree gives a patch of just the version byte.
+
+use pyo3::PyResult;
+
+use crate::Person;
+use crate::compact;
+use crate::compact::Reader;
+use crate::compact::write_varint;
+use crate::errors::ErrorCode;
+use crate::paths::node_at_mut;
+
+const FORMAT_VERSION: u8 = 0x01;
+
+const SET_NAME: u8 = 0x00;
+const SET_AGE: u8 = 0x01;
+const TRUNCATE: u8 = 0x02;
+const APPEND: u8 = 0x03;
+
+fn write_op(out: &mut Vec<u8>, tag: u8, path: &[usize]) {
+    out.push(tag);
+    write_varint(out, path.len() as u64);
+    for &index in path {
+        write_varint(out, index as u64);
+    }
+}
+
+/// Builds a patch that turns `old` into `new`.
+pub fn make_patch(old: &Person, new: &Person) -> Vec<u8> {
+    let mut out = vec![FORMAT_VERSION];
+    let mut stack = vec![(old, new, Vec::new())];
+
+    while let Some((a, b, path)) = stack.pop() {
+        if a.name != b.name {
+            write_op(&mut out, SET_NAME, &path);
+            write_varint(&mut out, b.name.len() as u64);
+            out.extend_from_slice(b.name.as_bytes());
+        }
+        if a.age != b.age {
+            write_op(&mut out, SET_AGE, &path);
+            write_varint(&mut out, u64::from(b.age));
+        }
+
+        let shared = a.children.len().min(b.children.len());
+        if a.children.len() > shared {
+            write_op(&mut out, TRUNCATE, &path);
+            write_varint(&mut out, shared as u64);
+        }
+        for child in &b.children[shared..] {
+            let encoded = compact::encode(child);
+            write_op(&mut out, APPEND, &path);
+            write_varint(&mut out, encoded.len() as u64);
+            out.extend_from_slice(&encoded);
+        }
+
+        // Push in reverse so shared children are patched in order.
+        for index in (0..shared).rev() {
+            let mut child_path = path.clone();
+            child_path.push(index);
+            stack.push((&a.children[index], &b.children[index], child_path));
+        }
+    }
+
+    out
+}
+
+/// Applies a patch produced by `make_patch` to `root`.
+///
+/// The patch is applied to a copy that replaces `root` only once every
+/// operation has succeeded, so a malformed patch or one whose paths do not
+/// fit the tree leaves `root` unchanged. Only the root's name, age and
+/// children are replaced; its state, including any change observer, is kept.
+/// Bad paths raise `IndexError`; any
+/// other problem raises `PersonError`.
+pub fn apply_patch(root: &mut Person, patch: &[u8]) -> PyResult<()> {
+    let mut reader = Reader::new(patch);
+    match reader.read_bytes(1) {
+        Ok(&[FORMAT_VERSION]) => {}
+        Ok(version) => {
+            return Err(ErrorCode::Malformed
+                .err(format!("Unsupported patch format version {}", version[0])));
+        }
+        Err(_) => return Err(ErrorCode::Malformed.err("Empty patch")),
+    }
+
+    let mut patched = root.clone();
+    while !reader.is_at_end() {
+        let start = reader.pos();
+        let tag = reader.read_bytes(1)?[0];
+        let len = reader.read_varint()?;
+        let path = (0..len)
+            .map(|_| {
+                let index = reader.read_varint()?;
+                usize::try_from(index).map_err(|_| {
+                    ErrorCode::Malformed.err(format!("Path index {} is out of range", index))
+                })
+            })
+            .collect::<PyResult<Vec<usize>>>()?;
+        let node = node_at_mut(&mut patched, &path)?;
+
+        match tag {
+            SET_NAME => {
+                let len = reader.read_varint()?;
+                node.name = reader.read_str(len)?;
+            }
+            SET_AGE => {
+                let age = reader.read_varint()?;
+                node.age = u32::try_from(age).map_err(|_| {
+                    ErrorCode::InvalidAge.err(format!("Age {} out of range in patch", age))
+                })?;
+            }
+            TRUNCATE => {
+                let keep = reader.read_varint()?;
+                if keep > node.children.len() as u64 {
+                    return Err(ErrorCode::Malformed.err(format!(
+                        "Cannot truncate {} children to {}",
+                        node.children.len(),
+                        keep
+                    )));
+                }
+                node.children.truncate(keep as usize);
+            }
+            APPEND => {
+                let len = reader.read_varint()?;
+                node.children
+                    .push(compact::decode(reader.read_bytes(len)?)?);
+            }
+            _ => {
+                return Err(ErrorCode::Malformed.err(format!(
+                    "Unknown patch operation {:#04x} at byte {}",
+                    tag, start
+                )));
+            }
+        }
+    }
+
+    // Keep the root's own state, which holds its change observer.
+    std::mem::swap(&mut root.name, &mut patched.name);
+    std::mem::swap(&mut root.age, &mut patched.age);
+    std::mem::swap(&mut root.children, &mut patched.children);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::Python;
+    use pyo3::exceptions::PyIndexError;
+
+    use super::*;
+    use crate::errors::code_of;
+
+    fn person(name: &str, age: u32, children: Vec<Person>) -> Person {
+        Person::new(name.to_string(), age, children)
+    }
+
+    fn family() -> Person {
+        person(
+            "Ann",
+            60,
+            vec![
+                person("Bob", 35, vec![person("Dan", 8, vec![])]),
+                person("Cid", 33, vec![]),
+            ],
+        )
+    }
+
+    fn assert_round_trip(old: &Person, new: &Person) {
+        let mut patched = old.clone();
+        apply_patch(&mut patched, &make_patch(old, new)).unwrap();
+        assert_eq!(&patched, new);
+    }
+
+    #[test]
+    fn patches_turn_old_into_new() {
+        let old = family();
+
+        let mut renamed = old.clone();
+        renamed.children[0].children[0].name = "Daniel".to_string();
+        assert_round_trip(&old, &renamed);
+
+        let mut aged = old.clone();
+        aged.age = 61;
+        aged.children[1].age = 34;
+        assert_round_trip(&old, &aged);
+
+        let mut truncated = old.clone();
+        truncated.children.truncate(1);
+        truncated.children[0].children.clear();
+        assert_round_trip(&old, &truncated);
+
+        let mut appended = old.clone();
+        appended.children[1].children.push(family());
+        appended.children.push(person("Eve", 30, vec![]));
+        assert_round_trip(&old, &appended);
+
+        assert_eq!(make_patch(&old, &old), [FORMAT_VERSION]);
+        assert_round_trip(&old, &old);
+    }
+
+    #[test]
+    fn bad_paths_leave_the_root_unchanged() {
+        let old = family();
+        let mut new = old.clone();
+        new.age = 61;
+        new.children[0].children[0].name = "Daniel".to_string();
+        let mut patch = make_patch(&old, &new);
+        // An age change at a child that does not exist, after valid operations.
+        write_op(&mut patch, SET_AGE, &[5]);
+        write_varint(&mut patch, 1);
+
+        let mut root = old.clone();
+        let err = apply_patch(&mut root, &patch).unwrap_err();
+        assert!(Python::with_gil(|py| err.is_instance_of::<PyIndexError>(py)));
+        assert_eq!(root, old);
+    }
+
+    #[test]
+    fn malformed_patches_leave_the_root_unchanged() {
+        let old = family();
+        let mut patch = make_patch(&old, &person("Zed", 1, vec![]));
+        patch.push(0x7f);
+
+        let mut root = old.clone();
+        let err = apply_patch(&mut root, &patch).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+        assert_eq!(root, old);
+
+        let err = apply_patch(&mut root, &[]).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+    }
+}
@@ -0,0 +1,95 @@
+//! Addressing nodes of a `Person` tree by path.
+//!
+//! A path is the list of child indices leading from a root to a node, so `[]`
+//! is the root itself and `[1, 0]` is the first child of the root's second
+//! child. Paths that leave the tree raise `IndexError`.
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyIndexError;
+
+use crate::Person;
+
+/// The `IndexError` for `path` leaving the tree at `depth`, where the node
+/// reached has `len` children.
+pub fn out_of_range(path: &[usize], depth: usize, len: usize) -> pyo3::PyErr {
+    PyIndexError::new_err(format!(
+        "Path {:?} is out of range: the node at {:?} has {} children",
+        path,
+        &path[..depth],
+        len
+    ))
+}
+
+/// Returns the node at `path` below `root`.
+pub fn node_at<'a>(root: &'a Person, path: &[usize]) -> PyResult<&'a Person> {
+    let mut node = root;
+    for (depth, &index) in path.iter().enumerate() {
+        node = node
+            .children
+            .get(index)
+            .ok_or_else(|| out_of_range(path, depth, node.children.len()))?;
+    }
+    Ok(node)
+}
+
+/// Returns the nodes on the way from `root` to the node at `path`, root first
+/// and excluding that node itself. The whole path must be in range.
+pub fn ancestors<'a>(root: &'a Person, path: &[usize]) -> PyResult<Vec<&'a Person>> {
+    let mut chain = Vec::with_capacity(path.len());
+    let mut node = root;
+    for (depth, &index) in path.iter().enumerate() {
+        chain.push(node);
+        node = node
+            .children
+            .get(index)
+            .ok_or_else(|| out_of_range(path, depth, node.children.len()))?;
+    }
+    Ok(chain)
+}
+
+/// Returns the path and node of the deepest common ancestor of the nodes at
+/// `path_a` and `path_b`, which is their longest common prefix. A node counts
+/// as its own ancestor, so if one path leads to an ancestor of the other, that
+/// node is the result. Both paths must be in range.
+pub fn lowest_common_ancestor<'a>(
+    root: &'a Person,
+    path_a: &[usize],
+    path_b: &[usize],
+) -> PyResult<(Vec<usize>, &'a Person)> {
+    node_at(root, path_a)?;
+    node_at(root, path_b)?;
+
+    let common: Vec<usize> = path_a
+        .iter()
+        .zip(path_b)
+        .take_while(|(a, b)| a == b)
+        .map(|(&index, _)| index)
+        .collect();
+    let node = node_at(root, &common)?;
+    Ok((common, node))
+}
+
+/// Returns the node at `path` below `root`, mutably.
+pub fn node_at_mut<'a>(root: &'a mut Person, path: &[usize]) -> PyResult<&'a mut Person> {
+    let mut node = root;
+    for (depth, &index) in path.iter().enumerate() {
+        let len = node.children.len();
+        node = node
+            .children
+            .get_mut(index)
+            .ok_or_else(|| out_of_range(path, depth, len))?;
+    }
+    Ok(node)
+}
+
+/// Formats a path for messages, e.g. `children[1].children[0]`, or `root` for
+/// the empty path.
+pub fn describe(path: &[usize]) -> String {
+    if path.is_empty() {
+        return "root".to_string();
+    }
+    path.iter()
+        .map(|index| format!("children[{}]", index))
+        .collect::<Vec<_>>()
+        .join(".")
+}
@@ -0,0 +1,130 @@
+//! Seeded generation of random `Person` trees for reproducible fixtures.
+//!
+//! A `PersonRng` owns one random number generator, and every call advances it,
+//! so a sequence of calls on generators created with the same seed always
+//! yields the same trees. The exact sequence for a seed is only stable for a
+//! given version of this crate and of `rand`.
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+use pyo3::pyclass;
+use pyo3::pymethods;
+use rand::Rng;
+use rand::SeedableRng;
+use rand::distr::Alphanumeric;
+use rand::distr::SampleString;
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+
+use crate::Person;
+
+/// A seeded source of random people.
+///
+/// This is not thread-safe: calls from several threads at once fail with
+/// `RuntimeError` ("Already borrowed"), and even serialized calls from several
+/// threads interleave nondeterministically. Share one between threads only
+/// behind a lock, or give each thread its own generator.
+#[pyclass]
+pub struct PersonRng {
+    rng: StdRng,
+}
+
+impl PersonRng {
+    /// Draws a childless person with a name of 3 to 10 alphanumeric characters
+    /// and an age from 1 to 99.
+    fn draw_person(&mut self) -> Person {
+        let name_length = self.rng.random_range(3..=10);
+        let name = Alphanumeric.sample_string(&mut self.rng, name_length);
+        let age = self.rng.random_range(1..=99);
+        Person::new(name, age, vec![])
+    }
+
+    /// Draws the number of children of a node at `level`.
+    fn child_count(&mut self, level: usize, depth: usize, max_children: usize) -> usize {
+        if level < depth {
+            self.rng.random_range(1..=max_children)
+        } else {
+            0
+        }
+    }
+}
+
+/// Returns a childless person named by a random entry of `names`, with an age
+/// drawn uniformly from `min_age..=max_age`, determined by `seed`.
+///
+/// An empty `names` or `min_age` above `max_age` raises `PyValueError`.
+pub fn random_person_from(
+    names: &[String],
+    min_age: u32,
+    max_age: u32,
+    seed: u64,
+) -> PyResult<Person> {
+    if min_age > max_age {
+        return Err(PyValueError::new_err(format!(
+            "min_age {} is greater than max_age {}",
+            min_age, max_age
+        )));
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let name = names
+        .choose(&mut rng)
+        .ok_or_else(|| PyValueError::new_err("The name list is empty"))?;
+    let age = rng.random_range(min_age..=max_age);
+    Ok(Person::new(name.clone(), age, vec![]))
+}
+
+#[pymethods]
+impl PersonRng {
+    /// Creates a generator whose sequence is determined by `seed`.
+    #[new]
+    fn new(seed: u64) -> Self {
+        PersonRng {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Returns a childless person with a random name and age.
+    fn random_person(&mut self) -> Person {
+        self.draw_person()
+    }
+
+    /// Returns a random tree in which every node above `depth` has between 1
+    /// and `max_children` children, like `create_nested_person` but with random
+    /// names and ages.
+    ///
+    /// # Arguments
+    /// * `depth` - Number of levels below the root
+    /// * `max_children` - Maximum number of children of each non-leaf node,
+    ///   which must be positive unless `depth` is 0
+    fn nested_person(&mut self, depth: usize, max_children: usize) -> PyResult<Person> {
+        if depth > 0 && max_children == 0 {
+            return Err(PyValueError::new_err(
+                "max_children must be positive when depth is positive",
+            ));
+        }
+
+        // Nodes still receiving children, with their level and the number of
+        // children still to generate. Nodes are drawn in pre-order.
+        let root = self.draw_person();
+        let count = self.child_count(0, depth, max_children);
+        let mut stack = vec![(root, 0, count)];
+
+        loop {
+            let (_, level, remaining) = stack.last_mut().unwrap();
+            if *remaining > 0 {
+                *remaining -= 1;
+                let level = *level + 1;
+                let child = self.draw_person();
+                let count = self.child_count(level, depth, max_children);
+                stack.push((child, level, count));
+                continue;
+            }
+
+            let (node, _, _) = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some((parent, _, _)) => parent.children.push(node),
+                None => return Ok(node),
+            }
+        }
+    }
+}
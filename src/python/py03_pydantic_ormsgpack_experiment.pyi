@@ -1,18 +1,266 @@
-from typing import Dict, List, Any
+from typing import Any, Callable, Dict, Iterable, Iterator, List, Literal, Mapping, Optional, Sequence, Tuple, Union
+
+__version__: str
 
 class Person:
     name: str
     age: int
     children: List["Person"]
+    __match_args__: Tuple[str, str, str]
 
+    def __init__(
+        self, *, name: str, age: int, children: Optional[List["Person"]] = None
+    ) -> None: ...
+    @property
+    def version(self) -> int: ...
     def __dict__(self) -> Dict[str, Any]: ...
+    def to_dict_cached(self) -> Dict[str, Any]: ...
+    def to_grouped_dict(self) -> Dict[str, Any]: ...
+    def to_dict(
+        self,
+        *,
+        age_format: str = "int",
+        with_ids: bool = False,
+        with_index: bool = False,
+        frozen: bool = False,
+        with_type: bool = False,
+        fields: Optional[List[str]] = None,
+        exclude: Optional[List[str]] = None,
+        key_case: Literal["snake", "camel", "kebab"] = "snake",
+    ) -> Mapping[str, Any]:
+        """Converts this Person and its descendants to nested dictionaries.
+
+        `key_case` changes exactly two keys, which appear only with
+        `with_ids` or `with_index`:
+
+        | key_case  | parent_id   | sibling_index   |
+        |-----------|-------------|-----------------|
+        | "snake"   | `parent_id` | `sibling_index` |
+        | "camel"   | `parentId`  | `siblingIndex`  |
+        | "kebab"   | `parent-id` | `sibling-index` |
+
+        `name`, `age`, `children`, `id` and `__type__` are the same in every
+        case.
+        """
+        ...
+    def __eq__(self, other: object) -> bool: ...
+    def __hash__(self) -> int: ...
+    def content_hash(self) -> str: ...
+    def content_hash_parallel(self) -> str: ...
+    def subtree_signatures(self) -> Dict[str, int]: ...
     def __repr__(self) -> str: ...
+    def debug_repr(self) -> str: ...
+    def view(self) -> "PersonRef": ...
+    def names(self) -> "NameIterator": ...
+    def iter_dicts(self) -> "DictIterator": ...
+    def iter_paths(self) -> "PathIterator": ...
+    def flatten_weighted(self) -> List[Tuple["Person", int]]: ...
     def add_child(self, child: "Person") -> None: ...
+    def set_on_change(self, cb: Optional[Callable[[Dict[str, Any]], Any]]) -> None: ...
+    def ancestors(self, path: List[int]) -> List["Person"]: ...
+    def lca(self, path_a: List[int], path_b: List[int]) -> Tuple[List[int], "Person"]: ...
+    def move_child(self, from_path: List[int], to_path: List[int]) -> None: ...
+    def swap_children(self, i: int, j: int) -> None: ...
+    def sort_children_by(self, key: Callable[["Person"], Any]) -> None: ...
+    def shuffle_children(self, seed: int) -> None: ...
+    def anonymize(self, seed: int, age_bucket: Optional[int] = None) -> None: ...
+    def bucketize_ages(self, bucket_size: int) -> None: ...
+    def regex_replace_names(self, pattern: str, replacement: str) -> int: ...
+    def rebalance(self) -> None: ...
     @staticmethod
-    def from_dict(dict_data: Dict[str, Any]) -> "Person": ...
+    def from_dict(
+        dict_data: Mapping[str, Any],
+        *,
+        camel_case: bool = False,
+        max_children_per_node: Optional[int] = None,
+        max_nodes: Optional[int] = None,
+        trim_names: bool = False,
+        age_modulo: Optional[int] = None,
+        reject_sentinels: Optional[List[int]] = None,
+        name_pattern: Optional[str] = None,
+        globally_unique_names: bool = False,
+    ) -> "Person": ...
     @staticmethod
     def validate(value: Any) -> "Person": ...
+    @staticmethod
+    def validate_with_warnings(
+        value: Any, *, duplicate_names: bool = False
+    ) -> Tuple["Person", List[str]]: ...
+    @staticmethod
+    def validate_against_schema(value: Any, schema: Dict[str, Any] | str) -> "Person": ...
+    def to_graphml(self) -> str: ...
+    @staticmethod
+    def from_edge_list(edges: List[Tuple[str, str]], ages: Dict[str, int]) -> "Person": ...
+    def to_compact_bytes(self) -> bytes: ...
+    @staticmethod
+    def from_compact_bytes(data: bytes) -> "Person": ...
+    def render_tree(self) -> str: ...
+    def first_mismatch(self, other: "Person") -> Optional[str]: ...
+    def equals_structure(self, other: "Person") -> bool: ...
+    def edit_distance(self, other: "Person") -> int: ...
+    def make_patch(self, other: "Person") -> bytes: ...
+    def apply_patch(self, patch: bytes) -> None: ...
+    def filter_tree(self, predicate: Callable[["Person"], Any]) -> Optional["Person"]: ...
+    def find_all(self, predicate: Callable[["Person"], Any]) -> List[Tuple[List[int], "Person"]]: ...
+    def bfs(
+        self, visitor: Callable[["Person", int], Any], max_depth: Optional[int] = None
+    ) -> None: ...
+    def closest_to_age(self, target: int) -> "Person": ...
+    def stats(self) -> Dict[str, Any]: ...
+    def depth(self) -> int: ...
+    def count_descendants(self) -> int: ...
+    def count_leaves(self) -> int: ...
+    def count_internal(self) -> int: ...
+    def leaf_internal_counts(self) -> Tuple[int, int]: ...
+    def to_json(self, indent: Optional[int] = None) -> str: ...
+    @staticmethod
+    def from_json(text: str) -> "Person": ...
+    def to_sexpr(self) -> str: ...
+    @staticmethod
+    def from_sexpr(text: str) -> "Person": ...
+    def to_toml(self) -> str: ...
+    @staticmethod
+    def from_toml(text: str) -> "Person": ...
+    def to_msgpack(self, *, compact_keys: bool = False) -> bytes: ...
+    def to_msgpack_gz(self, level: int = 6) -> bytes: ...
+    @staticmethod
+    def from_msgpack_gz(data: bytes, *, max_bytes: int = 268435456) -> "Person": ...
+    def to_msgpack_deduped(self) -> bytes: ...
+    def to_msgpack_tuples(self) -> bytes: ...
+    def to_msgpack_bfs(self) -> bytes: ...
+    @staticmethod
+    def from_msgpack_bfs(
+        data: bytes,
+        *,
+        max_nodes: Optional[int] = None,
+        max_bytes: Optional[int] = None,
+    ) -> "Person": ...
+    @staticmethod
+    def from_msgpack_deduped(
+        data: bytes,
+        *,
+        max_nodes: Optional[int] = None,
+        max_bytes: Optional[int] = None,
+    ) -> "Person": ...
+    def msgpack_size_estimate(self, *, compact_keys: bool = False) -> int: ...
+    @staticmethod
+    def from_msgpack(
+        data: bytes,
+        *,
+        lossy: bool = False,
+        max_nodes: Optional[int] = None,
+        max_bytes: Optional[int] = None,
+    ) -> "Person": ...
+    @staticmethod
+    def _from_pickled_dict(dict: Mapping[str, Any]) -> "Person": ...
+    def __reduce_ex__(self, protocol: int) -> Tuple[Callable[[Any], "Person"], Tuple[Any]]: ...
+    def append_msgpack(self, path: str) -> None: ...
+    def dump_msgpack_to(self, file_obj: Any) -> None: ...
+
+class NameIterator(Iterator[str]):
+    def __iter__(self) -> "NameIterator": ...
+    def __next__(self) -> str: ...
+
+class DictIterator(Iterator[Dict[str, Any]]):
+    def __iter__(self) -> "DictIterator": ...
+    def __next__(self) -> Dict[str, Any]: ...
+
+class PathIterator(Iterator[Tuple[Tuple[int, ...], Person]]):
+    def __iter__(self) -> "PathIterator": ...
+    def __next__(self) -> Tuple[Tuple[int, ...], Person]: ...
+
+class CowPerson:
+    def __init__(self, person: Person) -> None: ...
+    def snapshot(self) -> "CowPerson": ...
+    def to_person(self, path: List[int] = ...) -> Person: ...
+    def set_name(self, path: List[int], name: str) -> None: ...
+    def set_age(self, path: List[int], age: int) -> None: ...
+    def add_child(self, path: List[int], child: Person) -> None: ...
+    def remove_child(self, path: List[int], index: int) -> Person: ...
+    def shared_nodes(self, other: "CowPerson") -> int: ...
+
+class Forest:
+    def __init__(self, roots: List[Person] = ...) -> None: ...
+    def iter_roots(self) -> Iterator[Person]: ...
+    def total_nodes(self) -> int: ...
+    def merge(self, other: "Forest") -> None: ...
+    def to_msgpack(self) -> bytes: ...
+    @staticmethod
+    def from_msgpack(data: bytes) -> "Forest": ...
+    def __len__(self) -> int: ...
+
+class PersonZipper:
+    def __init__(self, person: Person) -> None: ...
+    @property
+    def focus(self) -> Person: ...
+    @property
+    def path(self) -> List[int]: ...
+    def down(self, index: int) -> None: ...
+    def up(self) -> None: ...
+    def left(self) -> None: ...
+    def right(self) -> None: ...
+    def set_focus(self, person: Person) -> None: ...
+    def root(self) -> Person: ...
+
+class PersonRef:
+    @property
+    def name(self) -> str: ...
+    @property
+    def age(self) -> int: ...
+    @property
+    def path(self) -> List[int]: ...
+    @property
+    def child_count(self) -> int: ...
+    @property
+    def children(self) -> List["PersonRef"]: ...
+    def child(self, index: int) -> "PersonRef": ...
+    def parent(self) -> Optional["PersonRef"]: ...
+    def to_person(self) -> Person: ...
+
+class PersonRng:
+    def __init__(self, seed: int) -> None: ...
+    def random_person(self) -> Person: ...
+    def nested_person(self, depth: int, max_children: int) -> Person: ...
+
+class PersonError(ValueError):
+    MALFORMED: int
+    MISSING_FIELD: int
+    INVALID_NAME: int
+    INVALID_AGE: int
+    NAME_PATTERN: int
+    DUPLICATE_NAME: int
+    TOO_MANY_CHILDREN: int
+    TOO_MANY_NODES: int
+    PAYLOAD_TOO_LARGE: int
+    code: int
 
 def new_person(name: str, age: int) -> Person: ...
 def create_random_person() -> Person: ...
-def create_nested_person(depth: int, max_children: int) -> Person: ...
+def create_random_person_from(
+    names: List[str], min_age: int, max_age: int, seed: int
+) -> Person: ...
+def create_nested_person(
+    depth: int,
+    max_children: int,
+    name_fn: Optional[Callable[[int, int], str]] = None,
+    max_total_nodes: Optional[int] = None,
+) -> Person: ...
+def create_balanced_person(depth: int, children_per_node: int) -> Person: ...
+def create_chain(names: List[str], ages: List[int]) -> Person: ...
+def read_msgpack_log(path: str) -> List[Person]: ...
+def from_csv(path: str) -> List[Person]: ...
+def persons_from_iter(it: Iterable[Mapping[str, Any]]) -> List[Person]: ...
+def to_columns(people: List[Person]) -> Dict[str, List[Any]]: ...
+def children_histogram(people: List[Person]) -> Dict[int, int]: ...
+def to_flat_arrays(people: List[Person]) -> Tuple[List[str], List[int], List[int]]: ...
+def from_flat_arrays(names: List[str], ages: List[int], parents: List[int]) -> List[Person]: ...
+def decode_persons_parallel(
+    payloads: Sequence[Union[bytes, bytearray, memoryview]], threads: int = 0
+) -> List[Person]: ...
+def set_decode_cache_size(size: int) -> None: ...
+def clear_decode_cache() -> None: ...
+def set_name_normalization(enabled: bool) -> None: ...
+def set_default_name_pattern(pattern: Optional[str]) -> None: ...
+def set_repr_mode(mode: str) -> None: ...
+def emit_type_stubs() -> str: ...
+def capabilities() -> Dict[str, bool]: ...
@@ -0,0 +1,132 @@
+//! Queries over `Person` trees, mostly driven by Python callables.
+//!
+//! Python callbacks receive each visited node as a `Person`. Since a Python
+//! object cannot borrow into a Rust tree, that `Person` is a copy of the node's
+//! whole subtree, so each call costs time and memory proportional to the size
+//! of that subtree: visiting every node of an `n`-node tree this way is O(n²)
+//! on a chain and O(n log n) on a balanced tree. Exceptions raised by a
+//! callback stop the traversal and are propagated unchanged.
+
+use std::collections::VecDeque;
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyResult;
+use pyo3::types::PyAnyMethods;
+
+use crate::Person;
+
+/// Calls `predicate` on a copy of `node` and returns the truthiness of the result.
+///
+/// Copying takes time proportional to the size of `node`'s subtree.
+fn matches(predicate: &Bound<'_, PyAny>, node: &Person) -> PyResult<bool> {
+    predicate.call1((node.clone(),))?.is_truthy()
+}
+
+/// Calls `visitor(node, depth)` for every node in level order, root first at
+/// depth 0, skipping nodes deeper than `max_depth` if it is set.
+///
+/// Nodes are queued explicitly, so each level is visited completely, left to
+/// right, before the next one. Each call copies the node's subtree.
+pub fn bfs(root: &Person, visitor: &Bound<'_, PyAny>, max_depth: Option<usize>) -> PyResult<()> {
+    let mut queue = VecDeque::from([(root, 0)]);
+
+    while let Some((node, depth)) = queue.pop_front() {
+        visitor.call1((node.clone(), depth))?;
+        if max_depth.is_none_or(|max| depth < max) {
+            queue.extend(node.children.iter().map(|child| (child, depth + 1)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the node whose age is nearest to `target`.
+///
+/// Ties in distance are broken by the lexicographically smaller name, then by
+/// pre-order position. Every node is visited once, iteratively.
+pub fn closest_to_age(root: &Person, target: u32) -> &Person {
+    let mut best = root;
+    let mut stack = vec![root];
+
+    while let Some(node) = stack.pop() {
+        let key = (node.age.abs_diff(target), &node.name);
+        if key < (best.age.abs_diff(target), &best.name) {
+            best = node;
+        }
+        stack.extend(node.children.iter().rev());
+    }
+
+    best
+}
+
+/// Returns the path and a copy of every node matching `predicate`, in pre-order.
+///
+/// Paths are lists of child indices from `root`, as used by `paths::node_at`.
+/// The traversal itself is iterative, but each predicate call copies the
+/// node's subtree, so a full search of a deep chain is quadratic.
+pub fn find_all(
+    root: &Person,
+    predicate: &Bound<'_, PyAny>,
+) -> PyResult<Vec<(Vec<usize>, Person)>> {
+    let mut found = Vec::new();
+    let mut stack = vec![(root, vec![])];
+
+    while let Some((node, path)) = stack.pop() {
+        if matches(predicate, node)? {
+            found.push((path.clone(), node.clone()));
+        }
+        for (index, child) in node.children.iter().enumerate().rev() {
+            let mut child_path = path.clone();
+            child_path.push(index);
+            stack.push((child, child_path));
+        }
+    }
+
+    Ok(found)
+}
+
+/// Returns a copy of `root` keeping only the nodes that match `predicate` or
+/// have a matching descendant, or `None` if nothing matches.
+///
+/// The predicate is called once per node, in pre-order, on a copy of that
+/// node's subtree. The traversal itself is iterative, but those copies make
+/// filtering a deep chain quadratic.
+pub fn filter_tree(root: &Person, predicate: &Bound<'_, PyAny>) -> PyResult<Option<Person>> {
+    struct Frame<'a> {
+        node: &'a Person,
+        matched: bool,
+        kept: Vec<Person>,
+        next_child: usize,
+    }
+
+    let mut stack = vec![Frame {
+        node: root,
+        matched: matches(predicate, root)?,
+        kept: vec![],
+        next_child: 0,
+    }];
+
+    loop {
+        let frame = stack.last_mut().unwrap();
+        if let Some(child) = frame.node.children.get(frame.next_child) {
+            frame.next_child += 1;
+            stack.push(Frame {
+                node: child,
+                matched: matches(predicate, child)?,
+                kept: vec![],
+                next_child: 0,
+            });
+            continue;
+        }
+
+        let frame = stack.pop().unwrap();
+        let result = (frame.matched || !frame.kept.is_empty())
+            .then(|| Person::new(frame.node.name.clone(), frame.node.age, frame.kept));
+
+        match stack.last_mut() {
+            Some(parent) => parent.kept.extend(result),
+            None => return Ok(result),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Reshaping of `Person` trees into balanced fixtures.
+//!
+//! Callers are responsible for calling `Person::mark_changed` on the root
+//! after a successful edit.
+
+use crate::Person;
+
+/// Rebuilds the tree as a balanced binary search tree over its nodes, ordered
+/// by name and then by age.
+///
+/// The tree keeps the same multiset of `(name, age)` pairs, but the original
+/// hierarchy is discarded entirely: every node ends up with at most two
+/// children, the median node becomes the root, and an in-order walk visits the
+/// nodes in sorted order. A tree of `n` nodes becomes `ceil(log2(n + 1))`
+/// levels deep, which makes this useful for comparing traversals over different
+/// shapes of the same data.
+pub fn rebalance(root: &mut Person) {
+    let mut nodes = Vec::new();
+    let mut stack = vec![std::mem::take(&mut root.children)];
+    nodes.push((std::mem::take(&mut root.name), root.age));
+
+    while let Some(children) = stack.pop() {
+        for mut child in children {
+            nodes.push((std::mem::take(&mut child.name), child.age));
+            stack.push(std::mem::take(&mut child.children));
+        }
+    }
+    nodes.sort();
+
+    // `nodes` always holds the root, so the balanced tree is never empty.
+    let mut balanced = build(nodes).unwrap();
+    root.name = std::mem::take(&mut balanced.name);
+    root.age = balanced.age;
+    root.children = std::mem::take(&mut balanced.children);
+}
+
+/// Builds a balanced tree from sorted nodes. The recursion is only as deep as
+/// the resulting tree, which is logarithmic in the number of nodes.
+fn build(mut nodes: Vec<(String, u32)>) -> Option<Person> {
+    if nodes.is_empty() {
+        return None;
+    }
+    let right = nodes.split_off(nodes.len() / 2 + 1);
+    let (name, age) = nodes.pop().unwrap();
+    let children = [build(nodes), build(right)].into_iter().flatten().collect();
+    Some(Person::new(name, age, children))
+}
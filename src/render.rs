@@ -0,0 +1,77 @@
+//! Human-readable rendering of `Person` trees.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+
+use crate::Person;
+
+/// Whether `Person.__repr__` renders a tree instead of the debug form. Off by default.
+static TREE_REPR: AtomicBool = AtomicBool::new(false);
+
+/// Selects the `__repr__` style process-wide: `"tree"` for `render_tree`
+/// output or `"debug"` for the `{:?}` form.
+pub fn set_repr_mode(mode: &str) -> PyResult<()> {
+    let tree = match mode {
+        "tree" => true,
+        "debug" => false,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "repr mode must be 'tree' or 'debug', got {:?}",
+                mode
+            )));
+        }
+    };
+    TREE_REPR.store(tree, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns whether `__repr__` should use `render_tree`.
+pub fn tree_repr_enabled() -> bool {
+    TREE_REPR.load(Ordering::Relaxed)
+}
+
+/// Renders a person tree in the style of the `tree` command.
+///
+/// Each node is printed on its own line as `name (age)`, with Unicode
+/// box-drawing connectors showing the hierarchy:
+///
+/// ```text
+/// John (30)
+/// ├── John (30)
+/// │   └── John (30)
+/// └── John (30)
+/// ```
+///
+/// Siblings are stacked vertically, so the output width only grows with the
+/// depth of the tree (four columns per level), never with the number of children.
+/// The traversal is iterative, so arbitrarily deep trees are safe to render.
+pub fn render_tree(root: &Person) -> String {
+    let mut out = format!("{} ({})", root.name, root.age);
+    let mut prefix = String::new();
+    // Children still to render at each level, with the prefix length to restore
+    // once that level is exhausted.
+    let mut stack = vec![(root.children.iter(), 0)];
+
+    while let Some((children, _)) = stack.last_mut() {
+        let Some(child) = children.next() else {
+            let (_, prefix_len) = stack.pop().unwrap();
+            prefix.truncate(prefix_len);
+            continue;
+        };
+        let is_last = children.len() == 0;
+
+        out.push('\n');
+        out.push_str(&prefix);
+        out.push_str(if is_last { "└── " } else { "├── " });
+        out.push_str(&format!("{} ({})", child.name, child.age));
+
+        let prefix_len = prefix.len();
+        prefix.push_str(if is_last { "    " } else { "│   " });
+        stack.push((child.children.iter(), prefix_len));
+    }
+
+    out
+}
@@ -0,0 +1,54 @@
+//! JSON Schema validation of Python values before they are converted to `Person`.
+
+use pyo3::Bound;
+use pyo3::PyAny;
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyAnyMethods;
+use pyo3::types::PyString;
+use pyo3::types::PyStringMethods;
+use serde_json::Value;
+
+use crate::Person;
+use crate::Wrapper;
+use crate::json::py_to_json;
+
+/// Validates `value` against a JSON Schema, then converts it to a `Person`.
+///
+/// The schema may be given as a Python object (usually a dict) or as a string
+/// of JSON text. The value is converted to JSON first, so `Person` instances
+/// are checked through their dict form.
+///
+/// Every schema violation is reported in a single `PyValueError`, one per line,
+/// prefixed with the JSON pointer of the offending value.
+pub fn validate_against_schema(
+    value: &Bound<'_, PyAny>,
+    schema: &Bound<'_, PyAny>,
+) -> PyResult<Person> {
+    let schema: Value = match schema.downcast::<PyString>() {
+        Ok(text) => serde_json::from_str(text.to_str()?)
+            .map_err(|e| PyValueError::new_err(format!("Schema is not valid JSON: {}", e)))?,
+        Err(_) => py_to_json(schema)?,
+    };
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| PyValueError::new_err(format!("Invalid JSON Schema: {}", e)))?;
+
+    let instance = py_to_json(value)?;
+    let violations: Vec<String> = validator
+        .iter_errors(&instance)
+        .map(|error| {
+            let path = error.instance_path().as_str();
+            let path = if path.is_empty() { "/" } else { path };
+            format!("{}: {}", path, error)
+        })
+        .collect();
+
+    if !violations.is_empty() {
+        return Err(PyValueError::new_err(format!(
+            "Value does not match schema:\n{}",
+            violations.join("\n")
+        )));
+    }
+
+    Wrapper::validate(value)
+}
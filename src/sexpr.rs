@@ -0,0 +1,281 @@
+//! A compact S-expression text form of `Person` trees.
+//!
+//! Each person is a list of its name, its age and then its children, each a
+//! list again:
+//!
+//! ```text
+//! (John 30 (Ann 5) (Bob 3 (Cy 1)))
+//! ```
+//!
+//! A name is written bare unless it is empty or contains whitespace, `(`, `)`,
+//! `"` or `\`; then it is written in double quotes, with `"` and `\` escaped by
+//! a backslash, as in `("Mary Ann" 40)` or `("a \"b\"" 1)`. No other escapes
+//! exist, so any other character, including a newline, stands for itself
+//! inside quotes. Ages are decimal integers.
+//!
+//! When parsing, any amount of whitespace may separate the elements, and a
+//! quoted name is accepted even where a bare one would do. Anything after the
+//! root list other than whitespace is an error.
+
+use pyo3::PyErr;
+use pyo3::PyResult;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+
+/// Writes a person tree as an S-expression.
+///
+/// The traversal is iterative, so deep trees cannot overflow the stack.
+pub fn to_sexpr(root: &Person) -> String {
+    enum Step<'a> {
+        Node(&'a Person),
+        Close,
+    }
+
+    let mut out = String::new();
+    let mut stack = vec![Step::Node(root)];
+    while let Some(step) = stack.pop() {
+        let Step::Node(node) = step else {
+            out.push(')');
+            continue;
+        };
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push('(');
+        write_name(&mut out, &node.name);
+        out.push(' ');
+        out.push_str(&node.age.to_string());
+
+        stack.push(Step::Close);
+        stack.extend(node.children.iter().rev().map(Step::Node));
+    }
+    out
+}
+
+/// Whether `c` ends a bare name.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"' | '\\')
+}
+
+fn write_name(out: &mut String, name: &str) {
+    if !name.is_empty() && !name.chars().any(is_delimiter) {
+        out.push_str(name);
+        return;
+    }
+    out.push('"');
+    for c in name.chars() {
+        if matches!(c, '"' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
+
+/// Parses a person tree from an S-expression.
+///
+/// Returns a `PersonError` naming the byte offset of the first problem if the
+/// text is not exactly one well-formed person list.
+pub fn from_sexpr(text: &str) -> PyResult<Person> {
+    let mut parser = Parser { text, pos: 0 };
+    // People whose lists are still open, outermost first.
+    let mut open: Vec<Person> = vec![parser.start_person()?];
+
+    loop {
+        parser.skip_whitespace();
+        match parser.peek() {
+            Some('(') => {
+                let child = parser.start_person()?;
+                open.push(child);
+            }
+            Some(')') => {
+                parser.pos += 1;
+                let person = open.pop().unwrap();
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(person),
+                    None => {
+                        parser.skip_whitespace();
+                        if parser.pos != text.len() {
+                            return Err(parser.error("Unexpected trailing text"));
+                        }
+                        return Ok(person);
+                    }
+                }
+            }
+            Some(_) => return Err(parser.error("Expected a child list or `)`")),
+            None => return Err(parser.error("Unterminated list")),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    /// The byte offset of the next unread character.
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> PyErr {
+        self.coded_error(ErrorCode::Malformed, message)
+    }
+
+    fn coded_error(&self, code: ErrorCode, message: &str) -> PyErr {
+        code.err(format!("{} at byte {} of S-expression", message, self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Reads `(`, a name and an age, returning the still childless person.
+    fn start_person(&mut self) -> PyResult<Person> {
+        self.skip_whitespace();
+        if self.peek() != Some('(') {
+            return Err(self.error("Expected `(`"));
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+        let name = self.read_name()?;
+        self.skip_whitespace();
+        let age = self.read_age()?;
+        Ok(Person::new(name, age, vec![]))
+    }
+
+    fn read_name(&mut self) -> PyResult<String> {
+        if self.peek() != Some('"') {
+            let token = self.read_bare();
+            if token.is_empty() {
+                return Err(self.error("Expected a name"));
+            }
+            return Ok(token.to_string());
+        }
+
+        let start = self.pos;
+        self.pos += 1;
+        let mut name = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += offset + 1;
+                    return Ok(name);
+                }
+                '\\' => match chars.next() {
+                    Some((_, escaped @ ('"' | '\\'))) => name.push(escaped),
+                    _ => {
+                        self.pos += offset;
+                        return Err(self.error("Invalid escape in name"));
+                    }
+                },
+                _ => name.push(c),
+            }
+        }
+        self.pos = start;
+        Err(self.error("Unterminated quoted name"))
+    }
+
+    fn read_age(&mut self) -> PyResult<u32> {
+        let start = self.pos;
+        let token = self.read_bare();
+        if token.is_empty() || !token.bytes().all(|b| b.is_ascii_digit()) {
+            self.pos = start;
+            return Err(self.coded_error(ErrorCode::InvalidAge, "Expected an age"));
+        }
+        token.parse().map_err(|_| {
+            self.pos = start;
+            self.coded_error(ErrorCode::InvalidAge, "Age out of range")
+        })
+    }
+
+    /// Reads characters up to the next delimiter.
+    fn read_bare(&mut self) -> &'a str {
+        let rest = &self.text[self.pos..];
+        let len = rest.find(is_delimiter).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::code_of;
+
+    fn person(name: &str, age: u32, children: Vec<Person>) -> Person {
+        Person::new(name.to_string(), age, children)
+    }
+
+    #[test]
+    fn writes_the_documented_form() {
+        let root = person(
+            "John",
+            30,
+            vec![
+                person("Ann", 5, vec![]),
+                person("Bob", 3, vec![person("Cy", 1, vec![])]),
+            ],
+        );
+        assert_eq!(to_sexpr(&root), "(John 30 (Ann 5) (Bob 3 (Cy 1)))");
+        assert_eq!(from_sexpr(&to_sexpr(&root)).unwrap(), root);
+    }
+
+    #[test]
+    fn round_trips_nested_trees_and_awkward_names() {
+        let names = [
+            "Mary Ann",
+            "(paren",
+            "paren)",
+            "a \"b\"",
+            "back\\slash",
+            "",
+            "new\nline",
+            "\\\"",
+        ];
+        let leaves = names.iter().map(|name| person(name, 1, vec![])).collect();
+        let root = person(
+            "",
+            2,
+            vec![person("mid dle", 3, leaves), person("x", 4, vec![])],
+        );
+        let text = to_sexpr(&root);
+        assert_eq!(from_sexpr(&text).unwrap(), root, "{}", text);
+        assert!(text.contains(r#"("a \"b\"" 1)"#), "{}", text);
+
+        let deep = crate::create_chain(
+            (0..1000).map(|i| format!("n {}", i)).collect(),
+            (0..1000).collect(),
+        )
+        .unwrap();
+        assert_eq!(from_sexpr(&to_sexpr(&deep)).unwrap(), deep);
+    }
+
+    #[test]
+    fn accepts_extra_whitespace_and_quoted_names() {
+        let parsed = from_sexpr("  (\"John\"\t30\n ( Ann  5 ) )  ").unwrap();
+        assert_eq!(parsed, person("John", 30, vec![person("Ann", 5, vec![])]));
+    }
+
+    #[test]
+    fn rejects_trailing_text_and_unterminated_lists() {
+        for (text, message) in [
+            ("(a 1) (b 2)", "Unexpected trailing text at byte 6"),
+            ("(a 1) x", "Unexpected trailing text at byte 6"),
+            ("(a 1 (b 2)", "Unterminated list at byte 10"),
+            ("(a 1", "Unterminated list at byte 4"),
+            ("(\"a 1)", "Unterminated quoted name"),
+        ] {
+            let err = from_sexpr(text).unwrap_err();
+            assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32), "{}", text);
+            assert!(err.to_string().contains(message), "{}: {}", text, err);
+        }
+
+        let err = from_sexpr("(a 99999999999)").unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::InvalidAge as u32));
+    }
+}
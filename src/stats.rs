@@ -0,0 +1,232 @@
+//! Whole-tree statistics gathered in a single traversal.
+
+use std::collections::BTreeMap;
+
+use pyo3::Bound;
+use pyo3::PyResult;
+use pyo3::Python;
+use pyo3::types::PyDict;
+use pyo3::types::PyDictMethods;
+
+use crate::Person;
+
+/// Aggregate statistics over a person and all of its descendants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    /// Total number of nodes, including the root.
+    pub count: usize,
+    /// Number of edges on the longest root-to-leaf path (0 for a lone root).
+    pub height: usize,
+    pub min_age: u32,
+    pub max_age: u32,
+    pub mean_age: f64,
+    /// Largest number of direct children of any single node.
+    pub max_branching: usize,
+}
+
+impl TreeStats {
+    /// Computes the statistics with one iterative pre-order traversal.
+    pub fn compute(root: &Person) -> Self {
+        let mut stats = TreeStats {
+            count: 0,
+            height: 0,
+            min_age: u32::MAX,
+            max_age: 0,
+            mean_age: 0.0,
+            max_branching: 0,
+        };
+        let mut age_sum = 0u64;
+        let mut stack = vec![(root, 0)];
+
+        while let Some((node, depth)) = stack.pop() {
+            stats.count += 1;
+            stats.height = stats.height.max(depth);
+            stats.min_age = stats.min_age.min(node.age);
+            stats.max_age = stats.max_age.max(node.age);
+            stats.max_branching = stats.max_branching.max(node.children.len());
+            age_sum += u64::from(node.age);
+
+            stack.extend(node.children.iter().map(|child| (child, depth + 1)));
+        }
+
+        stats.mean_age = age_sum as f64 / stats.count as f64;
+        stats
+    }
+
+    /// Converts the statistics to a Python dictionary keyed by field name.
+    pub fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("count", self.count)?;
+        dict.set_item("height", self.height)?;
+        dict.set_item("min_age", self.min_age)?;
+        dict.set_item("max_age", self.max_age)?;
+        dict.set_item("mean_age", self.mean_age)?;
+        dict.set_item("max_branching", self.max_branching)?;
+        Ok(dict)
+    }
+}
+
+/// The depth and descendant count of a tree, which `Person` caches per
+/// version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shape {
+    /// Number of edges on the longest root-to-leaf path (0 for a lone root).
+    pub depth: usize,
+    /// Number of nodes below the root.
+    pub descendants: usize,
+}
+
+impl Shape {
+    /// Computes the shape with one iterative traversal.
+    pub fn compute(root: &Person) -> Self {
+        let mut shape = Shape {
+            depth: 0,
+            descendants: 0,
+        };
+        let mut stack = vec![(root, 0)];
+
+        while let Some((node, depth)) = stack.pop() {
+            shape.depth = shape.depth.max(depth);
+            shape.descendants += node.children.len();
+            stack.extend(node.children.iter().map(|child| (child, depth + 1)));
+        }
+        shape
+    }
+}
+
+/// Counts leaves (nodes without children) and internal nodes in one iterative
+/// traversal, returned in that order. The two always sum to the node count.
+pub fn leaf_internal_counts(root: &Person) -> (usize, usize) {
+    let mut leaves = 0;
+    let mut internal = 0;
+    let mut stack = vec![root];
+
+    while let Some(node) = stack.pop() {
+        if node.children.is_empty() {
+            leaves += 1;
+        } else {
+            internal += 1;
+        }
+        stack.extend(node.children.iter());
+    }
+
+    (leaves, internal)
+}
+
+/// Counts the nodes of a forest by number of children, in one iterative
+/// traversal per tree.
+///
+/// Only branching factors that occur are present, in increasing order.
+pub fn children_histogram(people: &[Person]) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    let mut stack: Vec<&Person> = people.iter().collect();
+
+    while let Some(node) = stack.pop() {
+        *histogram.entry(node.children.len()).or_default() += 1;
+        stack.extend(node.children.iter());
+    }
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::Py;
+    use pyo3::types::PyBytes;
+
+    use super::*;
+    use crate::create_balanced_person;
+    use crate::patch::make_patch;
+    use crate::paths::node_at_mut;
+
+    fn leaf(name: &str) -> Person {
+        Person::new(name.to_string(), 1, vec![])
+    }
+
+    #[test]
+    fn shape_is_cached_until_marked_changed() {
+        let mut root = create_balanced_person(2, 2);
+        let shape = Shape {
+            depth: 2,
+            descendants: 6,
+        };
+        assert_eq!(root.state.cached_shape(), None);
+        assert_eq!(root.shape(), shape);
+        assert_eq!(root.state.cached_shape(), Some(shape));
+
+        // An edit that is not recorded leaves the cached shape in place.
+        node_at_mut(&mut root, &[0, 1])
+            .unwrap()
+            .children
+            .push(leaf("x"));
+        assert_eq!(root.shape(), shape);
+
+        root.mark_changed();
+        assert_eq!(root.state.cached_shape(), None);
+        assert_eq!(
+            root.shape(),
+            Shape {
+                depth: 3,
+                descendants: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn python_mutations_invalidate_depth_and_count_descendants() {
+        Python::with_gil(|py| {
+            let object = Py::new(py, create_balanced_person(2, 2)).unwrap();
+            let check = |expected: (usize, usize)| {
+                assert_eq!(object.borrow(py).state.cached_shape(), None);
+                let depth: usize = object
+                    .call_method0(py, "depth")
+                    .unwrap()
+                    .extract(py)
+                    .unwrap();
+                let descendants: usize = object
+                    .call_method0(py, "count_descendants")
+                    .unwrap()
+                    .extract(py)
+                    .unwrap();
+                assert_eq!((depth, descendants), expected);
+                let shape = object.borrow(py).state.cached_shape();
+                assert_eq!(shape, Some(Shape::compute(&object.borrow(py))));
+            };
+            check((2, 6));
+
+            let chain = Person::new("a".to_string(), 1, vec![leaf("b")]);
+            let chain = Person::new("c".to_string(), 1, vec![chain]);
+            object.call_method1(py, "add_child", (chain,)).unwrap();
+            check((3, 9));
+
+            object.setattr(py, "name", "renamed").unwrap();
+            check((3, 9));
+            object.setattr(py, "age", 31).unwrap();
+            check((3, 9));
+            object.setattr(py, "children", vec![leaf("d")]).unwrap();
+            check((1, 1));
+
+            let old = object.borrow(py).clone();
+            let mut new = old.clone();
+            new.children[0].children.push(create_balanced_person(1, 3));
+            let patch = PyBytes::new(py, &make_patch(&old, &new));
+            object.call_method1(py, "apply_patch", (patch,)).unwrap();
+            check((3, 5));
+
+            object
+                .call_method1(py, "move_child", (vec![0, 0], Vec::<usize>::new()))
+                .unwrap();
+            check((2, 5));
+
+            // Rust code editing through `node_at_mut` records the change on the root.
+            {
+                let mut root = object.borrow_mut(py);
+                node_at_mut(&mut root, &[0])
+                    .unwrap()
+                    .children
+                    .push(leaf("e"));
+                root.mark_changed();
+            }
+            check((2, 6));
+        });
+    }
+}
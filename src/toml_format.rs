@@ -0,0 +1,194 @@
+//! TOML serialization for human-edited `Person` files.
+//!
+//! # Mapping
+//!
+//! The root person is the top-level table, holding `name` (string) and `age`
+//! (integer). Children are an array of tables under `children`, so each level of
+//! nesting adds another `children` segment to the table header:
+//!
+//! ```toml
+//! age = 30
+//! name = "John"
+//!
+//! [[children]]
+//! age = 5
+//! name = "Jane"
+//!
+//! [[children.children]]
+//! age = 1
+//! name = "Jim"
+//! ```
+//!
+//! Keys are written in sorted order; any order is accepted when parsing.
+//!
+//! A node without children omits the `children` key, and `from_toml` treats a
+//! missing `children` key as an empty list. Since every level lengthens the
+//! headers, trees deeper than [`MAX_TOML_DEPTH`] are rejected in both directions.
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyValueError;
+use toml::Table;
+use toml::Value;
+
+use crate::Person;
+use crate::errors::ErrorCode;
+
+/// The deepest tree, counted in edges below the root, that TOML files may hold.
+pub const MAX_TOML_DEPTH: usize = 8;
+
+/// Serializes a person tree to a TOML document.
+pub fn to_toml(person: &Person) -> PyResult<String> {
+    let table = person_to_table(person, 0)?;
+    toml::to_string(&table)
+        .map_err(|e| PyValueError::new_err(format!("Cannot serialize Person to TOML: {}", e)))
+}
+
+/// Parses a person tree from a TOML document.
+pub fn from_toml(text: &str) -> PyResult<Person> {
+    let table: Table = toml::from_str(text)
+        .map_err(|e| ErrorCode::Malformed.err(format!("Invalid TOML: {}", e)))?;
+    table_to_person(&table, 0, "")
+}
+
+fn depth_error(depth: usize) -> pyo3::PyErr {
+    ErrorCode::Malformed.err(format!(
+        "Tree depth {} exceeds the TOML limit of {}",
+        depth, MAX_TOML_DEPTH
+    ))
+}
+
+fn person_to_table(person: &Person, depth: usize) -> PyResult<Table> {
+    if depth > MAX_TOML_DEPTH {
+        return Err(depth_error(depth));
+    }
+
+    let mut table = Table::new();
+    table.insert("name".into(), Value::String(person.name.clone()));
+    table.insert("age".into(), Value::Integer(i64::from(person.age)));
+    if !person.children.is_empty() {
+        let children = person
+            .children
+            .iter()
+            .map(|child| person_to_table(child, depth + 1).map(Value::Table))
+            .collect::<PyResult<Vec<_>>>()?;
+        table.insert("children".into(), Value::Array(children));
+    }
+    Ok(table)
+}
+
+/// Converts a table to a person. `path` is the dotted key of the table
+/// (empty for the root), used in error messages.
+fn table_to_person(table: &Table, depth: usize, path: &str) -> PyResult<Person> {
+    if depth > MAX_TOML_DEPTH {
+        return Err(depth_error(depth));
+    }
+    let location = if path.is_empty() { "root" } else { path };
+
+    let name = match table.get("name") {
+        Some(Value::String(name)) => name.clone(),
+        _ => {
+            return Err(ErrorCode::MissingField.err(format!("{}: missing string `name`", location)));
+        }
+    };
+    let age = match table.get("age") {
+        Some(Value::Integer(age)) => u32::try_from(*age).map_err(|_| {
+            ErrorCode::InvalidAge.err(format!("{}: age {} is out of range", location, age))
+        })?,
+        _ => {
+            return Err(ErrorCode::MissingField.err(format!("{}: missing integer `age`", location)));
+        }
+    };
+    let children = match table.get("children") {
+        None => vec![],
+        Some(Value::Array(items)) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let child_path = if path.is_empty() {
+                    format!("children[{}]", i)
+                } else {
+                    format!("{}.children[{}]", path, i)
+                };
+                match item {
+                    Value::Table(child) => table_to_person(child, depth + 1, &child_path),
+                    _ => Err(ErrorCode::Malformed.err(format!("{}: expected a table", child_path))),
+                }
+            })
+            .collect::<PyResult<Vec<_>>>()?,
+        Some(_) => {
+            return Err(ErrorCode::Malformed.err(format!(
+                "{}: `children` must be an array of tables",
+                location
+            )));
+        }
+    };
+
+    Ok(Person::new(name, age, children))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::code_of;
+
+    /// A tree `depth` edges deep whose every node has two children, the
+    /// second a leaf, so that array-of-tables headers at each level are
+    /// followed by a sibling.
+    fn tree(depth: usize) -> Person {
+        let mut node = Person::new("leaf".to_string(), 1, vec![]);
+        for level in (0..depth).rev() {
+            let sibling = Person::new(format!("sibling {}", level), 2, vec![]);
+            node = Person::new(format!("level {}", level), 30, vec![node, sibling]);
+        }
+        node
+    }
+
+    /// A TOML document holding a chain `depth` edges deep.
+    fn chain_text(depth: usize) -> String {
+        (0..=depth)
+            .map(|level| {
+                let header = match level {
+                    0 => String::new(),
+                    _ => format!("[[{}]]\n", vec!["children"; level].join(".")),
+                };
+                format!("{}name = \"n{}\"\nage = {}\n\n", header, level, level)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_up_to_the_depth_limit() {
+        for depth in [0, 1, MAX_TOML_DEPTH] {
+            let person = tree(depth);
+            let text = to_toml(&person).unwrap();
+            assert_eq!(from_toml(&text).unwrap(), person, "depth {}", depth);
+            assert_eq!(person.shape().depth, depth);
+        }
+    }
+
+    #[test]
+    fn parses_hand_written_chains() {
+        let person = from_toml(&chain_text(MAX_TOML_DEPTH)).unwrap();
+        assert_eq!(person.shape().depth, MAX_TOML_DEPTH);
+        assert_eq!(person.children[0].name, "n1");
+    }
+
+    #[test]
+    fn rejects_trees_past_the_depth_limit() {
+        let err = to_toml(&tree(MAX_TOML_DEPTH + 1)).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+        assert!(
+            err.to_string().contains("exceeds the TOML limit"),
+            "{}",
+            err
+        );
+
+        let err = from_toml(&chain_text(MAX_TOML_DEPTH + 1)).unwrap_err();
+        assert_eq!(code_of(&err), Some(ErrorCode::Malformed as u32));
+        assert!(
+            err.to_string().contains("exceeds the TOML limit"),
+            "{}",
+            err
+        );
+    }
+}
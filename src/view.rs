@@ -0,0 +1,140 @@
+//! Clone-free, read-only views into `Person` trees.
+//!
+//! The `children` getter and most query methods copy subtrees out of a tree.
+//! A `PersonRef` instead names one node of a tree owned by Python, by keeping
+//! a reference to the root `Person` and the node's path. Reading a field
+//! borrows the root just long enough to look the node up and copy that one
+//! field, so walking a large tree through views never copies a subtree.
+//!
+//! # Borrow constraints
+//!
+//! A view stays tied to the tree it came from, not to the data at the time it
+//! was created. Once the root is mutated (its `version` changes), every view
+//! into it is stale, and touching one raises `RuntimeError` rather than
+//! silently showing another node. Views never hold a borrow between calls, so
+//! they never block mutation of the tree, and reading one while the root is
+//! mutably borrowed on another thread raises `RuntimeError` as well.
+
+use pyo3::Bound;
+use pyo3::Py;
+use pyo3::PyResult;
+use pyo3::Python;
+use pyo3::exceptions::PyIndexError;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::pyclass;
+use pyo3::pymethods;
+
+use crate::Person;
+use crate::paths::node_at;
+
+/// A read-only view of one node of a tree owned by Python.
+#[pyclass(frozen)]
+pub struct PersonRef {
+    root: Py<Person>,
+    /// The root's version when the view was created.
+    version: u64,
+    path: Vec<usize>,
+}
+
+impl PersonRef {
+    /// Creates a view of the root of `root`.
+    pub fn new(root: &Bound<'_, Person>) -> Self {
+        PersonRef {
+            root: root.clone().unbind(),
+            version: root.borrow().version(),
+            path: vec![],
+        }
+    }
+
+    /// Returns a view of another node of the same tree.
+    fn at(&self, py: Python<'_>, path: Vec<usize>) -> Self {
+        PersonRef {
+            root: self.root.clone_ref(py),
+            version: self.version,
+            path,
+        }
+    }
+
+    /// Looks up the viewed node and applies `f` to it.
+    fn with_node<T>(&self, py: Python<'_>, f: impl FnOnce(&Person) -> T) -> PyResult<T> {
+        let root = self.root.bind(py).try_borrow()?;
+        if root.version() != self.version {
+            return Err(PyRuntimeError::new_err(
+                "Person changed since this view was created",
+            ));
+        }
+        Ok(f(node_at(&root, &self.path)?))
+    }
+}
+
+#[pymethods]
+impl PersonRef {
+    /// The viewed person's name.
+    #[getter]
+    fn name(&self, py: Python<'_>) -> PyResult<String> {
+        self.with_node(py, |node| node.name.clone())
+    }
+
+    /// The viewed person's age.
+    #[getter]
+    fn age(&self, py: Python<'_>) -> PyResult<u32> {
+        self.with_node(py, |node| node.age)
+    }
+
+    /// The child indices from the root to the viewed node.
+    #[getter]
+    fn path(&self) -> Vec<usize> {
+        self.path.clone()
+    }
+
+    /// The number of children of the viewed node.
+    #[getter]
+    fn child_count(&self, py: Python<'_>) -> PyResult<usize> {
+        self.with_node(py, |node| node.children.len())
+    }
+
+    /// Views of every child of the viewed node, in order.
+    #[getter]
+    fn children(&self, py: Python<'_>) -> PyResult<Vec<PersonRef>> {
+        let count = self.with_node(py, |node| node.children.len())?;
+        Ok((0..count)
+            .map(|index| {
+                let mut path = self.path.clone();
+                path.push(index);
+                self.at(py, path)
+            })
+            .collect())
+    }
+
+    /// Returns a view of the child at `index`.
+    ///
+    /// # Arguments
+    /// * `index` - The position of the child among its siblings
+    fn child(&self, py: Python<'_>, index: usize) -> PyResult<PersonRef> {
+        let count = self.with_node(py, |node| node.children.len())?;
+        if index >= count {
+            return Err(PyIndexError::new_err(format!(
+                "Child index {} is out of range for {} children",
+                index, count
+            )));
+        }
+        let mut path = self.path.clone();
+        path.push(index);
+        Ok(self.at(py, path))
+    }
+
+    /// Returns a view of the parent, or `None` for the root.
+    fn parent(&self, py: Python<'_>) -> Option<PersonRef> {
+        let (_, parent) = self.path.split_last()?;
+        Some(self.at(py, parent.to_vec()))
+    }
+
+    /// Copies the viewed subtree out as an independent Person.
+    fn to_person(&self, py: Python<'_>) -> PyResult<Person> {
+        self.with_node(py, Person::clone)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("PersonRef(path={:?})", self.path)
+    }
+}
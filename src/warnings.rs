@@ -0,0 +1,74 @@
+//! Soft validation: data that is accepted but worth flagging.
+//!
+//! The built-in rules, checked for every node, are:
+//!
+//! - the age is above [`MAX_PLAUSIBLE_AGE`];
+//! - the name is empty or consists only of whitespace;
+//! - the name has leading or trailing whitespace.
+//!
+//! Optionally, a node whose name repeats the name of an earlier sibling is
+//! also flagged, once per repeat, naming the first sibling with that name.
+//!
+//! Each warning is a message prefixed with the node's path, such as
+//! `children[0].children[2]: age 212 is suspiciously high`.
+
+use std::collections::HashMap;
+
+use crate::Person;
+use crate::paths::describe;
+
+/// Ages above this are reported as suspicious.
+pub const MAX_PLAUSIBLE_AGE: u32 = 120;
+
+/// Collects warnings for every node of a tree, in pre-order.
+///
+/// With `duplicate_names` set, repeated sibling names are reported too.
+pub fn collect(root: &Person, duplicate_names: bool) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut stack = vec![(root, vec![])];
+
+    while let Some((node, path)) = stack.pop() {
+        let location = describe(&path);
+        if node.age > MAX_PLAUSIBLE_AGE {
+            warnings.push(format!(
+                "{}: age {} is suspiciously high",
+                location, node.age
+            ));
+        }
+        if node.name.trim().is_empty() {
+            warnings.push(format!("{}: name {:?} is empty", location, node.name));
+        } else if node.name.trim() != node.name {
+            warnings.push(format!(
+                "{}: name {:?} has leading or trailing whitespace",
+                location, node.name
+            ));
+        }
+
+        if duplicate_names {
+            let mut first_seen: HashMap<&str, usize> = HashMap::new();
+            for (i, child) in node.children.iter().enumerate() {
+                let first = *first_seen.entry(&child.name).or_insert(i);
+                if first != i {
+                    let mut child_path = path.clone();
+                    child_path.push(i);
+                    let mut first_path = path.clone();
+                    first_path.push(first);
+                    warnings.push(format!(
+                        "{}: name {:?} repeats sibling {}",
+                        describe(&child_path),
+                        child.name,
+                        describe(&first_path)
+                    ));
+                }
+            }
+        }
+
+        for (i, child) in node.children.iter().enumerate().rev() {
+            let mut child_path = path.clone();
+            child_path.push(i);
+            stack.push((child, child_path));
+        }
+    }
+
+    warnings
+}
@@ -0,0 +1,143 @@
+//! A functional zipper for navigating and editing `Person` trees.
+//!
+//! The zipper owns its tree, split into the node in focus and the trail of
+//! parents above it. Each parent on the trail holds its own fields and the
+//! siblings to the left and right of the path, so moving the focus only moves
+//! nodes between the focus and the trail and never copies a subtree.
+
+use pyo3::PyResult;
+use pyo3::exceptions::PyIndexError;
+use pyo3::pyclass;
+use pyo3::pymethods;
+
+use crate::Person;
+
+/// A parent left behind when the focus moved down to one of its children.
+struct Crumb {
+    name: String,
+    age: u32,
+    /// Siblings before the focus, in order.
+    left: Vec<Person>,
+    /// Siblings after the focus, in reverse order so the nearest is last.
+    right: Vec<Person>,
+}
+
+/// A movable focus within a tree, which can be edited in place and zipped back
+/// into a whole tree with `root()`.
+///
+/// Moves that would leave the tree raise `IndexError` and leave the zipper
+/// where it was.
+#[pyclass]
+pub struct PersonZipper {
+    focus: Person,
+    trail: Vec<Crumb>,
+}
+
+impl PersonZipper {
+    fn move_up(&mut self) -> bool {
+        let Some(crumb) = self.trail.pop() else {
+            return false;
+        };
+        let focus = std::mem::replace(&mut self.focus, Person::new(crumb.name, crumb.age, vec![]));
+        let mut children = crumb.left;
+        children.push(focus);
+        children.extend(crumb.right.into_iter().rev());
+        self.focus.children = children;
+        true
+    }
+}
+
+#[pymethods]
+impl PersonZipper {
+    /// Creates a zipper focused on the root of a copy of `person`.
+    #[new]
+    fn new(person: Person) -> Self {
+        PersonZipper {
+            focus: person,
+            trail: vec![],
+        }
+    }
+
+    /// A copy of the subtree in focus.
+    #[getter]
+    fn focus(&self) -> Person {
+        self.focus.clone()
+    }
+
+    /// The child indices from the root to the focus.
+    #[getter]
+    fn path(&self) -> Vec<usize> {
+        self.trail.iter().map(|crumb| crumb.left.len()).collect()
+    }
+
+    /// Moves the focus to its child at `index`.
+    fn down(&mut self, index: usize) -> PyResult<()> {
+        if index >= self.focus.children.len() {
+            return Err(PyIndexError::new_err(format!(
+                "Cannot move down to child {}: the focus has {} children",
+                index,
+                self.focus.children.len()
+            )));
+        }
+
+        let mut left = std::mem::take(&mut self.focus.children);
+        let mut right = left.split_off(index + 1);
+        right.reverse();
+        let child = left.pop().unwrap();
+        let mut parent = std::mem::replace(&mut self.focus, child);
+        self.trail.push(Crumb {
+            name: std::mem::take(&mut parent.name),
+            age: parent.age,
+            left,
+            right,
+        });
+        Ok(())
+    }
+
+    /// Moves the focus to its parent.
+    fn up(&mut self) -> PyResult<()> {
+        if self.move_up() {
+            Ok(())
+        } else {
+            Err(PyIndexError::new_err("Cannot move up from the root"))
+        }
+    }
+
+    /// Moves the focus to its previous sibling.
+    fn left(&mut self) -> PyResult<()> {
+        let crumb = self
+            .trail
+            .last_mut()
+            .filter(|crumb| !crumb.left.is_empty())
+            .ok_or_else(|| PyIndexError::new_err("The focus has no previous sibling"))?;
+        let sibling = crumb.left.pop().unwrap();
+        crumb
+            .right
+            .push(std::mem::replace(&mut self.focus, sibling));
+        Ok(())
+    }
+
+    /// Moves the focus to its next sibling.
+    fn right(&mut self) -> PyResult<()> {
+        let crumb = self
+            .trail
+            .last_mut()
+            .filter(|crumb| !crumb.right.is_empty())
+            .ok_or_else(|| PyIndexError::new_err("The focus has no next sibling"))?;
+        let sibling = crumb.right.pop().unwrap();
+        crumb.left.push(std::mem::replace(&mut self.focus, sibling));
+        Ok(())
+    }
+
+    /// Replaces the subtree in focus with a copy of `person`.
+    fn set_focus(&mut self, person: Person) {
+        self.focus = person;
+    }
+
+    /// Moves the focus back to the root and returns a copy of the whole tree,
+    /// including every edit made through the zipper.
+    fn root(&mut self) -> Person {
+        while self.move_up() {}
+        self.focus.clone()
+    }
+}